@@ -38,9 +38,15 @@ pub fn push_fixed_bytes(fields: &Vec<AcceptedField>, access: FieldAccessPush) ->
                     None => 0
                 });
             },
-            DataField::Vec(_) => quote! {
+            DataField::Vec(_, Length::U8) => quote! {
                 bytes.push(#field_access.len() as u8);
             },
+            DataField::Vec(_, Length::U16) => {
+                fixed_buffer_size += 1;
+                quote! {
+                    bytes.extend_from_slice(&(#field_access.len() as u16).to_ne_bytes());
+                }
+            }
             DataField::HashMap {..} => quote! {
                 bytes.push(#field_access.len() as u8);
             },
@@ -59,8 +65,8 @@ pub fn push_unknown_bytes(fields: &Vec<AcceptedField>, access: FieldAccessPush)
         let field_access = &access.as_stream(&field.ident);
         let new_tokens = match &field.data {
             DataField::Option(ty) => {
-                let push_fixed_part = push_fixed_part(ty, field_access, &quote! {#field_access.as_ref().unwrap()}, None, &access);
-                let push_unknown_part = push_unknown_part(ty, field_access, &quote! {#field_access.as_ref().unwrap()});
+                let push_fixed_part = push_fixed_part(ty, field_ident, &quote! {#field_access.as_ref().unwrap()}, None, &access);
+                let push_unknown_part = push_unknown_part(ty, field_ident, &quote! {#field_access.as_ref().unwrap()});
                 quote! {
                     if let Some(_) = #field_access {
                         #push_fixed_part
@@ -68,15 +74,14 @@ pub fn push_unknown_bytes(fields: &Vec<AcceptedField>, access: FieldAccessPush)
                     }
                 }
             }
-            DataField::Vec(ty) => {
-                let push_fixed_part = push_fixed_part(ty, field_access, &quote! {#field_access[i]}, None, &access);
-                let push_unknown_part = push_unknown_part(ty, field_access, &quote! {#field_access[i]});
+            DataField::Vec(ty, _) => {
+                // Elements are read back one after another, so each one has to be complete.
+                // Indexing gives the element itself even for enum fields, which are references
+                let push_fixed_part = push_fixed_part(ty, field_ident, &quote! {#field_access[i]}, None, &FieldAccessPush::Struct);
+                let push_unknown_part = push_unknown_part(ty, field_ident, &quote! {#field_access[i]});
                 quote! {
-                    let vec_len = 0..#field_access.len();
-                    for i in vec_len.clone() {
+                    for i in 0..#field_access.len() {
                         #push_fixed_part
-                    }
-                    for i in vec_len {
                         #push_unknown_part
                     }
                 }
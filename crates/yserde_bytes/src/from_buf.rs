@@ -4,6 +4,9 @@ use syn::{Ident, Variant};
 
 use crate::{format_enum_fields::{format_enum_variant, init_enum_fields}, parse_field::parse_fields, AcceptedField, DataField, DataType, FieldAccessPull, Length};
 
+// Malformed packages are an error instead of a panic, they may come from anyone
+const TOO_SHORT: &str = "Buffer too short for the package";
+
 pub fn enum_from_buf(variants: &Vec<&Variant>) -> TokenStream2 {
     let implementation = variants.into_iter().enumerate().fold(quote! {}, |acc, (index, variant)| {
         let (fields, _) = parse_fields(&variant.fields);
@@ -20,7 +23,7 @@ pub fn enum_from_buf(variants: &Vec<&Variant>) -> TokenStream2 {
         }
     });
     quote! {
-        match buf[0] as usize {
+        match *buf.first().ok_or(#TOO_SHORT)? as usize {
             #implementation
             _ => Err("Given variant index was invalid")
         }
@@ -50,7 +53,7 @@ fn read_fixed_bytes(fields: &Vec<AcceptedField>, buf_index: &mut usize, access:
             DataField::Option(_) => {
                 let option_ident = Ident::new(format!("option_for_{}", field_ident.to_string()).as_str(), Span::call_site());
                 quote! {
-                    let #option_ident = match buf[#buf_index-1] {
+                    let #option_ident = match *buf.get(#buf_index-1).ok_or(#TOO_SHORT)? {
                         1 => true,
                         _ => {
                             #field_access = None;
@@ -59,14 +62,22 @@ fn read_fixed_bytes(fields: &Vec<AcceptedField>, buf_index: &mut usize, access:
                     };
                 }
             }
-            DataField::Vec(_) => {
-                let vec_ident = Ident::new(format!("len_of_{}", field_ident.to_string()).as_str(), Span::call_site()); quote! {
-                    let #vec_ident = buf[#buf_index-1] as usize;
+            DataField::Vec(_, length) => {
+                let vec_ident = Ident::new(format!("len_of_{}", field_ident.to_string()).as_str(), Span::call_site());
+                let vec_len = match length {
+                    Length::U8 => quote! {*buf.get(#buf_index-1).ok_or(#TOO_SHORT)?},
+                    Length::U16 => {
+                        *buf_index += 1;
+                        quote! {u16::from_ne_bytes(buf.get(#buf_index-2..#buf_index).ok_or(#TOO_SHORT)?.try_into().unwrap())}
+                    }
+                };
+                quote! {
+                    let #vec_ident = #vec_len as usize;
                 }
             }
             DataField::HashMap {..} => {
                 let map_ident = Ident::new(format!("len_of_{}", field_ident.to_string()).as_str(), Span::call_site()); quote! {
-                    let #map_ident = buf[#buf_index-1] as usize;
+                    let #map_ident = *buf.get(#buf_index-1).ok_or(#TOO_SHORT)? as usize;
                 }
             }
             DataField::Type(ty) => read_fixed_part(ty, field_ident, field_access, buf_index)
@@ -92,7 +103,7 @@ fn read_unknown_bytes(fields: &Vec<AcceptedField>, access: &FieldAccessPull) ->
                     }
                 }
             }
-            DataField::Vec(ty) => {
+            DataField::Vec(ty, _) => {
                 let vec_ident = Ident::new(format!("len_of_{}", field_ident.to_string()).as_str(), Span::call_site());
                 let type_impl = get_wrapped_ty_impl(ty);
                 quote! {
@@ -129,10 +140,10 @@ fn read_fixed_part(ty: &DataType, field_ident: &TokenStream2, field_access: &Tok
     *buf_index -= 1;
     let result = match ty {
         DataType::U8 => quote! {
-            #field_access = buf[#buf_index];
+            #field_access = *buf.get(#buf_index).ok_or(#TOO_SHORT)?;
         },
         DataType::Bool => quote! {
-            #field_access = match buf[#buf_index] {
+            #field_access = match *buf.get(#buf_index).ok_or(#TOO_SHORT)? {
                 1 => true,
                 _ => false
             };
@@ -140,10 +151,10 @@ fn read_fixed_part(ty: &DataType, field_ident: &TokenStream2, field_access: &Tok
         DataType::String(length) => {
             let string_ident = Ident::new(format!("len_of_{}", field_ident.to_string()).as_str(), Span::call_site());
             let string_len = match length {
-                Length::U8 => quote!{buf[#buf_index]},
+                Length::U8 => quote!{*buf.get(#buf_index).ok_or(#TOO_SHORT)?},
                 Length::U16 => {
                     *buf_index += 1;
-                    quote! {u16::from_ne_bytes(buf[#buf_index-1..#buf_index+1].try_into().unwrap())}
+                    quote! {u16::from_ne_bytes(buf.get(#buf_index-1..#buf_index+1).ok_or(#TOO_SHORT)?.try_into().unwrap())}
                 }
             };
             quote! {
@@ -151,12 +162,12 @@ fn read_fixed_part(ty: &DataType, field_ident: &TokenStream2, field_access: &Tok
             }
         },
         DataType::Int(int_ident, size) => quote! {
-            #field_access = #int_ident::from_ne_bytes(buf[#buf_index..#size+#buf_index].try_into().unwrap());
+            #field_access = #int_ident::from_ne_bytes(buf.get(#buf_index..#size+#buf_index).ok_or(#TOO_SHORT)?.try_into().unwrap());
         },
         DataType::Package(_) => {
             let pkg_ident = Ident::new(format!("pkg_len_{}", field_ident.to_string()).as_str(), Span::call_site());
             quote! {
-                let #pkg_ident = u32::from_ne_bytes(buf[#buf_index..#buf_index+4].try_into().unwrap()) as usize;
+                let #pkg_ident = u32::from_ne_bytes(buf.get(#buf_index..#buf_index+4).ok_or(#TOO_SHORT)?.try_into().unwrap()) as usize;
             }
         }
     };
@@ -173,14 +184,14 @@ fn read_unknown_part(ty: &DataType, field_ident: &TokenStream2, field_access: &T
         DataType::String(_) => {
             let string_ident = Ident::new(format!("len_of_{}", field_ident.to_string()).as_str(), Span::call_site());
             quote! {
-                #field_access = String::from_utf8_lossy(&buf[buf_index..#string_ident+buf_index]).to_string();
+                #field_access = String::from_utf8_lossy(buf.get(buf_index..#string_ident+buf_index).ok_or(#TOO_SHORT)?).to_string();
                 buf_index += #string_ident;
             }
         },
         DataType::Package(ty) => {
             let pkg_ident = Ident::new(format!("pkg_len_{}", field_ident.to_string()).as_str(), Span::call_site());
             quote! {
-                #field_access = #ty::from_buf(&buf[buf_index..#pkg_ident+buf_index]).unwrap();
+                #field_access = #ty::from_buf(buf.get(buf_index..#pkg_ident+buf_index).ok_or(#TOO_SHORT)?)?;
                 buf_index += #pkg_ident;
             }
         }
@@ -191,12 +202,12 @@ fn read_unknown_part(ty: &DataType, field_ident: &TokenStream2, field_access: &T
 fn get_wrapped_ty_impl(ty: &DataType) -> TokenStream2 {
     match ty {
         DataType::U8 => quote! {
-            let x = buf[buf_index];
+            let x = *buf.get(buf_index).ok_or(#TOO_SHORT)?;
             buf_index += 1;
             x
         },
         DataType::Bool => quote! {
-            let x = match buf[buf_index] {
+            let x = match *buf.get(buf_index).ok_or(#TOO_SHORT)? {
                 1 => true,
                 _ => false
             };
@@ -205,29 +216,29 @@ fn get_wrapped_ty_impl(ty: &DataType) -> TokenStream2 {
         },
         DataType::String(length) => {
             let string_len = match length {
-                Length::U8 => quote!{buf[buf_index]},
+                Length::U8 => quote!{*buf.get(buf_index).ok_or(#TOO_SHORT)?},
                 Length::U16 => quote! {{
                     buf_index += 1;
-                    u16::from_ne_bytes(buf[buf_index-1..buf_index+1].try_into().unwrap())
+                    u16::from_ne_bytes(buf.get(buf_index-1..buf_index+1).ok_or(#TOO_SHORT)?.try_into().unwrap())
                 }}
             };
             quote! {
                 let len = #string_len as usize;
                 buf_index += 1;
-                let x = String::from_utf8_lossy(&buf[buf_index..len+buf_index]).to_string();
+                let x = String::from_utf8_lossy(buf.get(buf_index..len+buf_index).ok_or(#TOO_SHORT)?).to_string();
                 buf_index += len;
                 x
             }
         }
         DataType::Int(int_ident, size) => quote! {
-            let x = #int_ident::from_ne_bytes(buf[buf_index..#size+buf_index].try_into().unwrap());
+            let x = #int_ident::from_ne_bytes(buf.get(buf_index..#size+buf_index).ok_or(#TOO_SHORT)?.try_into().unwrap());
             buf_index += #size;
             x
         },
         DataType::Package(ty_ident) => quote! {
-            let len = u32::from_ne_bytes(buf[buf_index..buf_index+4].try_into().unwrap()) as usize;
+            let len = u32::from_ne_bytes(buf.get(buf_index..buf_index+4).ok_or(#TOO_SHORT)?.try_into().unwrap()) as usize;
             buf_index += 4;
-            let x = #ty_ident::from_buf(&buf[buf_index..len+buf_index]).unwrap();
+            let x = #ty_ident::from_buf(buf.get(buf_index..len+buf_index).ok_or(#TOO_SHORT)?)?;
             buf_index += len;
             x
         },
@@ -2,7 +2,7 @@ use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
 use syn::{Ident, Variant};
 
-use crate::{parse_field::parse_fields, AcceptedField, DataField, DataType};
+use crate::{parse_field::parse_fields, AcceptedField, DataField, DataType, Length};
 
 pub fn size_from_variants(variants: &Vec<&Variant>) -> TokenStream2 {
     let implementation = variants.into_iter().fold(quote! {}, |acc, variant| {
@@ -48,11 +48,18 @@ pub fn size_from_fields(fields: &Vec<AcceptedField>) -> TokenStream2 {
 
 fn size_from_field(field: &AcceptedField) -> Result<usize, TokenStream2> {
     match &field.data {
-        DataField::Vec(ty) => match size_of_datatype(ty) {
-            Ok(size) => Ok(1 + 255 * size),
-            Err(ident) => Err(quote! {
-                + 1 + 255 * #ident::MAX_SIZE
-            })
+        DataField::Vec(ty, length) => {
+            let len_size = match length {
+                Length::U8 => 1,
+                Length::U16 => 2
+            };
+            let max_len = length.as_size();
+            match size_of_datatype(ty) {
+                Ok(size) => Ok(len_size + max_len * size),
+                Err(ident) => Err(quote! {
+                    + #len_size + #max_len * #ident::MAX_SIZE
+                })
+            }
         },
         DataField::HashMap { key, value } => {
             let key_size = size_of_datatype(key);
@@ -114,7 +114,7 @@ struct AcceptedField {
 impl AcceptedField {
     fn data_type(&self) -> TokenStream2 {
         match &self.data {
-            DataField::Vec(_) => quote! {Vec},
+            DataField::Vec(..) => quote! {Vec},
             DataField::HashMap {..} => quote! {HashMap},
             DataField::Option(_) => quote! {Option},
             DataField::Type(ty) => match ty {
@@ -131,7 +131,7 @@ impl AcceptedField {
 #[derive(Debug)]
 enum DataField {
     Type(DataType),
-    Vec(DataType),
+    Vec(DataType, Length),
     HashMap {
         key: DataType,
         value: DataType
@@ -82,7 +82,7 @@ fn parse_field_type(field_ident: &TokenStream2, data_ident: Ident, path_args: Pa
                     Some(ty) => AcceptedField {
                         ident: field_ident.clone(),
                         data: match container {
-                            "Vec" => DataField::Vec(ty),
+                            "Vec" => DataField::Vec(ty, *length),
                             "Option" => DataField::Option(ty),
                             _ => unreachable!("huh in parse_field.rs")
                         }
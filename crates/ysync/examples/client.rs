@@ -1,6 +1,10 @@
+use tracing_subscriber::EnvFilter;
 use ysync::client::ConnectionSocket;
 
 fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
     println!("Hello, world!");
     let rt = tokio::runtime::Runtime::new().unwrap();
     rt.block_on(async move {
@@ -0,0 +1,98 @@
+//! Proxy between a client and a lobby that simulates a bad network
+//!
+//! `cargo run --example netsim_proxy -- <listen addr> <lobby addr> [latency ms] [jitter ms] [loss] [duplication] [reorder]`
+//!
+//! Udp datagrams get all conditions applied in both directions, tcp only the latency. Run it on
+//! the client's machine and connect the client to the listen address, the lobby only allows one
+//! connection per ip and sees every client behind the proxy with the proxy's ip.
+use std::{env, net::SocketAddr, time::Duration};
+
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, net::{TcpListener, TcpStream, UdpSocket}, sync::mpsc::unbounded_channel, time::{sleep_until, Instant}};
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+use ysync::{netsim::{Conditioned, NetConditions}, transport::DatagramSocket};
+
+#[tokio::main]
+async fn main() -> tokio::io::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (Some(listen), Some(lobby)) = (args.first(), args.get(1)) else {
+        eprintln!("usage: netsim_proxy <listen addr> <lobby addr> [latency ms] [jitter ms] [loss] [duplication] [reorder]");
+        return Ok(());
+    };
+    let listen: SocketAddr = listen.parse().expect("invalid listen address");
+    let lobby: SocketAddr = lobby.parse().expect("invalid lobby address");
+    let arg = |i: usize| args.get(i).map(|a| a.parse::<f32>().expect("invalid number")).unwrap_or(0.);
+    let conditions = NetConditions {
+        latency: Duration::from_secs_f32(arg(2) / 1000.),
+        jitter: Duration::from_secs_f32(arg(3) / 1000.),
+        loss: arg(4),
+        duplication: arg(5),
+        reorder: arg(6),
+        seed: None,
+    };
+    info!(%listen, %lobby, ?conditions, "proxying");
+
+    let latency = conditions.latency;
+    let client_side = Conditioned::new(UdpSocket::bind(listen).await?, conditions.clone());
+    let lobby_side = Conditioned::new(UdpSocket::bind("0.0.0.0:0").await?, conditions);
+    tokio::spawn(async move {
+        let mut client = None;
+        let (mut from_client, mut from_lobby) = ([0; 2048], [0; 2048]);
+        loop {
+            tokio::select! {
+                Ok((n, addr)) = client_side.recv_from(&mut from_client) => {
+                    client = Some(addr);
+                    let _ = lobby_side.send_to(&from_client[..n], lobby).await;
+                }
+                Ok((n, addr)) = lobby_side.recv_from(&mut from_lobby) => {
+                    if let (true, Some(client)) = (addr == lobby, client) {
+                        let _ = client_side.send_to(&from_lobby[..n], client).await;
+                    }
+                }
+            }
+        }
+    });
+
+    let listener = TcpListener::bind(listen).await?;
+    loop {
+        let (client, addr) = listener.accept().await?;
+        info!(%addr, "client connected");
+        let lobby = match TcpStream::connect(lobby).await {
+            Ok(lobby) => lobby,
+            Err(e) => {
+                warn!("Failed to connect to the lobby, e: {e}");
+                continue;
+            }
+        };
+        let (client_read, client_write) = client.into_split();
+        let (lobby_read, lobby_write) = lobby.into_split();
+        tokio::spawn(delayed_copy(client_read, lobby_write, latency));
+        tokio::spawn(delayed_copy(lobby_read, client_write, latency));
+    }
+}
+
+// Forwards everything read after the latency passed, keeping the order of the stream
+async fn delayed_copy<R, W>(mut read: R, mut write: W, latency: Duration)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (send, mut recv) = unbounded_channel::<(Instant, Vec<u8>)>();
+    tokio::spawn(async move {
+        while let Some((due, chunk)) = recv.recv().await {
+            sleep_until(due).await;
+            if write.write_all(&chunk).await.is_err() {
+                return;
+            }
+        }
+    });
+    let mut buf = [0; 4096];
+    while let Ok(n @ 1..) = read.read(&mut buf).await {
+        if send.send((Instant::now() + latency, buf[..n].to_vec())).is_err() {
+            return;
+        }
+    }
+}
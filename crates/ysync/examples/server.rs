@@ -1,7 +1,22 @@
-use ysync::server::listen;
+use tracing_subscriber::EnvFilter;
+use ysync::server::{commands::{CommandOutput, CommandRegistry}, listen, ServerConfig};
 
 #[tokio::main]
 async fn main() -> tokio::io::Result<()> {
-    listen("0.0.0.0:9983", Some((10010, "abc".to_string()))).await?;
+    // Log levels and targets can be set with RUST_LOG, e.g. `RUST_LOG=ysync=debug`
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+    let mut commands = CommandRegistry::default();
+    commands.register("count", "count", "print the amount of clients and games", |_, view| {
+        let lobby = view.lobby();
+        Ok(CommandOutput::Text(format!("{} clients, {} games", lobby.client_count, lobby.game_count)))
+    });
+    listen("0.0.0.0:9983", ServerConfig {
+        rcon: Some((10010, "abc".to_string())),
+        console: true,
+        commands,
+        ..Default::default()
+    }).await?;
     Ok(())
 }
@@ -1,16 +1,34 @@
-use std::{fmt, time::Duration};
+// A browser only reaches the lobby over WebSocket, without it the client can't connect at all
+#![cfg_attr(all(target_arch = "wasm32", not(feature = "websocket")), allow(unused))]
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::io;
+use std::{fmt, net::SocketAddr, time::Duration};
 
 use crossbeam::channel::Receiver;
-use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpStream, ToSocketAddrs, UdpSocket}, select, sync::{mpsc::UnboundedSender, watch}, time::sleep};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::net::{lookup_host, ToSocketAddrs};
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, select, sync::{mpsc::UnboundedSender, watch}};
+use tracing::{info_span, warn, Instrument};
 use udp_handler::udp_handler;
 
+#[cfg(all(feature = "quic", not(target_arch = "wasm32")))]
+use crate::quic::Quic;
+#[cfg(all(feature = "websocket", not(target_arch = "wasm32")))]
+use crate::websocket::WebSocketClient;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{netsim::{Conditioned, NetConditions}, transport::{Network, Transport}};
 use crate::{
-    GameUpdate, Lobby, LobbyConnectionDenyReason, LobbyConnectionRequest, LobbyConnectionResponse, LobbyUpdate, TcpFromClient, UdpPackage
+    rt::{sleep, spawn, Instant}, transport::DatagramSocket, DisconnectReason, GameUpdate, Lobby, LobbyConnectionDenyReason, LobbyConnectionRequest, LobbyConnectionResponse, LobbyUpdate, TcpFromClient, UdpChannel, UdpPackage, MAX_WORLD_SIZE, WORLD_CHUNK_SIZE
 };
 
 mod tcp_handler;
 mod udp_handler;
-use tcp_handler::tcp_handler;
+mod world_download;
+pub(crate) use tcp_handler::tcp_handler;
+
+// Time the lobby has to accept the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 pub struct ConnectionSocket {
@@ -20,15 +38,64 @@ pub struct ConnectionSocket {
     pub client_id: u16,
     pub tcp_send: UnboundedSender<TcpFromClient>,
     pub tcp_recv: Receiver<TcpUpdate>,
-    pub udp_send: UnboundedSender<UdpPackage>,
+    // Prefer send_udp and send_udp_on
+    pub udp_send: UnboundedSender<(UdpPackage, UdpChannel)>,
     pub udp_recv: Receiver<(u16, UdpPackage)>,
-    pub ping: watch::Receiver<Duration>,
+    pub stats: watch::Receiver<NetStats>,
+}
+
+/// Quality of the udp connection to the lobby, updated every second
+#[derive(Debug, Clone, Default)]
+pub struct NetStats {
+    pub rtt_min: Duration,
+    /// Smoothed round trip time
+    pub rtt_avg: Duration,
+    /// Mean deviation of the round trip time
+    pub jitter: Duration,
+    /// Percentage of recent datagrams that got lost, only datagrams carrying reliable messages
+    /// are accounted for
+    pub packet_loss: f32,
+    /// Messages resent since the connection got established
+    pub resends: u64,
+    pub bytes_in_per_sec: u64,
+    pub bytes_out_per_sec: u64,
+    last_packet: Option<Instant>,
+    /// Set once the lobby stopped acknowledging reliable messages, everything pending got dropped
+    /// then, cleared again with the next datagram from the lobby
+    pub unreachable: bool,
+}
+
+impl NetStats {
+    /// Time since the last datagram from the lobby arrived, None if none did yet
+    pub fn since_last_packet(&self) -> Option<Duration> {
+        self.last_packet.map(|instant| instant.elapsed())
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum TcpUpdate {
     LobbyUpdate(LobbyUpdate),
     GameUpdate(GameUpdate),
+    // Replaces the whole lobby, as some updates got lost
+    LobbySnapshot(Lobby),
+    // The server closed the connection, no more updates will follow
+    Disconnected(DisconnectReason),
+    // The world of the joined game is being downloaded, `received` of `size` bytes arrived
+    WorldProgress {
+        received: u32,
+        size: u32
+    },
+    // The whole world of the joined game
+    World(String),
+    // The world being downloaded got replaced or its game closed
+    WorldUnavailable,
+    // The host didn't share the world of the joined game in time
+    WorldTimeout,
+    // The lobby asks the host for its world, answer with `ConnectionSocket::share_world`
+    WorldRequest {
+        request: u16,
+        client_id: u16
+    },
 }
 
 #[derive(Debug)]
@@ -60,60 +127,169 @@ impl fmt::Display for LobbyConnectionError {
 
 impl From<std::io::Error> for LobbyConnectionError {
     fn from(err: std::io::Error) -> Self {
-        println!("err: {err}");
+        warn!("Network error while connecting to the lobby, e: {err}");
         LobbyConnectionError::NetworkError
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl ConnectionSocket {
     pub async fn build<A: ToSocketAddrs + std::fmt::Display>(lobby_addr: A, local_udp_sock: A, sender_name: String) -> Result<(ConnectionSocket, Lobby), LobbyConnectionError> {
-        let mut tcp: TcpStream;
+        Self::build_conditioned(lobby_addr, local_udp_sock, sender_name, None).await
+    }
+    /// Like [`ConnectionSocket::build`], but simulates a bad network on everything sent over udp
+    /// if `net_conditions` is set
+    pub async fn build_conditioned<A: ToSocketAddrs + std::fmt::Display>(
+        lobby_addr: A,
+        local_udp_sock: A,
+        sender_name: String,
+        net_conditions: Option<NetConditions>,
+    ) -> Result<(ConnectionSocket, Lobby), LobbyConnectionError> {
+        let local_udp_sock = lookup_host(local_udp_sock).await?.next().ok_or(io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+        let mut result = Err(LobbyConnectionError::NetworkError);
+        // Like TcpStream::connect, every address the lobby resolves to is tried
+        for lobby_addr in lookup_host(lobby_addr).await? {
+            result = Self::build_with(&Network, lobby_addr, local_udp_sock, sender_name.clone(), net_conditions.clone()).await;
+            if !matches!(result, Err(LobbyConnectionError::NetworkError)) {
+                break;
+            }
+        }
+        result
+    }
+    /// Connects over WebSocket, the lobby has to accept WebSocket connections on `lobby_addr`
+    ///
+    /// In a browser the lobby is reached through the browser's WebSocket instead, by its url
+    #[cfg(feature = "websocket")]
+    pub async fn build_websocket<A: ToSocketAddrs>(lobby_addr: A, sender_name: String) -> Result<(ConnectionSocket, Lobby), LobbyConnectionError> {
+        let lobby_addr = lookup_host(lobby_addr).await?.next().ok_or(io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+        // The udp traffic is tunneled through the WebSocket, so no local address is needed
+        let unspecified = SocketAddr::from(([0, 0, 0, 0], 0));
+        Self::build_with(&WebSocketClient::default(), lobby_addr, unspecified, sender_name, None).await
+    }
+    /// Connects over QUIC, the lobby has to listen with [`Quic::server`] and prove its identity
+    /// with `lobby_cert`
+    #[cfg(feature = "quic")]
+    pub async fn build_quic<A: ToSocketAddrs>(
+        lobby_addr: A,
+        lobby_cert: quinn::rustls::pki_types::CertificateDer<'static>,
+        sender_name: String,
+    ) -> Result<(ConnectionSocket, Lobby), LobbyConnectionError> {
+        let lobby_addr = lookup_host(lobby_addr).await?.next().ok_or(io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+        // The game traffic is sent as datagrams of the same connection, so no local address is needed
+        let unspecified = SocketAddr::from(([0, 0, 0, 0], 0));
+        Self::build_with(&Quic::client(lobby_cert), lobby_addr, unspecified, sender_name, None).await
+    }
+    /// Connects over any [`Transport`], e.g. a [`crate::transport::MemoryNetwork`] host in tests
+    pub async fn build_with<T: Transport>(
+        transport: &T,
+        lobby_addr: SocketAddr,
+        local_udp_sock: SocketAddr,
+        sender_name: String,
+        net_conditions: Option<NetConditions>,
+    ) -> Result<(ConnectionSocket, Lobby), LobbyConnectionError> {
+        let mut tcp;
         select! {
-            tcp_bind = TcpStream::connect(&lobby_addr) => {tcp = tcp_bind?;},
-            _ = tokio::time::sleep(Duration::from_secs(5)) => return Err(LobbyConnectionError::Timeout),
+            tcp_bind = transport.connect(lobby_addr) => {tcp = tcp_bind?;},
+            _ = sleep(CONNECT_TIMEOUT) => return Err(LobbyConnectionError::Timeout),
         }
-        let udp = UdpSocket::bind(local_udp_sock).await?;
-        udp.connect(lobby_addr).await?;
-
-        tcp.write(&LobbyConnectionRequest(sender_name).as_bytes()).await?;
-        let mut buf = [0; 4];
-        tcp.read(&mut buf).await?;
-        let pkg_len = u32::from_ne_bytes(buf) as usize;
-        let mut pkg_buf = vec![0; pkg_len];
-        tcp.read(&mut pkg_buf).await?;
-        let (client_id, lobby) = match LobbyConnectionResponse::from_buf(&pkg_buf)  {
-            Ok(LobbyConnectionResponse::Accept { client_id, lobby }) => (client_id, lobby),
-            Ok(LobbyConnectionResponse::Deny(reason)) => return Err(LobbyConnectionError::ConnectionDenied(reason)),
-            Err(e) => {
-                println!("Failed to receive LobbyConnectionResponse, e: {e}");
-                return Err(LobbyConnectionError::InvalidResponse)
-            },
+        // The lobby receives udp on the same address as tcp
+        let udp = transport.bind(local_udp_sock).await?;
+        let (client_id, version, lobby) = handshake(&mut tcp, sender_name).await?;
+        let socket = match net_conditions {
+            Some(conditions) => run(tcp, Conditioned::new(udp, conditions), lobby_addr, client_id, version),
+            None => run(tcp, udp, lobby_addr, client_id, version),
         };
-        let (tcp_async_out, tcp_sync_in) = crossbeam::channel::unbounded();
-        let (tcp_sync_out, tcp_async_in) = tokio::sync::mpsc::unbounded_channel();
-        let (udp_async_out, udp_sync_in) = crossbeam::channel::unbounded();
-        let (udp_sync_out, udp_async_in) = tokio::sync::mpsc::unbounded_channel();
-        let (ping_out, ping_in) = tokio::sync::watch::channel(Duration::from_secs(1));
-        tokio::spawn(tcp_handler(tcp, tcp_async_in, tcp_async_out));
-        let heartbeat_send = tcp_sync_out.clone();
-        tokio::spawn(async move {
-            loop {
-                let _ = heartbeat_send.send(TcpFromClient::Heartbeat);
-                sleep(Duration::from_secs(3)).await;
-            }
-        });
-        tokio::spawn(udp_handler(udp, udp_async_in, udp_async_out, ping_out));
-        Ok((
-            ConnectionSocket {
-                game_id: None,
-                client_id,
-                tcp_send: tcp_sync_out,
-                tcp_recv: tcp_sync_in,
-                udp_send: udp_sync_out,
-                udp_recv: udp_sync_in,
-                ping: ping_in
-            },
-            lobby,
-        ))
+        Ok((socket, lobby))
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "websocket"))]
+impl ConnectionSocket {
+    /// Connects through the browser's WebSocket, `url` is the lobby's, e.g. `wss://example.com:7000/`
+    pub async fn build_websocket(url: &str, sender_name: String) -> Result<(ConnectionSocket, Lobby), LobbyConnectionError> {
+        let (mut tcp, udp) = select! {
+            result = crate::websocket::connect(url) => result?,
+            _ = sleep(CONNECT_TIMEOUT) => return Err(LobbyConnectionError::Timeout),
+        };
+        let (client_id, version, lobby) = handshake(&mut tcp, sender_name).await?;
+        Ok((run(tcp, udp, crate::websocket::LOBBY_ADDR, client_id, version), lobby))
+    }
+}
+
+// Returns the client id, the version of the lobby and the lobby itself once it accepted the client
+async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(tcp: &mut S, sender_name: String) -> Result<(u16, u32, Lobby), LobbyConnectionError> {
+    tcp.write_all(&LobbyConnectionRequest(sender_name).as_bytes()).await?;
+    let mut buf = [0; 4];
+    tcp.read_exact(&mut buf).await?;
+    let pkg_len = u32::from_ne_bytes(buf) as usize;
+    let mut pkg_buf = vec![0; pkg_len];
+    tcp.read_exact(&mut pkg_buf).await?;
+    match LobbyConnectionResponse::from_buf(&pkg_buf)  {
+        Ok(LobbyConnectionResponse::Accept { client_id, version, lobby }) => Ok((client_id, version, lobby)),
+        Ok(LobbyConnectionResponse::Deny(reason)) => Err(LobbyConnectionError::ConnectionDenied(reason)),
+        Err(e) => {
+            warn!("Failed to receive LobbyConnectionResponse, e: {e}");
+            Err(LobbyConnectionError::InvalidResponse)
+        },
+    }
+}
+
+// Spawns the handlers of an accepted connection
+fn run<S, D>(tcp: S, udp: D, lobby_addr: SocketAddr, client_id: u16, version: u32) -> ConnectionSocket
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    D: DatagramSocket,
+{
+    let (tcp_async_out, tcp_sync_in) = crossbeam::channel::unbounded();
+    let (tcp_sync_out, tcp_async_in) = tokio::sync::mpsc::unbounded_channel();
+    let (udp_async_out, udp_sync_in) = crossbeam::channel::unbounded();
+    let (udp_sync_out, udp_async_in) = tokio::sync::mpsc::unbounded_channel();
+    let (stats_out, stats_in) = tokio::sync::watch::channel(NetStats::default());
+    let span = info_span!("lobby", client_id);
+    spawn(tcp_handler(tcp, version, tcp_async_in, tcp_async_out).instrument(span.clone()));
+    let heartbeat_send = tcp_sync_out.clone();
+    spawn(async move {
+        loop {
+            let _ = heartbeat_send.send(TcpFromClient::Heartbeat);
+            sleep(Duration::from_secs(3)).await;
+        }
+    });
+    spawn(udp_handler(udp, lobby_addr, udp_async_in, udp_async_out, stats_out).instrument(span));
+    ConnectionSocket {
+        game_id: None,
+        client_id,
+        tcp_send: tcp_sync_out,
+        tcp_recv: tcp_sync_in,
+        udp_send: udp_sync_out,
+        udp_recv: udp_sync_in,
+        stats: stats_in
+    }
+}
+
+impl ConnectionSocket {
+    /// Answer a [`TcpUpdate::WorldRequest`] with the world of the hosted game, the lobby hands it
+    /// only to the player that joined
+    pub fn share_world(&self, request: u16, scene: &str) {
+        if scene.len() > MAX_WORLD_SIZE {
+            warn!(size = scene.len(), "The world is too large to be shared, the lobby accepts up to {MAX_WORLD_SIZE} bytes");
+            return;
+        }
+        // An empty world is still a single chunk, the lobby completes a world with its last chunk
+        let mut chunks = scene.as_bytes().chunks(WORLD_CHUNK_SIZE).collect::<Vec<_>>();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+        let count = chunks.len() as u16;
+        for (index, data) in chunks.into_iter().enumerate() {
+            let _ = self.tcp_send.send(TcpFromClient::WorldChunk { request, index: index as u16, count, data: data.to_vec() });
+        }
+    }
+    /// Send a package on its default [`UdpPackage::channel`]
+    pub fn send_udp(&self, pkg: UdpPackage) {
+        let channel = pkg.channel();
+        self.send_udp_on(channel, pkg);
+    }
+    pub fn send_udp_on(&self, channel: UdpChannel, pkg: UdpPackage) {
+        let _ = self.udp_send.send((pkg, channel));
     }
 }
@@ -1,93 +1,182 @@
 use crossbeam::channel::Sender;
-use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream, select, sync::mpsc::UnboundedReceiver};
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, select, sync::mpsc::UnboundedReceiver};
+use tracing::{debug, info, warn};
 
-use crate::{GameUpdate, LobbyUpdate, TcpFromClient, TcpFromServer};
+use crate::{rt::{sleep_until, Instant}, GameUpdate, LobbyUpdate, TcpFromClient, TcpFromServer};
 
-use super::TcpUpdate;
+use super::{world_download::WorldDownload, TcpUpdate};
 
-pub async fn tcp_handler(mut tcp: TcpStream, mut receiver: UnboundedReceiver<TcpFromClient>, sender: Sender<TcpUpdate>) {
+pub async fn tcp_handler<S: AsyncRead + AsyncWrite + Unpin>(mut tcp: S, mut version: u32, mut receiver: UnboundedReceiver<TcpFromClient>, sender: Sender<TcpUpdate>) {
+    // Set while waiting for a snapshot, updates changing the lobby are dropped until then
+    let mut resyncing = false;
+    let mut download: Option<WorldDownload> = None;
     loop {
         let mut buf = [0; 4];
+        let next_timeout = download.as_ref().and_then(|d| d.next_timeout());
         select! {
             n = tcp.read(&mut buf) => {
+                match n {
+                    Ok(0) | Err(_) => {
+                        warn!("Lost connection to server!");
+                        return;
+                    }
+                    // Only the first read is part of the select, so the rest of the package can't get lost
+                    Ok(n) => if tcp.read_exact(&mut buf[n..]).await.is_err() {
+                        warn!("Lost connection to server!");
+                        return;
+                    }
+                }
                 let pkg_len = u32::from_ne_bytes(buf) as usize;
-                if let Ok(0) = n {
-                    println!("Lost connection to server!");
+                let mut pkg_buf = vec![0; pkg_len];
+                if tcp.read_exact(&mut pkg_buf).await.is_err() {
+                    warn!("Lost connection to server!");
                     return;
                 }
-                let mut pkg_buf = vec![0; pkg_len];
-                let mut bytes_read = 0;
-                loop {
-                    let n = tcp.read(&mut pkg_buf[bytes_read..]).await;
-                    match n {
-                        Ok(len) => bytes_read += len,
-                        Err(e) => {
-                            println!("There was an error {e}");
-                            continue;
-                        }
+                let package = match TcpFromServer::from_buf(&pkg_buf) {
+                    Ok(pkg) => pkg,
+                    Err(e) => {
+                        warn!("Received invalid package, e: {e}\n\tbuf: {buf:?}");
+                        continue;
                     }
-                    if bytes_read >= pkg_len {
-                        if bytes_read > pkg_len {
-                            println!("\nShit...read more bytes ({bytes_read}) than length of pkg ({pkg_len})\n")
-                        }
-                        break;
+                };
+                // Updates which change the lobby have to be applied in order, anything else is always delivered
+                let stateful_version = match &package {
+                    TcpFromServer::LobbyUpdate { version, update } if update.changes_lobby() => Some(*version),
+                    TcpFromServer::GameUpdate { version, update } if update.changes_lobby() => Some(*version),
+                    _ => None
+                };
+                if let Some(pkg_version) = stateful_version {
+                    if resyncing || pkg_version <= version {
+                        continue;
                     }
-                }
-                let package;
-                match TcpFromServer::from_buf(&pkg_buf) {
-                    Ok(pkg) => package = pkg,
-                    Err(e) => {
-                        println!("Received invalid package, e: {e}\n\tbuf: {buf:?}");
+                    if pkg_version != version + 1 {
+                        info!("Missed lobby updates (at version {version}, got {pkg_version}), requesting a snapshot");
+                        resyncing = true;
+                        if tcp.write_all(&TcpFromClient::RequestLobbySnapshot.as_bytes()).await.is_err() {
+                            warn!("Lost connection to server!");
+                            return;
+                        }
                         continue;
                     }
+                    version = pkg_version;
                 }
                 match &package {
-                    TcpFromServer::LobbyUpdate(update) => {
+                    TcpFromServer::LobbyUpdate { update, .. } => {
                         match update {
                             LobbyUpdate::Connection(client) => {
-                                println!("A client connected! {client:#?}");
+                                debug!("A client connected! {client:?}");
                             }
                             LobbyUpdate::Disconnection(client_id) => {
-                                println!("client with id {client_id} disconnected");
+                                debug!("client with id {client_id} disconnected");
                             }
                             LobbyUpdate::ConnectionInterrupt(client_id) => {
-                                println!("connection to Client#{client_id} was interrupted");
+                                debug!("connection to Client#{client_id} was interrupted");
                             }
                             LobbyUpdate::Reconnect(client_id) => {
-                                println!("client with id {client_id} reconnected");
+                                debug!("client with id {client_id} reconnected");
                             }
                             LobbyUpdate::Message {sender, content} => {
-                                println!("client#{sender} has send a message: {content}");
+                                debug!("client#{sender} has send a message: {content}");
+                            }
+                            LobbyUpdate::ServerMessage(content) => {
+                                info!("the server announced: {content}");
                             }
-                            LobbyUpdate::Default => println!("unexpectedly received a LobbyUpdate::Default")
+                            LobbyUpdate::Default => warn!("unexpectedly received a LobbyUpdate::Default")
                         }
                         let _ = sender.send(TcpUpdate::LobbyUpdate(update.clone()));
                     }
-                    TcpFromServer::GameUpdate(update) => {
+                    TcpFromServer::GameUpdate { update, .. } => {
                         match update {
                             GameUpdate::Creation(game) => {
-                                println!("A game got created! {game:#?}");
+                                debug!("A game got created! {game:?}");
                             }
                             GameUpdate::Deletion(game_id) => {
-                                println!("Game#{game_id} got deleted!");
+                                debug!("Game#{game_id} got deleted!");
                             }
                             GameUpdate::Entry { client_id, game_id } => {
-                                println!("Client#{client_id} joined game#{game_id}");
+                                debug!("Client#{client_id} joined game#{game_id}");
                             }
                             GameUpdate::Exit(client_id) => {
-                                println!("Client#{client_id} left the game he was in");
+                                debug!("Client#{client_id} left the game it was in");
                             }
-                            GameUpdate::World(scene) => {
-                                println!("Received a scene! {scene}");
+                            GameUpdate::HostMigration { game_id, host_id } => {
+                                debug!("Client#{host_id} took over game#{game_id}");
                             }
-                            GameUpdate::Default => println!("unexpectedly received a GameUpdate::Default")
+                            GameUpdate::Default => warn!("unexpectedly received a GameUpdate::Default")
                         }
                         let _ = sender.send(TcpUpdate::GameUpdate(update.clone()));
                     }
+                    TcpFromServer::LobbySnapshot { version: snapshot_version, lobby } => {
+                        info!("Received a lobby snapshot at version {snapshot_version}");
+                        version = *snapshot_version;
+                        resyncing = false;
+                        let _ = sender.send(TcpUpdate::LobbySnapshot(lobby.clone()));
+                    }
+                    TcpFromServer::Disconnect(reason) => {
+                        warn!("Disconnected by the server: {reason}");
+                        let _ = sender.send(TcpUpdate::Disconnected(reason.clone()));
+                        return;
+                    }
+                    TcpFromServer::WorldRequest { request, client_id } => {
+                        debug!(request, client_id, "The lobby asks for the world of the hosted game");
+                        let _ = sender.send(TcpUpdate::WorldRequest { request: *request, client_id: *client_id });
+                    }
+                    TcpFromServer::WorldAvailable { world, size, count } => {
+                        debug!(world, size, count, "A world is available, downloading it");
+                        // A newer world replaces the one still being downloaded
+                        download = Some(WorldDownload::new(*world, *size, *count));
+                        let _ = sender.send(TcpUpdate::WorldProgress { received: 0, size: *size });
+                    }
+                    TcpFromServer::WorldChunk { world, index, data } => {
+                        let Some(current) = download.as_mut().filter(|d| d.world == *world) else {
+                            continue;
+                        };
+                        if !current.receive(*index, data.clone()) {
+                            continue;
+                        }
+                        let (received, size) = current.progress();
+                        let _ = sender.send(TcpUpdate::WorldProgress { received, size });
+                        if current.is_complete() {
+                            match download.take().and_then(|d| d.into_scene()) {
+                                Some(scene) => {
+                                    debug!(len = scene.len(), "Received a scene!");
+                                    let _ = sender.send(TcpUpdate::World(scene));
+                                }
+                                None => warn!("Received a world that isn't valid utf-8"),
+                            }
+                        }
+                    }
+                    TcpFromServer::WorldUnavailable(world) => {
+                        if download.as_ref().is_some_and(|d| d.world == *world) {
+                            warn!(world, "The world being downloaded is no longer available");
+                            download = None;
+                            let _ = sender.send(TcpUpdate::WorldUnavailable);
+                        }
+                    }
+                    TcpFromServer::WorldTimeout => {
+                        warn!("The host didn't share its world in time");
+                        let _ = sender.send(TcpUpdate::WorldTimeout);
+                    }
+                }
+                // Keep the window of requested chunks filled
+                for request in download.as_mut().map(|d| d.requests()).unwrap_or_default() {
+                    if tcp.write_all(&request.as_bytes()).await.is_err() {
+                        warn!("Lost connection to server!");
+                        return;
+                    }
                 }
             }
             Some(event) = receiver.recv() => {
-                let _ = tcp.write(&event.as_bytes()).await;
+                let _ = tcp.write_all(&event.as_bytes()).await;
+            }
+            // Request the chunks again that didn't arrive in time
+            _ = sleep_until(next_timeout.unwrap_or_else(Instant::now)), if next_timeout.is_some() => {
+                for request in download.as_mut().map(|d| d.requests()).unwrap_or_default() {
+                    if tcp.write_all(&request.as_bytes()).await.is_err() {
+                        warn!("Lost connection to server!");
+                        return;
+                    }
+                }
             }
         }
     }
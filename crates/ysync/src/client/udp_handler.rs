@@ -1,52 +1,99 @@
-use std::time::Duration;
+use std::{mem::take, net::SocketAddr, time::Duration};
 
 use crossbeam::channel::Sender;
-use tokio::{net::UdpSocket, select, sync::{mpsc::UnboundedReceiver, watch}, time::{sleep_until, Instant}};
+use tokio::{select, sync::{mpsc::UnboundedReceiver, watch}};
+use tracing::warn;
 
-use crate::{safe_udp::{SafeUdpSupervisor, UdpRecvMemory}, Udp, UdpData, UdpPackage};
+use crate::{rt::{interval, sleep_until, Instant, MissedTickBehavior}, transport::DatagramSocket, safe_udp::{ChannelReceiver, ChannelSequences, Resend, SafeUdpSupervisor, SEND_INTERVAL}, UdpChannel, UdpData, UdpDatagram, UdpPackage, MAX_DATAGRAM_SIZE};
+
+use super::NetStats;
 
 const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(1);
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
 
-pub async fn udp_handler(udp: UdpSocket, mut receiver: UnboundedReceiver<UdpPackage>, sender: Sender<(u16, UdpPackage)>, ping: watch::Sender<Duration>) {
+/// Runs until the [`ConnectionSocket`](super::ConnectionSocket) got dropped
+pub async fn udp_handler<S: DatagramSocket>(udp: S, lobby: SocketAddr, mut receiver: UnboundedReceiver<(UdpPackage, UdpChannel)>, sender: Sender<(u16, UdpPackage)>, stats: watch::Sender<NetStats>) {
     let mut supervisor = SafeUdpSupervisor::new();
-    let mut recv_memory = UdpRecvMemory::new();
+    let mut sequences = ChannelSequences::default();
+    let mut channels = ChannelReceiver::new();
+    let mut outgoing = vec![];
     let mut next_heartbeat = Instant::now() + HEARTBEAT_TIMEOUT;
-    let mut buf = [0; Udp::MAX_SIZE + 4];
+    let mut send_tick = interval(SEND_INTERVAL);
+    send_tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut stats_tick = interval(STATS_INTERVAL);
+    let (mut bytes_in, mut bytes_out) = (0, 0);
+    let mut last_packet = None;
+    let mut unreachable = false;
+    let mut buf = [0; MAX_DATAGRAM_SIZE];
     loop {
+        let next_resend = supervisor.next_resend();
         select! {
-            Some(pkg) = receiver.recv() => {
-                let _ = udp.send(&Udp::Data {
-                    id: supervisor.send(UdpData::FromClient(pkg.clone())),
-                    data: UdpData::FromClient(pkg)
-                }.as_bytes()).await;
+            pkg = receiver.recv() => {
+                let Some((pkg, channel)) = pkg else {
+                    return;
+                };
+                outgoing.extend(supervisor.package(UdpData::FromClient(pkg), channel, &mut sequences));
             }
             _ = sleep_until(next_heartbeat) => {
-                let _ = udp.send(&Udp::Data {
-                    id: supervisor.send(UdpData::FromClient(UdpPackage::Heartbeat)),
-                    data: UdpData::FromClient(UdpPackage::Heartbeat)
-                }.as_bytes()).await;
+                let heartbeat = UdpPackage::Heartbeat;
+                let channel = heartbeat.channel();
+                outgoing.extend(supervisor.package(UdpData::FromClient(heartbeat), channel, &mut sequences));
                 next_heartbeat = Instant::now() + HEARTBEAT_TIMEOUT;
             }
-            Ok(n) = udp.recv(&mut buf) => {
-                match Udp::from_buf(&buf[4..n]) {
-                    Ok(Udp::Data { id,  data }) => {
-                        if let UdpData::FromServer { sender_id, content } = data {
-                            if recv_memory.check_packet(id) {
-                                let _ = sender.send((sender_id, content));
+            _ = send_tick.tick() => {
+                if !outgoing.is_empty() || channels.ack_pending() {
+                    for datagram in supervisor.datagrams(take(&mut outgoing), channels.ack()) {
+                        if let Ok(n) = udp.send_to(&datagram.as_bytes(), lobby).await {
+                            bytes_out += n as u64;
+                        }
+                    }
+                }
+            }
+            Ok((n, from)) = udp.recv_from(&mut buf) => {
+                // Too short to even hold the length prefix
+                if from != lobby || n < 4 {
+                    continue;
+                }
+                bytes_in += n as u64;
+                last_packet = Some(Instant::now());
+                unreachable = false;
+                match UdpDatagram::from_buf(&buf[4..n]) {
+                    Ok(datagram) => {
+                        supervisor.acked(datagram.ack, datagram.ack_bits);
+                        for (_, data) in channels.receive(datagram) {
+                            match data {
+                                UdpData::FromServer { sender_id, content } => {
+                                    let _ = sender.send((sender_id, content));
+                                }
+                                data => warn!("Unexpectedly got a UdpData::FromClient: {data:?}")
                             }
-                            let _ = udp.send(&Udp::Response(id).as_bytes()).await;
                         }
                     }
-                    Ok(Udp::Response(id)) => {
-                        let _ = ping.send(supervisor.ping);
-                        supervisor.received(id)
-                    },
-                    Err(e) => println!("Got an error while receiving Udp, e: {e}")
+                    Err(e) => warn!("Got an error while receiving Udp, e: {e}")
                 }
             }
-            _ = sleep_until(supervisor.next_resend.instant) => {
-                if let Some(pkg) = supervisor.resend(supervisor.next_resend.id) {
-                    let _ = udp.send(&pkg.as_bytes()).await;
+            _ = stats_tick.tick() => {
+                let _ = stats.send(NetStats {
+                    rtt_min: supervisor.rtt_min(),
+                    rtt_avg: supervisor.ping(),
+                    jitter: supervisor.jitter(),
+                    packet_loss: supervisor.packet_loss(),
+                    resends: supervisor.resend_count(),
+                    bytes_in_per_sec: take(&mut bytes_in) * 1000 / STATS_INTERVAL.as_millis() as u64,
+                    bytes_out_per_sec: take(&mut bytes_out) * 1000 / STATS_INTERVAL.as_millis() as u64,
+                    last_packet,
+                    unreachable,
+                });
+            }
+            _ = sleep_until(next_resend.unwrap_or_else(Instant::now)), if next_resend.is_some() => {
+                match supervisor.resends() {
+                    Resend::Messages(messages) => outgoing.extend(messages),
+                    // Messages sent from now on are tried again, in case the lobby comes back
+                    Resend::Unreachable => {
+                        warn!("The lobby stopped acknowledging udp messages, dropped everything pending");
+                        unreachable = true;
+                        stats.send_modify(|stats| stats.unreachable = true);
+                    }
                 }
             }
         }
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use bevy_utils::HashMap;
+
+use crate::{rt::Instant, TcpFromClient};
+
+/// Chunks requested at once, so the lobby's queue for this client doesn't overflow
+const WINDOW: usize = 4;
+/// Chunks that didn't arrive in time are requested again, e.g. when the lobby dropped them
+/// because this client fell behind
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A world being downloaded from the lobby chunk by chunk
+pub struct WorldDownload {
+    pub world: u16,
+    size: u32,
+    chunks: Vec<Option<Vec<u8>>>,
+    received: u32,
+    // When every requested chunk that didn't arrive yet is requested again
+    requested: HashMap<u16, Instant>,
+    next: u16,
+}
+
+impl WorldDownload {
+    pub fn new(world: u16, size: u32, count: u16) -> WorldDownload {
+        WorldDownload {
+            world,
+            size,
+            chunks: vec![None; count as usize],
+            received: 0,
+            requested: HashMap::new(),
+            next: 0,
+        }
+    }
+    /// Requests for the chunks that timed out and new ones to fill the window
+    pub fn requests(&mut self) -> Vec<TcpFromClient> {
+        let now = Instant::now();
+        let mut indices = self.requested.iter()
+            .filter(|(_, due)| **due <= now)
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>();
+        while self.requested.len() < WINDOW && (self.next as usize) < self.chunks.len() {
+            self.requested.insert(self.next, now);
+            indices.push(self.next);
+            self.next += 1;
+        }
+        indices.into_iter().map(|index| {
+            self.requested.insert(index, now + CHUNK_TIMEOUT);
+            TcpFromClient::RequestWorldChunk { world: self.world, index }
+        }).collect()
+    }
+    /// When [`WorldDownload::requests`] has to be called again
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.requested.values().min().copied()
+    }
+    /// Returns false for chunks that weren't requested or already arrived
+    pub fn receive(&mut self, index: u16, data: Vec<u8>) -> bool {
+        if self.requested.remove(&index).is_none() {
+            return false;
+        }
+        self.received += data.len() as u32;
+        self.chunks[index as usize] = Some(data);
+        true
+    }
+    /// Bytes received so far and the size of the whole world
+    pub fn progress(&self) -> (u32, u32) {
+        (self.received, self.size)
+    }
+    pub fn is_complete(&self) -> bool {
+        self.requested.is_empty() && self.next as usize == self.chunks.len()
+    }
+    /// The whole world, None if it isn't valid utf-8
+    pub fn into_scene(self) -> Option<String> {
+        String::from_utf8(self.chunks.into_iter().flatten().flatten().collect()).ok()
+    }
+}
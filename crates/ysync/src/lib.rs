@@ -3,7 +3,21 @@
 /// functions and trait imlementations for use with the client side
 pub mod client;
 /// functions and trait imlementations for use with the server side
+#[cfg(not(target_arch = "wasm32"))]
 pub mod server;
+/// Simulation of bad network conditions, to reproduce what players on bad connections experience
+#[cfg(not(target_arch = "wasm32"))]
+pub mod netsim;
+/// Sockets the client and server communicate through
+pub mod transport;
+/// Lobby connections over WebSocket, for clients that can't open raw tcp and udp sockets
+#[cfg(feature = "websocket")]
+pub mod websocket;
+/// Lobby connections over QUIC, with the lobby on a stream and game traffic in datagrams
+#[cfg(all(feature = "quic", not(target_arch = "wasm32")))]
+pub mod quic;
+// Clock, timers and tasks, from tokio on native targets and from the browser on wasm32
+mod rt;
 
 mod tcp_types;
 mod udp_types;
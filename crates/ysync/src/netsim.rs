@@ -0,0 +1,85 @@
+use std::{io, net::SocketAddr, sync::{Arc, Mutex}, time::Duration};
+
+use fastrand::Rng;
+use tokio::time::sleep;
+
+use crate::transport::DatagramSocket;
+
+/// Extra delay of datagrams picked for reordering, enough to arrive after the ones sent next
+const REORDER_DELAY: Duration = Duration::from_millis(50);
+
+/// Network conditions to simulate, the default is a perfect network
+#[derive(Debug, Clone, Default)]
+pub struct NetConditions {
+    /// Added to every datagram
+    pub latency: Duration,
+    /// Random extra delay of up to this much per datagram, which reorders datagrams as well
+    pub jitter: Duration,
+    /// Chance from 0 to 1 that a datagram gets dropped
+    pub loss: f32,
+    /// Chance from 0 to 1 that a datagram arrives twice
+    pub duplication: f32,
+    /// Chance from 0 to 1 that a datagram gets held back until the next ones arrived
+    pub reorder: f32,
+    /// Makes the simulated conditions reproducible, a random seed is used if None
+    pub seed: Option<u64>,
+}
+
+impl NetConditions {
+    // The delay of every copy of a datagram that arrives, none if it gets dropped
+    fn delays(&self, rng: &mut Rng) -> Vec<Duration> {
+        if rng.f32() < self.loss {
+            return vec![];
+        }
+        let copies = if rng.f32() < self.duplication { 2 } else { 1 };
+        (0..copies).map(|_| {
+            let mut delay = self.latency + self.jitter.mul_f32(rng.f32());
+            if rng.f32() < self.reorder {
+                delay += REORDER_DELAY;
+            }
+            delay
+        }).collect()
+    }
+}
+
+/// Wraps a socket and applies [`NetConditions`] to every datagram it sends
+///
+/// Conditioning the sockets of both sides affects both directions.
+pub struct Conditioned<S> {
+    inner: Arc<S>,
+    conditions: NetConditions,
+    rng: Mutex<Rng>,
+}
+
+impl<S: DatagramSocket> Conditioned<S> {
+    pub fn new(inner: S, conditions: NetConditions) -> Conditioned<S> {
+        let rng = match conditions.seed {
+            Some(seed) => Rng::with_seed(seed),
+            None => Rng::new(),
+        };
+        Conditioned { inner: Arc::new(inner), conditions, rng: Mutex::new(rng) }
+    }
+}
+
+impl<S: DatagramSocket> DatagramSocket for Conditioned<S> {
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let delays = self.conditions.delays(&mut self.rng.lock().unwrap());
+        for delay in delays {
+            if delay.is_zero() {
+                self.inner.send_to(buf, target).await?;
+                continue;
+            }
+            let inner = self.inner.clone();
+            let datagram = buf.to_vec();
+            tokio::spawn(async move {
+                sleep(delay).await;
+                let _ = inner.send_to(&datagram, target).await;
+            });
+        }
+        // Dropped datagrams look sent as well, just like on a real network
+        Ok(buf.len())
+    }
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.inner.recv_from(buf).await
+    }
+}
@@ -0,0 +1,273 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use quinn::{
+    rustls::{pki_types::{CertificateDer, PrivatePkcs8KeyDer}, RootCertStore},
+    ClientConfig, Connection, ConnectionError, Endpoint, RecvStream, SendStream, ServerConfig, TransportConfig, VarInt,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    runtime::Handle,
+    select,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    time::timeout,
+};
+use tracing::warn;
+
+use crate::transport::{DatagramSocket, Listener, Transport};
+
+// The name the lobby's certificate is issued for, clients pin the certificate instead of looking
+// the lobby up by name
+const SERVER_NAME: &str = "ysync";
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// How long a closed stream waits for the peer to read everything before the connection closes
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+// A full udp datagram doesn't fit into quic's default of 1200 bytes including its own overhead,
+// 1280 bytes are the minimum mtu of ipv6 so every path should allow it
+const INITIAL_MTU: u16 = 1280;
+
+type Datagram = (Vec<u8>, SocketAddr);
+type Routes = Arc<Mutex<HashMap<SocketAddr, Connection>>>;
+
+fn transport_config() -> Arc<TransportConfig> {
+    let mut config = TransportConfig::default();
+    config.initial_mtu(INITIAL_MTU);
+    Arc::new(config)
+}
+
+// Route datagrams to the connection and hand the ones it receives to the inbox, until it closes
+fn track(conn: Connection, routes: &Routes, inbox: UnboundedSender<Datagram>) {
+    let peer = conn.remote_address();
+    routes.lock().unwrap().insert(peer, conn.clone());
+    let routes = routes.clone();
+    tokio::spawn(async move {
+        while let Ok(datagram) = conn.read_datagram().await {
+            let _ = inbox.send((datagram.to_vec(), peer));
+        }
+        routes.lock().unwrap().remove(&peer);
+    });
+}
+
+/// The certificate and key the lobby proves its identity with
+pub struct QuicIdentity {
+    /// Clients can only connect if they know this certificate
+    pub cert: CertificateDer<'static>,
+    key: Vec<u8>,
+}
+
+impl QuicIdentity {
+    /// A new self-signed certificate
+    pub fn generate() -> QuicIdentity {
+        let certified = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])
+            .expect("Failed to generate a certificate");
+        QuicIdentity { cert: certified.cert.der().clone(), key: certified.key_pair.serialize_der() }
+    }
+    /// A DER encoded certificate issued for `ysync` and its DER encoded PKCS#8 key, so the lobby
+    /// can keep its identity across restarts
+    pub fn from_der(cert: Vec<u8>, key: Vec<u8>) -> QuicIdentity {
+        QuicIdentity { cert: CertificateDer::from(cert), key }
+    }
+}
+
+/// Lobby connections over QUIC, the lobby uses a stream and the game traffic datagrams of the
+/// same connection
+///
+/// [`Transport::bind`] only works after [`Transport::listen`] or [`Transport::connect`], it
+/// returns a socket for the datagrams of the connections instead of binding a new one.
+pub struct Quic {
+    identity: Option<QuicIdentity>,
+    lobby_cert: Option<CertificateDer<'static>>,
+    datagrams: Mutex<Option<QuicDatagrams>>,
+}
+
+impl Quic {
+    /// The lobby's side, which can only listen
+    pub fn server(identity: QuicIdentity) -> Quic {
+        Quic { identity: Some(identity), lobby_cert: None, datagrams: Mutex::new(None) }
+    }
+    /// A client's side, which can only connect to the lobby with this certificate
+    pub fn client(lobby_cert: CertificateDer<'static>) -> Quic {
+        Quic { identity: None, lobby_cert: Some(lobby_cert), datagrams: Mutex::new(None) }
+    }
+    fn datagrams(&self, routes: Routes) -> UnboundedSender<Datagram> {
+        let (inbox_send, inbox) = unbounded_channel();
+        *self.datagrams.lock().unwrap() = Some(QuicDatagrams { routes, inbox: tokio::sync::Mutex::new(inbox) });
+        inbox_send
+    }
+}
+
+impl Transport for Quic {
+    type Stream = QuicStream;
+    type Listener = QuicListener;
+    type Datagram = QuicDatagrams;
+
+    async fn listen(&self, addr: SocketAddr) -> io::Result<QuicListener> {
+        let Some(identity) = &self.identity else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Listening needs the lobby's identity"));
+        };
+        let key = PrivatePkcs8KeyDer::from(identity.key.clone());
+        let mut config = ServerConfig::with_single_cert(vec![identity.cert.clone()], key.into()).map_err(io::Error::other)?;
+        config.transport_config(transport_config());
+        let endpoint = Endpoint::server(config, addr)?;
+        let routes = Routes::default();
+        let inbox = self.datagrams(routes.clone());
+        let (accepted_send, accepted) = unbounded_channel();
+        tokio::spawn(accept_handshakes(endpoint.clone(), routes, inbox, accepted_send));
+        Ok(QuicListener { endpoint, accepted })
+    }
+    async fn connect(&self, addr: SocketAddr) -> io::Result<QuicStream> {
+        let Some(lobby_cert) = &self.lobby_cert else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Connecting needs the lobby's certificate"));
+        };
+        let mut roots = RootCertStore::empty();
+        roots.add(lobby_cert.clone()).map_err(io::Error::other)?;
+        let mut config = ClientConfig::with_root_certificates(Arc::new(roots)).map_err(io::Error::other)?;
+        config.transport_config(transport_config());
+        let local = match addr {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let endpoint = Endpoint::client(local)?;
+        let conn = endpoint.connect_with(config, addr, SERVER_NAME).map_err(io::Error::other)?.await?;
+        let (send, recv) = conn.open_bi().await?;
+        let routes = Routes::default();
+        let inbox = self.datagrams(routes.clone());
+        track(conn.clone(), &routes, inbox);
+        Ok(QuicStream { send, recv, conn, _endpoint: Some(endpoint) })
+    }
+    async fn bind(&self, _addr: SocketAddr) -> io::Result<QuicDatagrams> {
+        self.datagrams.lock().unwrap().take().ok_or(io::ErrorKind::NotConnected.into())
+    }
+}
+
+pub struct QuicListener {
+    endpoint: Endpoint,
+    // Connections that finished their handshake
+    accepted: UnboundedReceiver<(QuicStream, SocketAddr)>,
+}
+
+impl Listener for QuicListener {
+    type Stream = QuicStream;
+
+    async fn accept(&mut self) -> io::Result<(QuicStream, SocketAddr)> {
+        self.accepted.recv().await.ok_or(io::ErrorKind::NotConnected.into())
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+}
+
+// Every handshake runs on its own, so a slow client doesn't hold up the others
+async fn accept_handshakes(
+    endpoint: Endpoint,
+    routes: Routes,
+    inbox: UnboundedSender<Datagram>,
+    accepted: UnboundedSender<(QuicStream, SocketAddr)>,
+) {
+    loop {
+        let incoming = select! {
+            incoming = endpoint.accept() => match incoming {
+                Some(incoming) => incoming,
+                // The endpoint closed, dropping the sender ends the listener too
+                None => return,
+            },
+            // The QuicListener got dropped
+            _ = accepted.closed() => return,
+        };
+        let addr = incoming.remote_address();
+        let (routes, inbox, accepted) = (routes.clone(), inbox.clone(), accepted.clone());
+        tokio::spawn(async move {
+            // The client opens the stream with its connection request
+            let handshake = async {
+                let conn = incoming.await?;
+                let (send, recv) = conn.accept_bi().await?;
+                Ok::<_, ConnectionError>((conn, send, recv))
+            };
+            match timeout(HANDSHAKE_TIMEOUT, handshake).await {
+                Ok(Ok((conn, send, recv))) => {
+                    track(conn.clone(), &routes, inbox);
+                    let _ = accepted.send((QuicStream { send, recv, conn, _endpoint: None }, addr));
+                }
+                Ok(Err(e)) => warn!(%addr, "QUIC handshake failed, e: {e}"),
+                Err(_) => warn!(%addr, "QUIC handshake timed out"),
+            }
+        });
+    }
+}
+
+/// The stream the lobby packages are sent on, closes the whole connection once dropped
+pub struct QuicStream {
+    send: SendStream,
+    recv: RecvStream,
+    conn: Connection,
+    // The client's endpoint has to outlive the connection
+    _endpoint: Option<Endpoint>,
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_flush(cx)
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_shutdown(cx)
+    }
+}
+
+impl Drop for QuicStream {
+    fn drop(&mut self) {
+        let _ = self.send.finish();
+        // Closing right away would drop what the peer didn't receive yet, e.g. the reason of a kick
+        let conn = self.conn.clone();
+        let endpoint = self._endpoint.take();
+        if let Ok(runtime) = Handle::try_current() {
+            runtime.spawn(async move {
+                let _ = timeout(CLOSE_TIMEOUT, conn.closed()).await;
+                conn.close(VarInt::from_u32(0), b"closed");
+                drop(endpoint);
+            });
+        }
+    }
+}
+
+/// The datagrams of all quic connections, in place of a udp socket
+pub struct QuicDatagrams {
+    routes: Routes,
+    inbox: tokio::sync::Mutex<UnboundedReceiver<Datagram>>,
+}
+
+impl DatagramSocket for QuicDatagrams {
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let conn = self.routes.lock().unwrap().get(&target).cloned();
+        if let Some(conn) = conn {
+            // Datagrams the connection can't take get lost, like on udp
+            let _ = conn.send_datagram(Bytes::copy_from_slice(buf));
+        }
+        Ok(buf.len())
+    }
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        // Once the connection closed nothing arrives anymore, just like on a udp socket
+        let Some((datagram, from)) = self.inbox.lock().await.recv().await else {
+            return std::future::pending().await;
+        };
+        let n = datagram.len().min(buf.len());
+        buf[..n].copy_from_slice(&datagram[..n]);
+        Ok((n, from))
+    }
+}
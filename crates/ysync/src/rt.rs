@@ -0,0 +1,94 @@
+#[cfg(not(target_arch = "wasm32"))]
+pub use tokio::time::{interval, sleep, sleep_until, Instant, MissedTickBehavior};
+#[cfg(target_arch = "wasm32")]
+pub use browser::{interval, sleep, sleep_until, MissedTickBehavior};
+#[cfg(target_arch = "wasm32")]
+pub use web_time::Instant;
+
+use std::future::Future;
+
+/// Runs the future in the background, on tokio's runtime or the browser's event loop
+#[cfg(not(target_arch = "wasm32"))]
+pub fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+    tokio::spawn(future);
+}
+
+/// Runs the future in the background, on tokio's runtime or the browser's event loop
+#[cfg(target_arch = "wasm32")]
+pub fn spawn<F: Future<Output = ()> + 'static>(future: F) {
+    wasm_bindgen_futures::spawn_local(future);
+}
+
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use std::{future::Future, pin::Pin, task::{Context, Poll}, time::Duration};
+
+    use js_sys::{Function, Promise};
+    use wasm_bindgen::{prelude::wasm_bindgen, JsValue};
+    use wasm_bindgen_futures::JsFuture;
+    use web_time::Instant;
+
+    #[wasm_bindgen]
+    extern "C" {
+        #[wasm_bindgen(js_name = setTimeout)]
+        fn set_timeout(handler: &Function, millis: i32) -> JsValue;
+        #[wasm_bindgen(js_name = clearTimeout)]
+        fn clear_timeout(id: &JsValue);
+    }
+
+    /// Completes once the browser's timeout fired, clearing it when dropped early
+    pub struct Sleep {
+        id: JsValue,
+        fired: JsFuture,
+    }
+
+    impl Future for Sleep {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            Pin::new(&mut self.fired).poll(cx).map(|_| ())
+        }
+    }
+
+    impl Drop for Sleep {
+        fn drop(&mut self) {
+            clear_timeout(&self.id);
+        }
+    }
+
+    pub fn sleep(duration: Duration) -> Sleep {
+        let millis = duration.as_millis().min(i32::MAX as u128) as i32;
+        let mut id = JsValue::UNDEFINED;
+        let fired = Promise::new(&mut |resolve, _| id = set_timeout(&resolve, millis));
+        Sleep { id, fired: JsFuture::from(fired) }
+    }
+
+    pub fn sleep_until(deadline: Instant) -> Sleep {
+        sleep(deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Only delaying is supported, a browser throttles timers of background tabs anyway
+    pub enum MissedTickBehavior {
+        Delay,
+    }
+
+    pub struct Interval {
+        period: Duration,
+        next: Instant,
+    }
+
+    /// Like tokio's interval, the first tick completes right away
+    pub fn interval(period: Duration) -> Interval {
+        Interval { period, next: Instant::now() }
+    }
+
+    impl Interval {
+        pub async fn tick(&mut self) -> Instant {
+            sleep_until(self.next).await;
+            let now = Instant::now();
+            self.next = now + self.period;
+            now
+        }
+        pub fn set_missed_tick_behavior(&mut self, _behavior: MissedTickBehavior) {}
+    }
+}
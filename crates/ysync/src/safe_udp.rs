@@ -1,117 +1,585 @@
-use std::{collections::{HashSet, VecDeque}, time::Duration, u16};
+use std::{cmp::Reverse, collections::{BinaryHeap, VecDeque}, mem::{discriminant, Discriminant}, time::Duration};
 
 use bevy_utils::HashMap;
-use tokio::time::Instant;
+use tracing::warn;
 
-use crate::{Udp, UdpData};
+use crate::{rt::Instant, UdpChannel, UdpData, UdpDatagram, UdpMessage, UdpPackage, MAX_DATAGRAM_SIZE};
 
+/// Messages queued within this interval are send together in as few datagrams as possible
+pub const SEND_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Retransmission timeout used until the first round trip time got measured
+const INITIAL_RTO: Duration = Duration::from_millis(250);
+// Acks are only send once per tick, so anything below that would resend needlessly
+const MIN_RTO: Duration = Duration::from_millis(50);
+const MAX_RTO: Duration = Duration::from_secs(2);
+/// Resends of a single message before the other side is considered unreachable
+const MAX_RETRIES: u32 = 6;
+/// Newer datagrams that have to be acknowledged before a missing one counts as lost
+const LOSS_THRESHOLD: u16 = 3;
+/// Amount of datagrams the packet loss is measured over
+const LOSS_SAMPLES: usize = 100;
+
+/// Messages larger than this are split into fragments of this size, leaves enough room for the
+/// headers of the datagram and the fragment
+pub const FRAGMENT_SIZE: usize = 1024;
+/// Largest message that is sent at all, bigger ones are dropped by the sender and the receiver
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+/// Unreliable messages reassembled at the same time per link, the oldest one is given up on for a
+/// new one. Reliable ones are kept until they time out, as their fragments got acknowledged already.
+const MAX_REASSEMBLING: usize = 16;
+/// Longer than a reliable message gets resent, whatever is still missing afterwards won't arrive
+const GIVE_UP_TIMEOUT: Duration = Duration::from_secs(MAX_RTO.as_secs() * (MAX_RETRIES as u64 + 1));
+
+/// Keeps track of sent reliable messages and when to resend them
+///
+/// The retransmission timeout (RTO) is derived from the smoothed round trip time and its variance
+/// like TCP does (RFC 6298), every resend of a message doubles its timeout.
 #[derive(Clone)]
 pub struct SafeUdpSupervisor {
     index: u16,
-    ping_stats: VecDeque<Duration>,
-    pub ping: Duration,
-    pub next_resend: NextPkg,
+    fragment_size: usize,
+    datagram_seq: u16,
+    // Ids of the reliable messages carried by every datagram that may still get acknowledged
+    in_flight: HashMap<u16, Vec<u16>>,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rtt_min: Option<Duration>,
+    rto: Duration,
+    resend_count: u64,
+    // Whether the last datagrams carrying reliable messages got acknowledged
+    delivered: VecDeque<bool>,
     packets: HashMap<u16, PacketStat>,
+    // Due time of every message, entries of acknowledged or resent messages are skipped lazily
+    resends: BinaryHeap<Reverse<(Instant, u16)>>,
 }
 
-#[derive(Clone)]
-pub struct NextPkg {
-    pub id: u16,
-    pub instant: Instant
-}
-
-impl Default for NextPkg {
-    fn default() -> Self {
-        NextPkg {
-            id: u16::MAX,
-            instant: Instant::now() + Duration::from_secs(300)
-        }
-    }
+/// Returned by [`SafeUdpSupervisor::resends`]
+#[derive(Debug)]
+pub enum Resend {
+    /// Messages to queue again, they are acknowledged with whatever datagram carries them next
+    Messages(Vec<UdpMessage>),
+    /// A message exhausted its retries, all pending messages got dropped
+    Unreachable,
 }
 
 impl SafeUdpSupervisor {
     pub fn new() -> Self {
         SafeUdpSupervisor {
             index: 0,
-            ping_stats: VecDeque::new(),
-            ping: Duration::from_millis(200),
-            next_resend: NextPkg::default(),
-            packets: HashMap::new()
+            fragment_size: FRAGMENT_SIZE,
+            datagram_seq: 0,
+            in_flight: HashMap::new(),
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rtt_min: None,
+            rto: INITIAL_RTO,
+            resend_count: 0,
+            delivered: VecDeque::new(),
+            packets: HashMap::new(),
+            resends: BinaryHeap::new(),
+        }
+    }
+    /// Splits messages into smaller fragments, so tests don't need huge packages
+    #[cfg(test)]
+    pub fn with_fragment_size(fragment_size: usize) -> Self {
+        SafeUdpSupervisor { fragment_size, ..SafeUdpSupervisor::new() }
+    }
+    /// Smoothed round trip time
+    pub fn ping(&self) -> Duration {
+        self.srtt.unwrap_or(INITIAL_RTO)
+    }
+    /// Mean deviation of the round trip time
+    pub fn jitter(&self) -> Duration {
+        self.rttvar
+    }
+    pub fn rtt_min(&self) -> Duration {
+        self.rtt_min.unwrap_or_default()
+    }
+    /// Messages resent so far
+    pub fn resend_count(&self) -> u64 {
+        self.resend_count
+    }
+    /// Percentage of the recent datagrams carrying reliable messages that never got acknowledged
+    pub fn packet_loss(&self) -> f32 {
+        match self.delivered.is_empty() {
+            true => 0.,
+            false => {
+                let lost = self.delivered.iter().filter(|d| !**d).count();
+                lost as f32 * 100. / self.delivered.len() as f32
+            }
+        }
+    }
+    fn record_delivery(&mut self, delivered: bool) {
+        self.delivered.push_back(delivered);
+        if self.delivered.len() > LOSS_SAMPLES {
+            self.delivered.pop_front();
         }
     }
-    pub fn send(&mut self, pkg: UdpData) -> u16 {
+    /// When [`SafeUdpSupervisor::resends`] has to be called next
+    pub fn next_resend(&self) -> Option<Instant> {
+        self.resends.peek().map(|Reverse((due, _))| *due)
+    }
+    /// Wrap a package for the given channel, reliable packages are kept until they got a response
+    ///
+    /// Packages too large for a single datagram are split into several fragments, which are
+    /// reliable if the package is.
+    pub fn package(&mut self, data: UdpData, channel: UdpChannel, sequences: &mut ChannelSequences) -> Vec<UdpMessage> {
+        let msg = match channel {
+            UdpChannel::Sequenced => {
+                sequences.sequenced = sequences.sequenced.wrapping_add(1);
+                UdpMessage::Sequenced { seq: sequences.sequenced, data }
+            }
+            UdpChannel::ReliableUnordered => UdpMessage::Data { id: self.next_id(), data },
+            UdpChannel::ReliableOrdered => {
+                let seq = sequences.ordered;
+                sequences.ordered = sequences.ordered.wrapping_add(1);
+                UdpMessage::Ordered { id: self.next_id(), seq, data }
+            }
+        };
+        let bytes = msg.as_bytes();
+        if bytes.len() <= self.fragment_size {
+            return vec![match msg {
+                UdpMessage::Data { id, .. } | UdpMessage::Ordered { id, .. } => self.send(id, msg),
+                msg => msg
+            }];
+        }
+        if bytes.len() > MAX_MESSAGE_SIZE {
+            warn!(size = bytes.len(), "Dropped a udp message larger than {MAX_MESSAGE_SIZE} bytes");
+            return vec![];
+        }
+        let group = sequences.fragmented;
+        sequences.fragmented = sequences.fragmented.wrapping_add(1);
+        // The receiver reads the message back without the length in front
+        let chunks = bytes[4..].chunks(self.fragment_size);
+        let count = chunks.len() as u8;
+        chunks.enumerate().map(|(index, payload)| {
+            let fragment = |id| UdpMessage::Fragment { id, group, index: index as u8, count, payload: payload.to_vec() };
+            match channel {
+                UdpChannel::Sequenced => fragment(None),
+                _ => {
+                    let id = self.next_id();
+                    self.send(id, fragment(Some(id)))
+                }
+            }
+        }).collect()
+    }
+    fn next_id(&mut self) -> u16 {
         let id = self.index;
         self.index = self.index.wrapping_add(1);
+        id
+    }
+    fn send(&mut self, id: u16, pkg: UdpMessage) -> UdpMessage {
         let now = Instant::now();
-        let response_time = now + self.ping;
+        let due = now + self.rto;
         self.packets.insert(id, PacketStat {
             time: now,
-            expected_response: response_time,
-            data: pkg
+            due,
+            retries: 0,
+            pkg: pkg.clone()
         });
-        if response_time < self.next_resend.instant {
-            self.next_resend.instant = response_time;
+        self.resends.push(Reverse((due, id)));
+        pkg
+    }
+    /// Pack messages into as few datagrams as possible, a single datagram only carrying the
+    /// acknowledgement if there are no messages
+    pub fn datagrams(&mut self, messages: Vec<UdpMessage>, (ack, ack_bits): (u16, u32)) -> Vec<UdpDatagram> {
+        let mut datagrams = vec![];
+        let mut messages = messages.into_iter().peekable();
+        loop {
+            let mut datagram = UdpDatagram { seq: self.datagram_seq, ack, ack_bits, messages: vec![] };
+            let mut size = datagram.as_bytes().len();
+            let mut reliable = vec![];
+            while let Some(msg_size) = messages.peek().map(|m| m.as_bytes().len()) {
+                let is_full = size + msg_size > MAX_DATAGRAM_SIZE || datagram.messages.len() == u8::MAX as usize;
+                if is_full && !datagram.messages.is_empty() {
+                    break;
+                }
+                let Some(msg) = messages.next() else { break };
+                if let UdpMessage::Data { id, .. } | UdpMessage::Ordered { id, .. } | UdpMessage::Fragment { id: Some(id), .. } = &msg {
+                    reliable.push(*id);
+                }
+                size += msg_size;
+                datagram.messages.push(msg);
+            }
+            let seq = self.datagram_seq;
+            self.datagram_seq = self.datagram_seq.wrapping_add(1);
+            // Acks only reach 32 datagrams back, older ones rely on the resend
+            let lost = self.in_flight.keys().filter(|s| seq.wrapping_sub(**s) > 32).copied().collect::<Vec<_>>();
+            for s in lost {
+                self.in_flight.remove(&s);
+                self.record_delivery(false);
+            }
+            if !reliable.is_empty() {
+                self.in_flight.insert(seq, reliable);
+            }
+            datagrams.push(datagram);
+            if messages.peek().is_none() {
+                return datagrams;
+            }
         }
-        self.next_resend = NextPkg { id, instant: response_time };
-        id
     }
-    pub fn received(&mut self, id: u16) {
-        if let Some(pkg) = self.packets.remove(&id) {
-            self.ping_stats.push_back(pkg.time.elapsed());
-            if self.ping_stats.len() > 10 {
-                self.ping_stats.pop_front();
+    /// Handle the acknowledgements of a received datagram, returns the round trip time of the
+    /// newest acknowledged message
+    pub fn acked(&mut self, ack: u16, ack_bits: u32) -> Option<Duration> {
+        let mut rtt = None;
+        for n in 0..=32 {
+            if n > 0 && ack_bits & (1 << (n - 1)) == 0 {
+                continue;
             }
-            self.ping = (self.ping_stats.iter().sum::<Duration>() / self.ping_stats.len().try_into().unwrap()) * 12 / 10;
-            if id == self.next_resend.id {
-                self.next_resend = self.packets.
-                    iter()
-                    .min_by(|x, y| x.1.expected_response.cmp(&y.1.expected_response))
-                    .map(|(id, pkg)| NextPkg {
-                        id: *id,
-                        instant: pkg.expected_response
-                    })
-                    .unwrap_or(NextPkg::default());
+            let Some(ids) = self.in_flight.remove(&ack.wrapping_sub(n)) else {
+                continue;
+            };
+            self.record_delivery(true);
+            for id in ids {
+                rtt = self.received(id).or(rtt);
             }
         }
+        // Datagrams still missing although several newer ones arrived most likely got lost, their
+        // messages are resent once they are due
+        let lost = self.in_flight.keys()
+            .filter(|s| is_newer(ack, **s) && ack.wrapping_sub(**s) >= LOSS_THRESHOLD)
+            .copied()
+            .collect::<Vec<_>>();
+        for s in lost {
+            self.in_flight.remove(&s);
+            self.record_delivery(false);
+        }
+        rtt
     }
-    pub fn resend(&mut self, id: u16) -> Option<Udp> {
-        self.packets.get_mut(&id).map(|p| {
-            p.expected_response = NextPkg::default().instant;
-            Udp::Data {
-                id,
-                data: p.data.clone()
+    // Returns the round trip time of the message, if it was still waiting for a response and never
+    // got resent (otherwise we can't tell which send got acknowledged)
+    fn received(&mut self, id: u16) -> Option<Duration> {
+        let pkg = self.packets.remove(&id)?;
+        if pkg.retries > 0 {
+            return None;
+        }
+        let rtt = pkg.time.elapsed();
+        self.rtt_min = Some(self.rtt_min.map_or(rtt, |min| min.min(rtt)));
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let deviation = srtt.abs_diff(rtt);
+                self.rttvar = (self.rttvar * 3 + deviation) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
             }
-        })
+        }
+        self.rto = (self.ping() + self.rttvar * 4).clamp(MIN_RTO, MAX_RTO);
+        Some(rtt)
+    }
+    /// Take all messages which are due for a resend
+    pub fn resends(&mut self) -> Resend {
+        let now = Instant::now();
+        let mut messages = vec![];
+        while let Some(Reverse((due, id))) = self.resends.peek().copied() {
+            if due > now {
+                break;
+            }
+            self.resends.pop();
+            let Some(pkg) = self.packets.get_mut(&id).filter(|p| p.due == due) else {
+                continue;
+            };
+            if pkg.retries == MAX_RETRIES {
+                self.packets.clear();
+                self.resends.clear();
+                self.in_flight.clear();
+                return Resend::Unreachable;
+            }
+            pkg.retries += 1;
+            pkg.due = now + (self.rto * 2u32.pow(pkg.retries)).min(MAX_RTO);
+            self.resends.push(Reverse((pkg.due, id)));
+            self.resend_count += 1;
+            messages.push(pkg.pkg.clone());
+        }
+        Resend::Messages(messages)
     }
 }
 
 #[derive(Clone)]
 struct PacketStat {
+    // First send, round trip times are only measured for messages that never got resent
     time: Instant,
-    expected_response: Instant,
-    data: UdpData
+    due: Instant,
+    retries: u32,
+    pkg: UdpMessage
 }
 
+/// Sequence numbers of the sequenced and ordered channel of a single link
+#[derive(Default)]
+pub struct ChannelSequences {
+    sequenced: u16,
+    ordered: u16,
+    fragmented: u16,
+}
+
+/// Amount of ids behind the newest one that are still remembered, has to divide 65536 so the
+/// window lines up when the ids wrap
+pub const RECV_WINDOW: u16 = 1024;
+
+/// Detects duplicate message ids with a sliding window behind the newest id received
+///
+/// Ids are compared wraparound-aware, so this keeps working however many messages a session
+/// sends. Ids older than the window are treated as duplicates, the sender gives up on a message
+/// long before that many newer ones got sent.
 pub struct UdpRecvMemory {
-    unique: HashSet<u16>,
-    order: VecDeque<u16>
+    newest: Option<u16>,
+    // Bit `id % RECV_WINDOW` is set if that id got received, bits leaving the window are cleared
+    // when the window moves
+    received: [u64; RECV_WINDOW as usize / 64],
 }
 
 impl UdpRecvMemory {
     pub fn new() -> Self {
         UdpRecvMemory {
-            unique: HashSet::new(),
-            order: VecDeque::new()
+            newest: None,
+            received: [0; RECV_WINDOW as usize / 64],
         }
     }
+    /// Returns true if the id was not received before
     pub fn check_packet(&mut self, id: u16) -> bool {
-        let is_new = self.unique.insert(id);
-        if is_new {
-            self.order.push_back(id);
-            if self.order.len() > 50 {
-                self.order.pop_front();
+        let Some(newest) = self.newest else {
+            self.newest = Some(id);
+            self.set(id);
+            return true;
+        };
+        if is_newer(id, newest) {
+            let advance = id.wrapping_sub(newest);
+            if advance >= RECV_WINDOW {
+                self.received = [0; RECV_WINDOW as usize / 64];
+            } else {
+                for n in 1..=advance {
+                    self.clear(newest.wrapping_add(n));
+                }
+            }
+            self.newest = Some(id);
+            self.set(id);
+            return true;
+        }
+        if newest.wrapping_sub(id) >= RECV_WINDOW || self.is_set(id) {
+            return false;
+        }
+        self.set(id);
+        true
+    }
+    fn is_set(&self, id: u16) -> bool {
+        let bit = id % RECV_WINDOW;
+        self.received[bit as usize / 64] & (1 << (bit % 64)) != 0
+    }
+    fn set(&mut self, id: u16) {
+        let bit = id % RECV_WINDOW;
+        self.received[bit as usize / 64] |= 1 << (bit % 64);
+    }
+    fn clear(&mut self, id: u16) {
+        let bit = id % RECV_WINDOW;
+        self.received[bit as usize / 64] &= !(1 << (bit % 64));
+    }
+}
+
+/// Amount of ordered packages held back while waiting for a missing one, beyond that the missing
+/// ones fall out of the [`UdpRecvMemory`] window and would be taken for duplicates, so they are
+/// skipped
+const MAX_PENDING_ORDERED: usize = RECV_WINDOW as usize;
+
+// Whether `a` comes after `b`, taking wrapping into account
+fn is_newer(a: u16, b: u16) -> bool {
+    (a.wrapping_sub(b) as i16) > 0
+}
+
+/// Receiving side of a single link, acknowledges datagrams and applies the semantics of every
+/// [`UdpChannel`]
+pub struct ChannelReceiver {
+    // Newest datagram received, None until the first one arrived
+    ack: Option<u16>,
+    ack_bits: u32,
+    // Whether we received reliable messages since the last acknowledgement was send
+    ack_pending: bool,
+    memory: UdpRecvMemory,
+    // Newest sequence number per original sender and package kind
+    sequenced: HashMap<(u16, Discriminant<UdpPackage>), u16>,
+    next_ordered: u16,
+    pending_ordered: HashMap<u16, UdpData>,
+    // Since when the next ordered package is missing while later ones are held back
+    ordered_stalled: Option<Instant>,
+    // Fragments of the messages not complete yet, by group
+    reassembling: HashMap<u16, Reassembly>,
+}
+
+// The fragments of a single message received so far
+struct Reassembly {
+    started: Instant,
+    reliable: bool,
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+}
+
+impl ChannelReceiver {
+    pub fn new() -> Self {
+        ChannelReceiver {
+            ack: None,
+            ack_bits: 0,
+            ack_pending: false,
+            memory: UdpRecvMemory::new(),
+            sequenced: HashMap::new(),
+            next_ordered: 0,
+            pending_ordered: HashMap::new(),
+            ordered_stalled: None,
+            reassembling: HashMap::new(),
+        }
+    }
+    /// Returns the messages to deliver, in this order
+    pub fn receive(&mut self, datagram: UdpDatagram) -> Vec<(UdpChannel, UdpData)> {
+        self.track(datagram.seq);
+        let mut delivered = vec![];
+        for message in datagram.messages {
+            self.handle(message, &mut delivered);
+        }
+        delivered
+    }
+    fn handle(&mut self, message: UdpMessage, delivered: &mut Vec<(UdpChannel, UdpData)>) {
+        match message {
+            UdpMessage::Data { id, data } => {
+                self.ack_pending = true;
+                if self.memory.check_packet(id) {
+                    delivered.push((UdpChannel::ReliableUnordered, data));
+                }
+            }
+            UdpMessage::Ordered { id, seq, data } => {
+                self.ack_pending = true;
+                if self.memory.check_packet(id) {
+                    delivered.extend(self.order(seq, data));
+                }
+            }
+            UdpMessage::Sequenced { seq, data } => {
+                if self.is_current(seq, &data) {
+                    delivered.push((UdpChannel::Sequenced, data));
+                }
             }
+            UdpMessage::Fragment { id, group, index, count, payload } => {
+                if let Some(id) = id {
+                    self.ack_pending = true;
+                    if !self.memory.check_packet(id) {
+                        return;
+                    }
+                }
+                // The complete message goes through its channel like any other
+                if let Some(message) = self.reassemble(group, index, count, payload, id.is_some()) {
+                    self.handle(message, delivered);
+                }
+            }
+        }
+    }
+    /// Whether reliable messages arrived that have not been acknowledged yet
+    pub fn ack_pending(&self) -> bool {
+        self.ack_pending
+    }
+    /// The `ack` and `ack_bits` for the next datagram send over this link
+    pub fn ack(&mut self) -> (u16, u32) {
+        self.ack_pending = false;
+        // Nothing can be acknowledged before the first datagram arrived, the other side only
+        // sends datagram u16::MAX long after that
+        (self.ack.unwrap_or(u16::MAX), self.ack_bits)
+    }
+    fn track(&mut self, seq: u16) {
+        let Some(ack) = self.ack else {
+            self.ack = Some(seq);
+            return;
+        };
+        if is_newer(seq, ack) {
+            let shift = seq.wrapping_sub(ack) as u32;
+            self.ack_bits = self.ack_bits.checked_shl(shift).unwrap_or(0) | 1u32.checked_shl(shift - 1).unwrap_or(0);
+            self.ack = Some(seq);
+        } else if seq != ack {
+            let n = ack.wrapping_sub(seq) as u32;
+            if n <= 32 {
+                self.ack_bits |= 1 << (n - 1);
+            }
+        }
+    }
+    fn is_current(&mut self, seq: u16, data: &UdpData) -> bool {
+        let key = match data {
+            UdpData::FromClient(content) => (0, discriminant(content)),
+            UdpData::FromServer { sender_id, content } => (*sender_id, discriminant(content)),
+        };
+        match self.sequenced.get(&key) {
+            Some(newest) if !is_newer(seq, *newest) => false,
+            _ => {
+                self.sequenced.insert(key, seq);
+                true
+            }
+        }
+    }
+    // Returns the message once the last missing fragment arrived
+    fn reassemble(&mut self, group: u16, index: u8, count: u8, payload: Vec<u8>, reliable: bool) -> Option<UdpMessage> {
+        let now = Instant::now();
+        self.reassembling.retain(|_, r| now - r.started < GIVE_UP_TIMEOUT);
+        if index >= count || count as usize > MAX_MESSAGE_SIZE.div_ceil(FRAGMENT_SIZE) {
+            warn!(group, index, count, "Got an invalid fragment");
+            return None;
+        }
+        let unreliable = self.reassembling.values().filter(|r| !r.reliable).count();
+        if !self.reassembling.contains_key(&group) && unreliable >= MAX_REASSEMBLING {
+            let oldest = self.reassembling.iter()
+                .filter(|(_, r)| !r.reliable)
+                .min_by_key(|(_, r)| r.started)
+                .map(|(group, _)| *group);
+            if let Some(oldest) = oldest {
+                self.reassembling.remove(&oldest);
+            }
+        }
+        let reassembly = self.reassembling.entry(group).or_insert_with(|| Reassembly {
+            started: now,
+            reliable,
+            fragments: vec![None; count as usize],
+            missing: count as usize,
+        });
+        let Some(fragment @ None) = reassembly.fragments.get_mut(index as usize) else {
+            // Either a duplicate or the count doesn't match the other fragments
+            return None;
+        };
+        *fragment = Some(payload);
+        reassembly.missing -= 1;
+        if reassembly.missing > 0 {
+            return None;
+        }
+        let reassembly = self.reassembling.remove(&group)?;
+        let bytes = reassembly.fragments.into_iter().flatten().flatten().collect::<Vec<_>>();
+        match UdpMessage::from_buf(&bytes) {
+            Ok(message) => Some(message),
+            Err(e) => {
+                warn!(group, "Got an invalid fragmented message, e: {e}");
+                None
+            }
+        }
+    }
+    fn order(&mut self, seq: u16, data: UdpData) -> Vec<(UdpChannel, UdpData)> {
+        if is_newer(self.next_ordered, seq) {
+            return vec![];
+        }
+        self.pending_ordered.insert(seq, data);
+        let now = Instant::now();
+        let given_up = self.ordered_stalled.is_some_and(|since| now - since > GIVE_UP_TIMEOUT);
+        if given_up || self.pending_ordered.len() > MAX_PENDING_ORDERED {
+            // Give up on the missing packages and continue with the oldest one we have
+            if let Some(oldest) = self.pending_ordered.keys()
+                .min_by_key(|s| s.wrapping_sub(self.next_ordered))
+            {
+                self.next_ordered = *oldest;
+            }
+        }
+        let mut ready = vec![];
+        while let Some(data) = self.pending_ordered.remove(&self.next_ordered) {
+            ready.push((UdpChannel::ReliableOrdered, data));
+            self.next_ordered = self.next_ordered.wrapping_add(1);
+        }
+        if !ready.is_empty() {
+            self.ordered_stalled = None;
+        }
+        if !self.pending_ordered.is_empty() {
+            self.ordered_stalled.get_or_insert(now);
         }
-        is_new
+        ready
     }
 }
@@ -0,0 +1,194 @@
+use std::{collections::BTreeMap, net::IpAddr, str::FromStr, time::Duration};
+
+use crate::CustomDisplay;
+
+pub use super::manager::LobbyView;
+
+type Handler = Box<dyn Fn(&mut Args, &LobbyView) -> Result<CommandOutput, String> + Send>;
+
+/// What a command wants to happen, commands only get read access to the lobby so everything
+/// changing it is carried out by the manager
+pub enum CommandOutput {
+    /// Respond with this text
+    Text(String),
+    Action(Action),
+}
+
+pub enum Action {
+    Kick(u16),
+    Ban {
+        addr: IpAddr,
+        duration: Option<Duration>,
+    },
+    Unban(IpAddr),
+    Mute(u16),
+    Unmute(u16),
+    Say(String),
+    CloseGame(u16),
+    Motd(Option<String>),
+}
+
+struct RegisteredCommand {
+    usage: String,
+    help: String,
+    handler: Handler,
+}
+
+/// Named console commands, used by both the rcon and the stdin console of the lobby server
+///
+/// [`CommandRegistry::default`] contains the built-in commands, `help` is always available and
+/// generated from the registered commands.
+pub struct CommandRegistry {
+    commands: BTreeMap<String, RegisteredCommand>,
+}
+
+impl CommandRegistry {
+    /// A registry without any commands besides `help`
+    pub fn empty() -> CommandRegistry {
+        CommandRegistry { commands: BTreeMap::new() }
+    }
+    /// Register a command, replacing any command with the same name
+    ///
+    /// `usage` is shown in the help and if the handler returns an error.
+    pub fn register<F>(&mut self, name: &str, usage: &str, help: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&mut Args, &LobbyView) -> Result<CommandOutput, String> + Send + 'static
+    {
+        self.commands.insert(name.to_string(), RegisteredCommand {
+            usage: usage.to_string(),
+            help: help.to_string(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+    /// Parse and run a command line
+    pub fn run(&self, line: &str, view: &LobbyView) -> CommandOutput {
+        let line = line.trim();
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        if name == "help" {
+            return CommandOutput::Text(self.help(args.trim()));
+        }
+        match self.commands.get(name) {
+            Some(command) => match (command.handler)(&mut Args::new(args), view) {
+                Ok(output) => output,
+                Err(e) => CommandOutput::Text(format!("{e}\nUsage: {}", command.usage)),
+            }
+            None => CommandOutput::Text(format!("'{line}' is not a valid command, try 'help' instead"))
+        }
+    }
+    fn help(&self, name: &str) -> String {
+        if let Some(command) = self.commands.get(name) {
+            return format!("{} - {}", command.usage, command.help);
+        }
+        self.commands.values().fold("help [command] - print this help".to_string(), |acc, command| {
+            format!("{acc}\n{} - {}", command.usage, command.help)
+        })
+    }
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        let mut registry = CommandRegistry::empty();
+        registry
+            .register("clients", "clients", "list connected clients", |_, view| {
+                Ok(CommandOutput::Text(view.clients().to_string()))
+            })
+            .register("games", "games", "list hosted games", |_, view| {
+                Ok(CommandOutput::Text(view.games().to_string()))
+            })
+            .register("stats", "stats", "print the server metrics", |_, view| {
+                Ok(CommandOutput::Text(view.metrics().to_string()))
+            })
+            .register("kick", "kick <id>", "disconnect a client", |args, _| {
+                Ok(CommandOutput::Action(Action::Kick(args.required("id")?)))
+            })
+            .register("ban", "ban <ip|name> [duration]", "ban an address, permanently if no duration (e.g. 30m, 2h, 7d) is given", |args, view| {
+                let target: String = args.required("ip or name")?;
+                let addr = match target.parse::<IpAddr>() {
+                    Ok(addr) => addr,
+                    Err(_) => view.find_client(&target)
+                        .and_then(|id| view.client_addr(id))
+                        .ok_or(format!("There is no connected client named '{target}'"))?,
+                };
+                let duration = match args.optional::<String>("duration")? {
+                    Some(duration) => Some(parse_duration(&duration)
+                        .ok_or(format!("'{duration}' is not a valid duration, try e.g. 90s, 30m, 2h or 7d"))?),
+                    None => None
+                };
+                Ok(CommandOutput::Action(Action::Ban { addr, duration }))
+            })
+            .register("unban", "unban <ip>", "lift a ban", |args, _| {
+                Ok(CommandOutput::Action(Action::Unban(args.required("ip")?)))
+            })
+            .register("bans", "bans", "list banned addresses", |_, view| {
+                Ok(CommandOutput::Text(view.bans()))
+            })
+            .register("mute", "mute <id>", "stop delivering a client's chat messages", |args, _| {
+                Ok(CommandOutput::Action(Action::Mute(args.required("id")?)))
+            })
+            .register("unmute", "unmute <id>", "deliver a client's chat messages again", |args, _| {
+                Ok(CommandOutput::Action(Action::Unmute(args.required("id")?)))
+            })
+            .register("say", "say <msg>", "send a message to all clients", |args, _| {
+                match args.rest() {
+                    msg if msg.is_empty() => Err("Missing argument <msg>".to_string()),
+                    msg => Ok(CommandOutput::Action(Action::Say(msg))),
+                }
+            })
+            .register("close-game", "close-game <id>", "delete a hosted game", |args, _| {
+                Ok(CommandOutput::Action(Action::CloseGame(args.required("id")?)))
+            })
+            .register("motd", "motd [text]", "set the message of the day, clears it if no text is given", |args, _| {
+                Ok(CommandOutput::Action(Action::Motd(Some(args.rest()).filter(|m| !m.is_empty()))))
+            });
+        registry
+    }
+}
+
+/// The arguments of a command, consumed from left to right
+pub struct Args {
+    rest: String,
+}
+
+impl Args {
+    fn new(args: &str) -> Args {
+        Args { rest: args.trim().to_string() }
+    }
+    /// The next whitespace separated argument
+    pub fn next_str(&mut self) -> Option<String> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let (arg, rest) = self.rest.split_once(char::is_whitespace).unwrap_or((&self.rest, ""));
+        let arg = arg.to_string();
+        self.rest = rest.trim_start().to_string();
+        Some(arg)
+    }
+    pub fn required<T: FromStr>(&mut self, name: &str) -> Result<T, String> {
+        self.optional(name)?.ok_or(format!("Missing argument <{name}>"))
+    }
+    pub fn optional<T: FromStr>(&mut self, name: &str) -> Result<Option<T>, String> {
+        match self.next_str() {
+            Some(arg) => arg.parse().map(Some).map_err(|_| format!("'{arg}' is not a valid {name}")),
+            None => Ok(None)
+        }
+    }
+    /// Everything not consumed yet, as typed
+    pub fn rest(&mut self) -> String {
+        std::mem::take(&mut self.rest)
+    }
+}
+
+/// Plain numbers are seconds, otherwise a single unit suffix (s, m, h, d) is expected
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let unit_pos = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(unit_pos);
+    let secs = amount.parse::<u64>().ok()?.checked_mul(match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return None
+    })?;
+    Some(Duration::from_secs(secs))
+}
@@ -0,0 +1,37 @@
+use std::io::{stdin, BufRead};
+
+use tokio::sync::{mpsc::UnboundedSender, oneshot};
+
+use super::manager::ManagerNotify;
+
+// Blocking, so it has to run on its own thread
+pub fn console(manager_notify: UnboundedSender<ManagerNotify>) {
+    for line in stdin().lock().lines() {
+        let Ok(value) = line else {
+            return;
+        };
+        if value.trim().is_empty() {
+            continue;
+        }
+        let (response, response_recv) = oneshot::channel();
+        if manager_notify.send(ManagerNotify::Command { response, value }).is_err() {
+            return;
+        }
+        if let Ok(response) = response_recv.blocking_recv() {
+            println!("{response}");
+        }
+    }
+}
+
+/// Types commands into the console of a lobby running in the same process
+#[cfg(test)]
+pub struct TestConsole(pub(super) UnboundedSender<ManagerNotify>);
+
+#[cfg(test)]
+impl TestConsole {
+    pub async fn run(&self, command: &str) -> String {
+        let (response, response_recv) = oneshot::channel();
+        self.0.send(ManagerNotify::Command { response, value: command.to_string() }).expect("The lobby stopped");
+        response_recv.await.expect("The lobby didn't respond")
+    }
+}
@@ -0,0 +1,100 @@
+use std::{fmt, fs, net::IpAddr, path::PathBuf, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use bevy_utils::HashMap;
+use tracing::warn;
+
+// Bans are stored one per line as `<ip> <expiry>`, the expiry being a unix timestamp in seconds or
+// `-` for permanent bans
+#[derive(Debug)]
+pub struct BanList {
+    path: PathBuf,
+    bans: HashMap<IpAddr, Option<u64>>,
+}
+
+impl BanList {
+    pub fn load(path: PathBuf) -> BanList {
+        let mut bans = HashMap::new();
+        if let Ok(content) = fs::read_to_string(&path) {
+            for line in content.lines().filter(|l| !l.trim().is_empty()) {
+                let mut parts = line.split_whitespace();
+                let addr = parts.next().and_then(|a| a.parse::<IpAddr>().ok());
+                let expiry = match parts.next() {
+                    Some("-") => Some(None),
+                    Some(expiry) => expiry.parse::<u64>().ok().map(Some),
+                    None => None
+                };
+                match (addr, expiry) {
+                    (Some(addr), Some(expiry)) => {
+                        bans.insert(addr, expiry);
+                    }
+                    _ => warn!("Ignoring invalid line in ban list {}: {line}", path.display()),
+                }
+            }
+        }
+        BanList { path, bans }
+    }
+    fn save(&self) {
+        let content = self.bans.iter().fold(String::new(), |acc, (addr, expiry)| {
+            match expiry {
+                Some(expiry) => format!("{acc}{addr} {expiry}\n"),
+                None => format!("{acc}{addr} -\n"),
+            }
+        });
+        if let Err(e) = fs::write(&self.path, content) {
+            warn!("Failed to save ban list to {}, e: {e}", self.path.display());
+        }
+    }
+    /// Ban an address, permanently if no duration is given, returns the seconds until the ban
+    /// expires (None for permanent bans)
+    pub fn ban(&mut self, addr: IpAddr, duration: Option<Duration>) -> Result<Option<u64>, String> {
+        let now = now();
+        let expiry = match duration {
+            Some(duration) => Some(now.checked_add(duration.as_secs())
+                .ok_or(format!("A ban of {}s would never expire, leave out the duration to ban permanently", duration.as_secs()))?),
+            None => None
+        };
+        self.bans.insert(addr, expiry);
+        self.save();
+        Ok(expiry.map(|expiry| expiry - now))
+    }
+    /// Returns false if the address was not banned
+    pub fn unban(&mut self, addr: IpAddr) -> bool {
+        let banned = self.bans.remove(&addr).is_some();
+        if banned {
+            self.save();
+        }
+        banned
+    }
+    /// Returns None if the address is not banned, otherwise the seconds until the ban expires
+    /// (None for permanent bans)
+    pub fn remaining(&mut self, addr: IpAddr) -> Option<Option<u64>> {
+        let now = now();
+        match *self.bans.get(&addr)? {
+            None => Some(None),
+            Some(expiry) if expiry <= now => {
+                self.unban(addr);
+                None
+            }
+            Some(expiry) => Some(Some(expiry - now)),
+        }
+    }
+}
+
+impl fmt::Display for BanList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.bans.is_empty() {
+            return write!(f, "No addresses banned");
+        }
+        for (addr, expiry) in self.bans.iter() {
+            match expiry {
+                Some(expiry) => writeln!(f, "{addr} (expires in {}s)", expiry.saturating_sub(now()))?,
+                None => writeln!(f, "{addr} (permanent)")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
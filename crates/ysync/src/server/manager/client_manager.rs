@@ -41,7 +41,7 @@ impl ClientManager {
     }
     pub fn add_client(&mut self, client: &mut Client, addr: IpAddr) -> Option<bool> {
         if let Some(connection) = self.clients.iter_mut().find(|c| c.addr == addr) {
-            if let Some(_) = self.connected_clients.iter().find(|c| **c == connection.client.client_id) {
+            if self.connected_clients.contains(&connection.client.client_id) {
                 match connection.active {
                     true => return None,
                     false => {
@@ -78,14 +78,25 @@ impl ClientManager {
     pub fn get_client(&self, client_id: u16) -> Client {
         self.clients[client_id as usize].as_client()
     }
+    pub fn get_addr(&self, client_id: u16) -> Option<IpAddr> {
+        self.connected_clients.contains(&client_id).then(|| self.clients[client_id as usize].addr)
+    }
+    pub fn find_client(&self, name: &str) -> Option<u16> {
+        self.connected_clients.iter().copied().find(|id| self.clients[*id as usize].client.name == name)
+    }
+    pub fn client_count(&self) -> usize {
+        self.connected_clients.len()
+    }
     pub fn get_clients(&self) -> HashMap<u16, Client> {
         self.connected_clients.iter().map(|id| (*id, self.get_client(*id))).collect()
     }
-    pub fn inactivate_client(&mut self, addr: IpAddr) -> u16 {
-        let client = self.clients.iter_mut().find(|c| c.addr == addr).unwrap();
+    /// None if the client isn't connected or already inactive
+    pub fn inactivate_client(&mut self, addr: IpAddr) -> Option<u16> {
+        let client = self.clients.iter_mut()
+            .find(|c| c.addr == addr && c.active && self.connected_clients.contains(&c.client.client_id))?;
         client.active = false;
         client.client.status = ClientStatus::Idle(0);
         client.last_con = Instant::now();
-        client.client.client_id
+        Some(client.client.client_id)
     }
 }
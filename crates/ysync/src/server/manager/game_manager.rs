@@ -20,10 +20,9 @@ impl GameManager {
         }
     }
     pub fn add_game(&mut self, game: &mut Game) -> bool {
-        if let Some(existing_game) = self.games.iter().find(|g| g.host_id == game.host_id) {
-            if let Some(_) = self.active_games.iter().find(|a| **a == existing_game.game_id) {
-                return false;
-            }
+        // Hosts can change, so closed games may still name the host
+        if self.games.iter().any(|g| g.host_id == game.host_id && self.active_games.contains(&g.game_id)) {
+            return false;
         }
         let mut new_id: bool = false;
         let id = match self.free_ids.pop_front() {
@@ -42,7 +41,7 @@ impl GameManager {
         true
     }
     pub fn remove_game(&mut self, host_id: u16) -> Option<u16> {
-        let game_id = self.games.iter().find(|c| c.host_id == host_id)?.game_id;
+        let game_id = self.games.iter().find(|g| g.host_id == host_id && self.active_games.contains(&g.game_id))?.game_id;
         self.active_games.retain(|a| *a != game_id);
         self.free_ids.push_back(game_id);
         Some(game_id)
@@ -56,14 +55,30 @@ impl GameManager {
             g.game_id
         }).unwrap()
     }
+    /// Hand the game over to the player that is in it the longest, removes the old host from the
+    /// game and returns the new one, None if no other player is left
+    pub fn migrate_host(&mut self, game_id: u16) -> Option<u16> {
+        let game = &mut self.games[game_id as usize];
+        let host_id = *game.clients.iter().find(|c| **c != game.host_id)?;
+        let old_host = game.host_id;
+        game.clients.retain(|c| *c != old_host);
+        game.host_id = host_id;
+        Some(host_id)
+    }
     pub fn get_game_id(&self, client_id: u16) -> Option<u16> {
         self.games.iter()
             .find(|g| g.clients.contains(&client_id) && self.active_games.contains(&g.game_id))
             .map(|g| g.game_id)
     }
+    pub fn game_count(&self) -> usize {
+        self.active_games.len()
+    }
     pub fn get_games(&self) -> HashMap<u16, Game> {
         self.active_games.iter().map(|id| (*id, self.games[*id as usize].clone())).collect()
     }
+    pub fn is_active(&self, game_id: u16) -> bool {
+        self.active_games.contains(&game_id)
+    }
     pub fn game_host(&self, game_id: u16) -> u16 {
         self.games[game_id as usize].host_id
     }
@@ -1,21 +1,28 @@
-use std::{net::IpAddr, time::Duration};
+use std::{net::IpAddr, path::PathBuf, sync::Arc, time::Duration};
 
-use bevy_utils::HashMap;
+use ban_list::BanList;
+use bevy_utils::{HashMap, HashSet};
 use client_manager::ClientManager;
 use game_manager::GameManager;
-use tokio::{sync::{broadcast::Sender, mpsc::{UnboundedReceiver, UnboundedSender}, oneshot}, time::{sleep_until, Instant}};
+use world_store::WorldStore;
+use tokio::{select, sync::{mpsc::{UnboundedReceiver, UnboundedSender}, oneshot}, time::{sleep_until, Instant}};
+use tracing::{debug, info, warn};
 
-use crate::{Client, CustomDisplay, Game};
+use crate::{Client, DisconnectReason, Game, Lobby, LobbyConnectionDenyReason, LobbyConnectionResponse, TcpFromServer};
 
-use super::EventBroadcast;
+use super::{commands::{Action, CommandOutput, CommandRegistry}, metrics::Metrics, outbound::{EventDistributor, Outbound}, EventBroadcast};
 
+mod ban_list;
 mod client_manager;
 mod game_manager;
+mod world_store;
 
 pub enum ManagerNotify {
     Connected {
         addr: IpAddr,
         client: Client,
+        outbound: Outbound,
+        response: oneshot::Sender<LobbyConnectionResponse>,
     },
     Disconnected(IpAddr),
     ConnectionInterrupt(IpAddr),
@@ -35,10 +42,21 @@ pub enum ManagerNotify {
         game_id: u16,
     },
     GameExit(/*client_id:*/u16),
-    GameWorld {
+    WorldChunk {
         client_id: u16,
-        scene: String,
+        request: u16,
+        index: u16,
+        count: u16,
+        data: Vec<u8>,
     },
+    RequestWorldChunk {
+        client_id: u16,
+        world: u16,
+        index: u16,
+    },
+    // A client's outbound queue overflowed and has been drained or the client noticed a gap in the
+    // lobby versions, it needs a fresh snapshot
+    Resync(/*client_id:*/u16),
     Command {
         response: oneshot::Sender<String>,
         value: String
@@ -46,98 +64,339 @@ pub enum ManagerNotify {
 }
 
 pub async fn client_game_manager(
-    client_event: Sender<EventBroadcast>,
-    client_list: Sender<HashMap<u16, Client>>,
-    game_list: Sender<HashMap<u16, Game>>,
+    mut client_event: EventDistributor,
     mut receiver: UnboundedReceiver<ManagerNotify>,
     con_event_sender: UnboundedSender<ConnectionEvent>,
+    ban_file: PathBuf,
+    commands: CommandRegistry,
+    metrics: Arc<Metrics>,
 ) -> tokio::io::Result<()> {
     let mut client_manager = ClientManager::new();
     let mut game_manager = GameManager::new();
+    let mut world_store = WorldStore::new();
+    let mut ban_list = BanList::load(ban_file);
+    let mut muted: HashSet<u16> = HashSet::new();
+    let mut motd: Option<String> = None;
     loop {
-        let manager_notify = receiver.recv().await;
-        match manager_notify.expect("ManagerNotify channel has been closed ... fuck") {
-            ManagerNotify::Connected { addr, mut client } => {
-                println!("{} connected! addr: {addr}", client.name);
+        metrics.set_lobby_size(client_manager.client_count(), game_manager.game_count());
+        // Players that left their game don't need its world anymore
+        world_store.retain_players(|client_id, game_id| game_manager.get_game_id(client_id) == Some(game_id));
+        let deadline = world_store.next_deadline();
+        let manager_notify = select! {
+            notify = receiver.recv() => match notify {
+                Some(notify) => notify,
+                // The listener holds a sender as long as the server is running
+                None => return Ok(()),
+            },
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                for client_id in world_store.timed_out() {
+                    info!(client_id, "the host didn't share its world in time");
+                    client_event.send_to(client_id, TcpFromServer::WorldTimeout);
+                }
+                continue;
+            }
+        };
+        match manager_notify {
+            ManagerNotify::Connected { addr, mut client, outbound, response } => {
+                if let Some(remaining) = ban_list.remaining(addr) {
+                    info!(%addr, name = %client.name, "banned client tried to connect, denying the connection");
+                    let _ = response.send(LobbyConnectionResponse::Deny(LobbyConnectionDenyReason::Banned(remaining)));
+                    continue;
+                }
+
                 if let Some(reconnect) = client_manager.add_client(&mut client, addr) {
+                    let client_id = client.client_id;
+                    info!(%addr, client_id, name = %client.name, reconnect, "client connected");
                     match reconnect {
                         true => {
-                            let _ = client_event.send(EventBroadcast::Reconnected {addr, client});
+                            client_event.send(EventBroadcast::Reconnected(client_id));
                             let _ = con_event_sender.send(ConnectionEvent::Reconnect(addr));
                         }
                         false => {
-                            let _ = client_event.send(EventBroadcast::Connected {addr, client});
+                            client_event.send(EventBroadcast::Connected { client, addr });
                         }
                     }
+                    client_event.add_client(client_id, outbound);
+                    let _ = response.send(LobbyConnectionResponse::Accept {
+                        client_id,
+                        version: client_event.version(),
+                        lobby: lobby(&client_manager, &game_manager)
+                    });
+                    if let (false, Some(motd)) = (reconnect, &motd) {
+                        client_event.notify(client_id, motd.clone());
+                    }
                 } else {
-                    println!("client {addr} is already connected!");
-                    let _ = client_event.send(EventBroadcast::Multiconnect(addr));
+                    info!(%addr, name = %client.name, "client is already connected, denying the connection");
+                    let _ = response.send(LobbyConnectionResponse::Deny(LobbyConnectionDenyReason::AlreadyConnected));
                 }
             }
             ManagerNotify::Disconnected(addr) => {
-                println!("client disconnected! addr: {addr}");
                 let client_id = client_manager.remove_client(addr);
-                game_manager.remove_game(client_id);
-                let _ = client_event.send(EventBroadcast::Disconnected(client_id));
+                info!(%addr, client_id, "client disconnected");
+                client_event.remove_client(client_id);
+                muted.remove(&client_id);
+                metrics.remove_client(client_id);
+                leave_game(client_id, &mut game_manager, &mut world_store, &mut client_event);
+                client_event.send(EventBroadcast::Disconnected(client_id));
             }
             ManagerNotify::ConnectionInterrupt(addr) => {
-                println!("Connection with {addr} has been interrupted!");
-                let client_id = client_manager.inactivate_client(addr);
+                // Both the tcp and the udp handler may notice the lost connection
+                let Some(client_id) = client_manager.inactivate_client(addr) else {
+                    continue;
+                };
+                info!(%addr, client_id, "connection interrupted");
+                client_event.remove_client(client_id);
+                metrics.remove_client(client_id);
                 let _ = con_event_sender.send(ConnectionEvent::Interrupt(addr));
-                if let Some(game_id) = game_manager.get_game_id(client_id) {
-                    match client_id == game_manager.game_host(game_id) {
-                        true => {
-                            game_manager.remove_game(client_id);
-                            let _ = client_event.send(EventBroadcast::GameDeletion(game_id));
-                        }
-                        false => {
-                            game_manager.remove_client_from_game(client_id);
-                            let _ = client_event.send(EventBroadcast::GameExit(client_id));
-                        }
-                    }
-                }
-                let _ = client_event.send(EventBroadcast::ConnectionInterrupt(client_id));
+                leave_game(client_id, &mut game_manager, &mut world_store, &mut client_event);
+                client_event.send(EventBroadcast::ConnectionInterrupt(client_id));
             }
             ManagerNotify::Message { client_id, content } => {
-                println!("{} (#{client_id}): {content}", client_manager.get_client(client_id).name);
-                let _ = client_event.send(EventBroadcast::Message { client_id, content });
+                info!(client_id, name = %client_manager.get_client(client_id).name, "message: {content}");
+                if muted.contains(&client_id) {
+                    client_event.notify(client_id, "You are muted, your message was not delivered".to_string());
+                    continue;
+                }
+                client_event.send(EventBroadcast::Message { client_id, content });
             }
             ManagerNotify::GameCreation {mut game, host_addr} => {
-                println!("{} (#{}) wants to create a game {}", game.host_id, client_manager.get_client(game.host_id).name, game.game_name);
                 if game_manager.add_game(&mut game) {
-                    let _ = client_event.send(EventBroadcast::GameCreation {game, host_addr});
+                    info!(game_id = game.game_id, host_id = game.host_id, name = %game.game_name, "game created");
+                    client_event.send(EventBroadcast::GameCreation {game, host_addr});
                 }
             }
             ManagerNotify::GameDeletion(host_id) => {
-                println!("{} (#{host_id}) wants to delete his game", client_manager.get_client(host_id).name);
-                let game_id = game_manager.remove_game(host_id);
-                let _ = client_event.send(EventBroadcast::GameDeletion(game_id.unwrap()));
+                match game_manager.remove_game(host_id) {
+                    Some(game_id) => {
+                        info!(game_id, host_id, "game deleted");
+                        client_event.send(EventBroadcast::GameDeletion(game_id));
+                    }
+                    None => warn!(host_id, "client tried to delete a game it doesn't host"),
+                }
             }
             ManagerNotify::GameEntry { client_id, client_addr, game_id, password } => {
-                println!("{} (#{client_id}) wants to join the game #{game_id} with password: {password:?}", client_manager.get_client(client_id).name);
+                info!(game_id, client_id, ?password, "client joins game");
                 game_manager.add_client_to_game(client_id, game_id);
-                let _ = client_event.send(EventBroadcast::GameEntry { client_id, client_addr, game_id });
+                client_event.send(EventBroadcast::GameEntry { client_id, client_addr, game_id });
+                // Only the host knows the current state of its world
+                let request = world_store.request(game_id, client_id);
+                client_event.send_to(game_manager.game_host(game_id), TcpFromServer::WorldRequest { request, client_id });
             }
             ManagerNotify::GameExit(client_id) => {
-                println!("{} (#{client_id}) wants to leave his game", client_manager.get_client(client_id).name);
-                game_manager.remove_client_from_game(client_id);
-                let _ = client_event.send(EventBroadcast::GameExit(client_id));
+                info!(client_id, "client leaves its game");
+                leave_game(client_id, &mut game_manager, &mut world_store, &mut client_event);
             }
-            ManagerNotify::GameWorld { client_id, scene } => {
-                println!("{} (#{client_id}) shares his game world", client_manager.get_client(client_id).name);
-                let _ = client_event.send(EventBroadcast::GameWorld { client_id, scene });
+            ManagerNotify::WorldChunk { client_id, request, index, count, data } => {
+                let Some(game_id) = game_manager.get_game_id(client_id).filter(|game_id| game_manager.game_host(*game_id) == client_id) else {
+                    warn!(client_id, "client shared a world without hosting a game");
+                    continue;
+                };
+                let Some((joiner, size, count)) = world_store.upload(game_id, request, index, count, data) else {
+                    continue;
+                };
+                debug!(client_id, game_id, joiner, size, "client shared its game world");
+                client_event.send_to(joiner, TcpFromServer::WorldAvailable { world: request, size, count });
+            }
+            ManagerNotify::RequestWorldChunk { client_id, world, index } => {
+                let chunk = world_store.chunk(client_id, world, index);
+                client_event.send_to(client_id, match chunk {
+                    Some(data) => TcpFromServer::WorldChunk { world, index, data: data.to_vec() },
+                    None => TcpFromServer::WorldUnavailable(world),
+                });
+            }
+            ManagerNotify::Resync(client_id) => {
+                debug!(client_id, "client needs a lobby snapshot");
+                client_event.resync(client_id, lobby(&client_manager, &game_manager));
             }
             ManagerNotify::Command { response, value } => {
-                let _ = response.send(match value.as_str() {
-                    "help" => "help - print this help\nclients - list connected clients\ngames - list hosted games".to_string(),
-                    "clients" => client_manager.get_clients().to_string(),
-                    "games" => game_manager.get_games().to_string(),
-                    _ => format!("'{value}' is not a valid command, try 'help' instead")
+                let view = LobbyView {
+                    client_manager: &client_manager,
+                    game_manager: &game_manager,
+                    ban_list: &ban_list,
+                    muted: &muted,
+                    motd: motd.as_deref(),
+                    metrics: &metrics,
+                };
+                let action = match commands.run(&value, &view) {
+                    CommandOutput::Text(text) => {
+                        let _ = response.send(text);
+                        continue;
+                    }
+                    CommandOutput::Action(action) => action,
+                };
+                let _ = response.send(match action {
+                    Action::Kick(client_id) => {
+                        match kick(client_id, DisconnectReason::Kicked, &mut client_manager, &mut game_manager, &mut world_store, &mut client_event, &con_event_sender) {
+                            true => {
+                                muted.remove(&client_id);
+                                metrics.remove_client(client_id);
+                                format!("Kicked client #{client_id}")
+                            }
+                            false => format!("There is no client with id {client_id}")
+                        }
+                    }
+                    Action::Ban { addr, duration } => match ban_list.ban(addr, duration) {
+                        Ok(remaining) => {
+                            let banned_clients: Vec<u16> = client_manager.get_clients().into_keys()
+                                .filter(|id| client_manager.get_addr(*id) == Some(addr))
+                                .collect();
+                            for client_id in banned_clients {
+                                kick(client_id, DisconnectReason::Banned(remaining), &mut client_manager, &mut game_manager, &mut world_store, &mut client_event, &con_event_sender);
+                                muted.remove(&client_id);
+                                metrics.remove_client(client_id);
+                            }
+                            match remaining {
+                                Some(secs) => format!("Banned {addr} for {secs}s"),
+                                None => format!("Banned {addr} permanently"),
+                            }
+                        }
+                        Err(e) => e,
+                    }
+                    Action::Unban(addr) => match ban_list.unban(addr) {
+                        true => format!("Unbanned {addr}"),
+                        false => format!("{addr} is not banned"),
+                    }
+                    Action::Mute(client_id) => match client_manager.get_addr(client_id) {
+                        Some(_) => {
+                            muted.insert(client_id);
+                            client_event.notify(client_id, "You have been muted".to_string());
+                            format!("Muted client #{client_id}")
+                        }
+                        None => format!("There is no client with id {client_id}")
+                    }
+                    Action::Unmute(client_id) => match muted.remove(&client_id) {
+                        true => {
+                            client_event.notify(client_id, "You are no longer muted".to_string());
+                            format!("Unmuted client #{client_id}")
+                        }
+                        false => format!("Client #{client_id} is not muted")
+                    }
+                    Action::Say(content) => {
+                        client_event.send(EventBroadcast::ServerMessage(content));
+                        "Message sent".to_string()
+                    }
+                    Action::CloseGame(game_id) => match game_manager.is_active(game_id) {
+                        true => {
+                            game_manager.remove_game(game_manager.game_host(game_id));
+                            client_event.send(EventBroadcast::GameDeletion(game_id));
+                            format!("Closed game #{game_id}")
+                        }
+                        false => format!("There is no game with id {game_id}")
+                    }
+                    Action::Motd(text) => {
+                        motd = text;
+                        match &motd {
+                            Some(motd) => format!("Message of the day set to: {motd}"),
+                            None => "Message of the day cleared".to_string(),
+                        }
+                    }
                 });
             }
         }
-        let _ = client_list.send(client_manager.get_clients());
-        let _ = game_list.send(game_manager.get_games());
+    }
+}
+
+// Remove the client from the game it is in, a game it hosts is handed over to another player or
+// deleted if none is left
+fn leave_game(client_id: u16, game_manager: &mut GameManager, world_store: &mut WorldStore, client_event: &mut EventDistributor) {
+    if let Some(game_id) = game_manager.get_game_id(client_id) {
+        match client_id == game_manager.game_host(game_id) {
+            true => match game_manager.migrate_host(game_id) {
+                Some(host_id) => {
+                    info!(game_id, old_host = client_id, host_id, "host left, migrating the game");
+                    client_event.send(EventBroadcast::HostMigration { game_id, host_id });
+                    client_event.send(EventBroadcast::GameExit(client_id));
+                    // The old host won't answer anymore, and the new one can't download its own world
+                    world_store.remove_joiner(host_id);
+                    for (request, joiner) in world_store.restart_requests(game_id) {
+                        client_event.send_to(host_id, TcpFromServer::WorldRequest { request, client_id: joiner });
+                    }
+                }
+                None => {
+                    game_manager.remove_game(client_id);
+                    client_event.send(EventBroadcast::GameDeletion(game_id));
+                }
+            }
+            false => {
+                game_manager.remove_client_from_game(client_id);
+                client_event.send(EventBroadcast::GameExit(client_id));
+            }
+        }
+    }
+}
+
+// Close the connection of a client, returns false if no such client is connected
+fn kick(
+    client_id: u16,
+    reason: DisconnectReason,
+    client_manager: &mut ClientManager,
+    game_manager: &mut GameManager,
+    world_store: &mut WorldStore,
+    client_event: &mut EventDistributor,
+    con_event_sender: &UnboundedSender<ConnectionEvent>,
+) -> bool {
+    let Some(addr) = client_manager.get_addr(client_id) else {
+        return false;
+    };
+    info!(%addr, client_id, "disconnecting client: {reason}");
+    client_event.disconnect(client_id, reason);
+    // Cancel the disconnect timeout in case the connection was interrupted
+    let _ = con_event_sender.send(ConnectionEvent::Reconnect(addr));
+    leave_game(client_id, game_manager, world_store, client_event);
+    client_manager.remove_client(addr);
+    client_event.send(EventBroadcast::Disconnected(client_id));
+    true
+}
+
+/// Read-only access to the lobby for console commands
+pub struct LobbyView<'a> {
+    client_manager: &'a ClientManager,
+    game_manager: &'a GameManager,
+    ban_list: &'a BanList,
+    muted: &'a HashSet<u16>,
+    motd: Option<&'a str>,
+    metrics: &'a Metrics,
+}
+
+impl LobbyView<'_> {
+    pub fn clients(&self) -> HashMap<u16, Client> {
+        self.client_manager.get_clients()
+    }
+    pub fn games(&self) -> HashMap<u16, Game> {
+        self.game_manager.get_games()
+    }
+    pub fn lobby(&self) -> Lobby {
+        lobby(self.client_manager, self.game_manager)
+    }
+    pub fn client_addr(&self, client_id: u16) -> Option<IpAddr> {
+        self.client_manager.get_addr(client_id)
+    }
+    /// Id of the connected client with this name
+    pub fn find_client(&self, name: &str) -> Option<u16> {
+        self.client_manager.find_client(name)
+    }
+    pub fn is_muted(&self, client_id: u16) -> bool {
+        self.muted.contains(&client_id)
+    }
+    pub fn bans(&self) -> String {
+        self.ban_list.to_string()
+    }
+    pub fn motd(&self) -> Option<&str> {
+        self.motd
+    }
+    pub fn metrics(&self) -> &Metrics {
+        self.metrics
+    }
+}
+
+fn lobby(client_manager: &ClientManager, game_manager: &GameManager) -> Lobby {
+    let clients = client_manager.get_clients();
+    let games = game_manager.get_games();
+    Lobby {
+        client_count: clients.len() as u16,
+        game_count: games.len() as u16,
+        clients,
+        games
     }
 }
 
@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use bevy_utils::HashMap;
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::MAX_WORLD_SIZE;
+
+/// How long the host may take to start answering a world request, and between two of its chunks
+pub const WORLD_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// A world the host got asked for, its chunks arrive in order over tcp
+#[derive(Debug)]
+struct Request {
+    game_id: u16,
+    joiner: u16,
+    deadline: Instant,
+    count: u16,
+    size: usize,
+    chunks: Vec<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct StoredWorld {
+    game_id: u16,
+    joiner: u16,
+    chunks: Vec<Vec<u8>>,
+}
+
+/// The worlds the hosts shared for their joining players, kept so each of them can download its
+/// world at its own pace
+#[derive(Debug)]
+pub struct WorldStore {
+    // By request id
+    requests: HashMap<u16, Request>,
+    // Complete worlds by the id of the request they answer
+    worlds: HashMap<u16, StoredWorld>,
+    next_request: u16,
+}
+
+impl WorldStore {
+    pub fn new() -> WorldStore {
+        WorldStore {
+            requests: HashMap::new(),
+            worlds: HashMap::new(),
+            next_request: 0,
+        }
+    }
+    /// Ask for a world of the game on behalf of the joining player, returns the id of the request
+    pub fn request(&mut self, game_id: u16, joiner: u16) -> u16 {
+        // The player only needs the newest world
+        self.remove_joiner(joiner);
+        let request = self.next_request;
+        self.next_request = self.next_request.wrapping_add(1);
+        self.requests.insert(request, Request {
+            game_id,
+            joiner,
+            deadline: Instant::now() + WORLD_REQUEST_TIMEOUT,
+            count: 0,
+            size: 0,
+            chunks: vec![],
+        });
+        request
+    }
+    /// Add a chunk the host of the game uploaded, returns the joining player, the world's size and
+    /// chunk count once it is complete
+    pub fn upload(&mut self, game_id: u16, request: u16, index: u16, count: u16, data: Vec<u8>) -> Option<(u16, u32, u16)> {
+        let pending = self.requests.get_mut(&request)
+            .filter(|pending| pending.game_id == game_id && pending.deadline > Instant::now())?;
+        if count == 0 {
+            warn!(game_id, request, "Got a world without any chunks, dropping it");
+            return None;
+        }
+        if index == 0 {
+            pending.count = count;
+        }
+        if index as usize != pending.chunks.len() || count != pending.count {
+            warn!(game_id, request, index, count, "Got a world chunk out of order, dropping it");
+            return None;
+        }
+        pending.size += data.len();
+        if pending.size > MAX_WORLD_SIZE {
+            warn!(game_id, request, "World is larger than {MAX_WORLD_SIZE} bytes, dropping the upload");
+            // Let it time out right away, so the joining player learns about it
+            pending.deadline = Instant::now();
+            return None;
+        }
+        pending.chunks.push(data);
+        pending.deadline = Instant::now() + WORLD_REQUEST_TIMEOUT;
+        if pending.chunks.len() < pending.count as usize {
+            return None;
+        }
+        let pending = self.requests.remove(&request)?;
+        self.worlds.insert(request, StoredWorld { game_id, joiner: pending.joiner, chunks: pending.chunks });
+        Some((pending.joiner, pending.size as u32, count))
+    }
+    /// Restart the requests of the game, e.g. once its host changed, returns the requests and the
+    /// players waiting for them
+    pub fn restart_requests(&mut self, game_id: u16) -> Vec<(u16, u16)> {
+        self.requests.iter_mut()
+            .filter(|(_, pending)| pending.game_id == game_id)
+            .map(|(request, pending)| {
+                pending.deadline = Instant::now() + WORLD_REQUEST_TIMEOUT;
+                pending.count = 0;
+                pending.size = 0;
+                pending.chunks.clear();
+                (*request, pending.joiner)
+            })
+            .collect()
+    }
+    /// A chunk of the world downloaded by the player, None if the world got replaced in the meantime
+    pub fn chunk(&self, joiner: u16, world: u16, index: u16) -> Option<&[u8]> {
+        self.worlds.get(&world)
+            .filter(|stored| stored.joiner == joiner)
+            .and_then(|stored| stored.chunks.get(index as usize))
+            .map(|chunk| chunk.as_slice())
+    }
+    /// When the next request times out
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.requests.values().map(|pending| pending.deadline).min()
+    }
+    /// Drop the requests the hosts didn't answer in time, returns the players waiting for them
+    pub fn timed_out(&mut self) -> Vec<u16> {
+        let now = Instant::now();
+        let mut joiners = vec![];
+        self.requests.retain(|_, pending| {
+            if pending.deadline > now {
+                return true;
+            }
+            joiners.push(pending.joiner);
+            false
+        });
+        joiners
+    }
+    /// Forget the requests and worlds of the player
+    pub fn remove_joiner(&mut self, joiner: u16) {
+        self.requests.retain(|_, pending| pending.joiner != joiner);
+        self.worlds.retain(|_, stored| stored.joiner != joiner);
+    }
+    /// Forget the requests and worlds of players that aren't in the game anymore
+    pub fn retain_players(&mut self, in_game: impl Fn(u16, u16) -> bool) {
+        self.requests.retain(|_, pending| in_game(pending.joiner, pending.game_id));
+        self.worlds.retain(|_, stored| in_game(stored.joiner, stored.game_id));
+    }
+}
@@ -0,0 +1,131 @@
+use std::{fmt::{self, Write}, net::SocketAddr, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}, time::Duration};
+
+use bevy_utils::HashMap;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+
+/// Counters and gauges of the lobby server, shared by all of its tasks
+#[derive(Debug, Default)]
+pub struct Metrics {
+    connected_clients: AtomicU64,
+    active_games: AtomicU64,
+    tcp_packets_in: AtomicU64,
+    tcp_packets_out: AtomicU64,
+    udp_packets_in: AtomicU64,
+    udp_packets_out: AtomicU64,
+    udp_resends: AtomicU64,
+    decode_errors: AtomicU64,
+    // Smoothed round trip time of every client we got udp responses from
+    pings: Mutex<HashMap<u16, Duration>>,
+}
+
+impl Metrics {
+    pub(super) fn set_lobby_size(&self, clients: usize, games: usize) {
+        self.connected_clients.store(clients as u64, Ordering::Relaxed);
+        self.active_games.store(games as u64, Ordering::Relaxed);
+    }
+    pub(super) fn tcp_in(&self) {
+        self.tcp_packets_in.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(super) fn tcp_out(&self) {
+        self.tcp_packets_out.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(super) fn udp_in(&self) {
+        self.udp_packets_in.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(super) fn udp_out(&self) {
+        self.udp_packets_out.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(super) fn udp_resend(&self) {
+        self.udp_resends.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(super) fn decode_error(&self) {
+        self.decode_errors.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(super) fn record_ping(&self, client_id: u16, rtt: Duration) {
+        let mut pings = self.pings.lock().unwrap();
+        let ping = pings.entry(client_id).or_insert(rtt);
+        *ping = (*ping * 7 + rtt) / 8;
+    }
+    pub(super) fn remove_client(&self, client_id: u16) {
+        self.pings.lock().unwrap().remove(&client_id);
+    }
+    pub fn connected_clients(&self) -> u64 {
+        self.connected_clients.load(Ordering::Relaxed)
+    }
+    pub fn active_games(&self) -> u64 {
+        self.active_games.load(Ordering::Relaxed)
+    }
+    pub fn ping(&self, client_id: u16) -> Option<Duration> {
+        self.pings.lock().unwrap().get(&client_id).copied()
+    }
+    fn counters(&self) -> [(&'static str, &'static str, &'static str, u64); 8] {
+        let load = |value: &AtomicU64| value.load(Ordering::Relaxed);
+        [
+            ("ysync_connected_clients", "gauge", "Clients connected to the lobby", load(&self.connected_clients)),
+            ("ysync_active_games", "gauge", "Games hosted in the lobby", load(&self.active_games)),
+            ("ysync_tcp_packets_received_total", "counter", "Tcp packages received from clients", load(&self.tcp_packets_in)),
+            ("ysync_tcp_packets_sent_total", "counter", "Tcp packages sent to clients", load(&self.tcp_packets_out)),
+            ("ysync_udp_packets_received_total", "counter", "Udp packages received from clients", load(&self.udp_packets_in)),
+            ("ysync_udp_packets_sent_total", "counter", "Udp packages sent to clients", load(&self.udp_packets_out)),
+            ("ysync_udp_resends_total", "counter", "Udp packages resent because no response arrived in time", load(&self.udp_resends)),
+            ("ysync_decode_errors_total", "counter", "Packages that could not be decoded", load(&self.decode_errors)),
+        ]
+    }
+    /// The metrics in the Prometheus text exposition format
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        for (name, kind, help, value) in self.counters() {
+            let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n");
+        }
+        out.push_str("# HELP ysync_client_ping_seconds Smoothed udp round trip time per client\n");
+        out.push_str("# TYPE ysync_client_ping_seconds gauge\n");
+        for (client_id, ping) in self.pings.lock().unwrap().iter() {
+            let _ = writeln!(out, "ysync_client_ping_seconds{{client_id=\"{client_id}\"}} {}", ping.as_secs_f64());
+        }
+        out
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, _, _, value) in self.counters() {
+            writeln!(f, "{}: {value}", name.trim_start_matches("ysync_"))?;
+        }
+        let pings = self.pings.lock().unwrap();
+        match pings.is_empty() {
+            true => write!(f, "ping: no udp clients"),
+            false => {
+                let average = pings.values().sum::<Duration>() / pings.len() as u32;
+                write!(f, "ping: {}ms average", average.as_millis())?;
+                for (client_id, ping) in pings.iter() {
+                    write!(f, "\n  #{client_id}: {}ms", ping.as_millis())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// Minimal http server answering `GET /metrics`, anything else gets a 404
+pub async fn serve_metrics(addr: SocketAddr, metrics: Arc<Metrics>) -> tokio::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(respond(stream, metrics.clone()));
+    }
+}
+
+async fn respond(mut stream: TcpStream, metrics: Arc<Metrics>) -> tokio::io::Result<()> {
+    let mut buf = [0; 1024];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let response = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => {
+            let body = metrics.prometheus();
+            format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -1,32 +1,44 @@
-use std::net::IpAddr;
+use std::{io, net::{IpAddr, SocketAddr}, path::PathBuf, sync::Arc};
+use commands::CommandRegistry;
+use console::console;
 use manager::{client_game_manager, disconnect_timeout_handler, ManagerNotify};
+use metrics::{serve_metrics, Metrics};
 use tcp_handler::handle_client_tcp;
-use tokio::{net::{TcpListener, ToSocketAddrs}, sync::{broadcast, mpsc::unbounded_channel}};
+use outbound::EventDistributor;
+use tokio::{net::{lookup_host, ToSocketAddrs}, sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender}};
+use tracing::{field, info_span, Instrument};
 use udp_handler::udp_handler;
 
-use crate::{Client, Game};
+#[cfg(feature = "websocket")]
+use crate::websocket::WebSocketListener;
+use crate::{netsim::{Conditioned, NetConditions}, transport::{DatagramSocket, Listener, Network, Transport}, Client, Game};
 
+pub mod commands;
+mod console;
+#[cfg(test)]
+pub use console::TestConsole;
 mod manager;
+pub mod metrics;
+mod outbound;
+#[cfg(test)]
+pub use outbound::outbound_queue;
 mod tcp_handler;
 mod udp_handler;
 
 #[derive(Clone, Debug)]
 enum EventBroadcast {
     Connected {
-        addr: IpAddr,
         client: Client,
+        addr: IpAddr
     },
     Disconnected(u16),
     ConnectionInterrupt(u16),
-    Reconnected {
-        addr: IpAddr,
-        client: Client,
-    },
-    Multiconnect(IpAddr),
+    Reconnected(u16),
     Message {
         client_id: u16,
         content: String
     },
+    ServerMessage(String),
     GameCreation {
         game: Game,
         host_addr: IpAddr
@@ -38,34 +50,98 @@ enum EventBroadcast {
         game_id: u16,
     },
     GameExit(/*client_id*/u16),
-    GameWorld {
-        client_id: u16,
-        scene: String,
+    HostMigration {
+        game_id: u16,
+        host_id: u16,
     },
 }
 
-pub async fn listen<A: ToSocketAddrs>(tcp_addr: A, rcon: Option<(u16, String)>) -> std::io::Result<()> {
+pub struct ServerConfig {
+    /// Port and password of the rcon console
+    pub rcon: Option<(u16, String)>,
+    /// Read commands from stdin
+    pub console: bool,
+    /// Commands available in the rcon and stdin console
+    pub commands: CommandRegistry,
+    /// File the ban list is loaded from and saved to
+    pub ban_file: PathBuf,
+    /// Serve the metrics in the Prometheus text format on `http://<addr>/metrics`
+    pub metrics_addr: Option<SocketAddr>,
+    /// Simulate a bad network on everything the server sends over udp
+    pub net_conditions: Option<NetConditions>,
+    /// Also accept lobby connections over WebSocket on this address, their udp traffic is
+    /// tunneled through the WebSocket
+    #[cfg(feature = "websocket")]
+    pub websocket_addr: Option<SocketAddr>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            rcon: None,
+            console: false,
+            commands: CommandRegistry::default(),
+            ban_file: PathBuf::from("bans.txt"),
+            metrics_addr: None,
+            net_conditions: None,
+            #[cfg(feature = "websocket")]
+            websocket_addr: None,
+        }
+    }
+}
+
+/// Serves the lobby on `addr`, over tcp and udp on the same port
+pub async fn listen<A: ToSocketAddrs>(addr: A, config: ServerConfig) -> io::Result<()> {
+    let addr = lookup_host(addr).await?.next().ok_or(io::ErrorKind::AddrNotAvailable)?;
+    listen_with(Network, addr, config).await
+}
+
+/// Like [`listen`], but over any [`Transport`], e.g. a [`crate::transport::MemoryNetwork`] host
+/// in tests
+pub async fn listen_with<T: Transport>(transport: T, addr: SocketAddr, config: ServerConfig) -> io::Result<()> {
     // Channel to send data to the client manager
     let (client_send, manager_recv) = unbounded_channel();
-    // Channel for client join/leave events
-    let (client_event_channel, _) = broadcast::channel(5);
-    // Channel for client_list broadcast
-    let (client_list_channel, _) = broadcast::channel(1);
-    // Channel for game_list broadcast
-    let (game_list_channel, _) = broadcast::channel(1);
+    serve(transport, addr, config, client_send, manager_recv).await
+}
+
+/// Like [`listen_with`] in the background, the returned console sends commands to the lobby
+#[cfg(test)]
+pub fn spawn_with_console<T: Transport>(transport: T, addr: SocketAddr, config: ServerConfig) -> TestConsole {
+    let (client_send, manager_recv) = unbounded_channel();
+    tokio::spawn(serve(transport, addr, config, client_send.clone(), manager_recv));
+    TestConsole(client_send)
+}
+
+async fn serve<T: Transport>(
+    transport: T,
+    addr: SocketAddr,
+    config: ServerConfig,
+    client_send: UnboundedSender<ManagerNotify>,
+    manager_recv: UnboundedReceiver<ManagerNotify>,
+) -> io::Result<()> {
+    // Channel for game events the udp handler needs to route packets
+    let (udp_event_send, udp_event_recv) = unbounded_channel();
     // Channel for connection events
     let (con_event_send, con_event_recv) = unbounded_channel();
 
-    let listener = TcpListener::bind(tcp_addr).await?;
+    let metrics = Arc::new(Metrics::default());
+
+    let listener = transport.listen(addr).await?;
+    // The port may have been picked by the os
+    let udp = transport.bind(listener.local_addr()?).await?;
     tokio::spawn(client_game_manager(
-        client_event_channel.clone(),
-        client_list_channel.clone(),
-        game_list_channel.clone(),
+        EventDistributor::new(udp_event_send),
         manager_recv,
         con_event_send,
+        config.ban_file,
+        config.commands,
+        metrics.clone(),
     ));
+    if let Some(addr) = config.metrics_addr {
+        tokio::spawn(serve_metrics(addr, metrics.clone()));
+    }
     tokio::spawn(disconnect_timeout_handler(client_send.clone(), con_event_recv));
-    if let Some((port, password)) = rcon {
+    if let Some((port, password)) = config.rcon {
         let (s, mut r) = unbounded_channel();
         tokio::spawn(rcon_server::listen(Some(port), password, s));
         let manager_notify = client_send.clone();
@@ -80,16 +156,42 @@ pub async fn listen<A: ToSocketAddrs>(tcp_addr: A, rcon: Option<(u16, String)>)
             }
         });
     }
-    tokio::spawn(udp_handler(client_event_channel.subscribe()));
+    if config.console {
+        let manager_notify = client_send.clone();
+        std::thread::spawn(move || console(manager_notify));
+    }
+    #[cfg(feature = "websocket")]
+    if let Some(websocket_addr) = config.websocket_addr {
+        let mut websocket = WebSocketListener::bind(websocket_addr).await?;
+        spawn_udp_handler(websocket.datagrams(udp), config.net_conditions, udp_event_recv, client_send.clone(), metrics.clone());
+        tokio::spawn(accept_clients(websocket, client_send.clone(), metrics.clone()));
+        return accept_clients(listener, client_send, metrics).await;
+    }
+    spawn_udp_handler(udp, config.net_conditions, udp_event_recv, client_send.clone(), metrics.clone());
+    accept_clients(listener, client_send, metrics).await
+}
+
+fn spawn_udp_handler<S: DatagramSocket>(
+    udp: S,
+    net_conditions: Option<NetConditions>,
+    udp_event_recv: UnboundedReceiver<EventBroadcast>,
+    manager_send: UnboundedSender<ManagerNotify>,
+    metrics: Arc<Metrics>,
+) {
+    match net_conditions {
+        Some(conditions) => tokio::spawn(udp_handler(Conditioned::new(udp, conditions), udp_event_recv, manager_send, metrics)),
+        None => tokio::spawn(udp_handler(udp, udp_event_recv, manager_send, metrics)),
+    };
+}
+
+async fn accept_clients<L: Listener>(mut listener: L, client_send: UnboundedSender<ManagerNotify>, metrics: Arc<Metrics>) -> io::Result<()> {
     loop {
         let (tcp, addr) = listener.accept().await?;
         tokio::spawn(handle_client_tcp(
             tcp,
             addr,
             client_send.clone(),
-            client_event_channel.subscribe(),
-            client_list_channel.subscribe(),
-            game_list_channel.subscribe(),
-        ));
+            metrics.clone(),
+        ).instrument(info_span!("connection", %addr, client_id = field::Empty)));
     }
 }
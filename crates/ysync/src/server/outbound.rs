@@ -0,0 +1,225 @@
+use std::{collections::VecDeque, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}};
+
+use bevy_utils::HashMap;
+use tokio::{select, sync::{mpsc::{channel, error::TrySendError, Receiver, Sender, UnboundedSender}, oneshot}};
+
+use tracing::warn;
+
+use crate::{DisconnectReason, GameUpdate, Lobby, LobbyUpdate, TcpFromServer};
+
+use super::EventBroadcast;
+
+/// Amount of packages that may be queued for a single client before it is considered lagging
+const OUTBOUND_CAPACITY: usize = 32;
+
+/// Create the queue holding all packages which still have to be send to a client
+pub fn outbound_queue() -> (Outbound, OutboundReceiver) {
+    let (sender, receiver) = channel(OUTBOUND_CAPACITY);
+    let (close_send, close_recv) = oneshot::channel();
+    let lagging = Arc::new(AtomicBool::new(false));
+    let held = Arc::new(Mutex::new(Vec::new()));
+    (
+        Outbound { sender, close: close_send, lagging: lagging.clone(), held: held.clone() },
+        OutboundReceiver { receiver, close: close_recv, lagging, held, pending: VecDeque::new(), resync_requested: false }
+    )
+}
+
+// Manager side of a client's queue
+#[derive(Debug)]
+pub struct Outbound {
+    sender: Sender<TcpFromServer>,
+    // Bypasses the queue, so even a lagging client learns why it got disconnected
+    close: oneshot::Sender<DisconnectReason>,
+    // Set once the queue overflowed, no more updates are queued until the client got a snapshot
+    lagging: Arc<AtomicBool>,
+    // Packages the snapshot doesn't replace, they are sent right after it
+    held: Arc<Mutex<Vec<TcpFromServer>>>,
+}
+
+impl Outbound {
+    /// Queue a package, lobby and game updates get dropped while the client is lagging
+    pub fn push(&self, pkg: TcpFromServer) {
+        if self.lagging.load(Ordering::Acquire) {
+            self.hold(pkg);
+            return;
+        }
+        if let Err(TrySendError::Full(pkg)) = self.sender.try_send(pkg) {
+            warn!("Outbound queue is full, dropping updates until the client got resynced");
+            self.lagging.store(true, Ordering::Release);
+            self.hold(pkg);
+        }
+    }
+    fn hold(&self, pkg: TcpFromServer) {
+        if !matches!(pkg, TcpFromServer::LobbyUpdate {..} | TcpFromServer::GameUpdate {..}) {
+            self.held.lock().unwrap().push(pkg);
+        }
+    }
+    /// Queue the snapshot the lagging client catches up with, `snapshot` has to be a
+    /// [`TcpFromServer::LobbySnapshot`]
+    pub fn resync(&self, snapshot: TcpFromServer) {
+        self.lagging.store(false, Ordering::Release);
+        self.push(snapshot);
+    }
+}
+
+// Handler side of a client's queue
+pub struct OutboundReceiver {
+    receiver: Receiver<TcpFromServer>,
+    close: oneshot::Receiver<DisconnectReason>,
+    lagging: Arc<AtomicBool>,
+    held: Arc<Mutex<Vec<TcpFromServer>>>,
+    // Held packages, returned before anything queued after the snapshot
+    pending: VecDeque<TcpFromServer>,
+    resync_requested: bool,
+}
+
+impl OutboundReceiver {
+    /// Receive the next package, a [`TcpFromServer::Disconnect`] takes precedence over anything
+    /// queued and is the last package returned
+    pub async fn recv(&mut self) -> Option<TcpFromServer> {
+        let pkg = select! {
+            biased;
+            Ok(reason) = &mut self.close => {
+                self.receiver.close();
+                return Some(TcpFromServer::Disconnect(reason));
+            }
+            Some(pkg) = async { self.pending.pop_front() } => return Some(pkg),
+            pkg = self.receiver.recv() => pkg,
+        };
+        if let Some(TcpFromServer::LobbySnapshot {..}) = pkg {
+            self.resync_requested = false;
+            self.pending.extend(self.held.lock().unwrap().drain(..));
+        }
+        pkg
+    }
+    /// Whether the client fell behind and everything queued before that has been written, so the
+    /// manager has to be asked for a snapshot (returns true only once per lag)
+    pub fn needs_resync(&mut self) -> bool {
+        if !self.resync_requested && self.lagging.load(Ordering::Acquire) && self.receiver.is_empty() {
+            self.resync_requested = true;
+            return true;
+        }
+        false
+    }
+}
+
+/// Distributes [`EventBroadcast`]s to the udp handler and the outbound queue of every client
+pub struct EventDistributor {
+    udp_events: UnboundedSender<EventBroadcast>,
+    clients: HashMap<u16, Outbound>,
+    // Incremented with every update that changes the lobby
+    version: u32,
+}
+
+impl EventDistributor {
+    pub fn new(udp_events: UnboundedSender<EventBroadcast>) -> EventDistributor {
+        EventDistributor {
+            udp_events,
+            clients: HashMap::new(),
+            version: 0,
+        }
+    }
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+    pub fn add_client(&mut self, client_id: u16, outbound: Outbound) {
+        self.clients.insert(client_id, outbound);
+    }
+    pub fn remove_client(&mut self, client_id: u16) {
+        self.clients.remove(&client_id);
+    }
+    pub fn resync(&self, client_id: u16, lobby: Lobby) {
+        if let Some(outbound) = self.clients.get(&client_id) {
+            outbound.resync(TcpFromServer::LobbySnapshot { version: self.version, lobby });
+        }
+    }
+    /// Send a server message to a single client
+    pub fn notify(&self, client_id: u16, content: String) {
+        self.send_to(client_id, TcpFromServer::LobbyUpdate {
+            version: self.version,
+            update: LobbyUpdate::ServerMessage(content)
+        });
+    }
+    /// Send a package meant for a single client only
+    pub fn send_to(&self, client_id: u16, pkg: TcpFromServer) {
+        if let Some(outbound) = self.clients.get(&client_id) {
+            outbound.push(pkg);
+        }
+    }
+    /// Stop sending updates to a client and tell its handler to close the connection
+    pub fn disconnect(&mut self, client_id: u16, reason: DisconnectReason) {
+        if let Some(outbound) = self.clients.remove(&client_id) {
+            let _ = outbound.close.send(reason);
+        }
+    }
+    pub fn send(&mut self, event: EventBroadcast) {
+        let pkg = event.as_package(&mut self.version);
+        for outbound in self.clients.values() {
+            outbound.push(pkg.clone());
+        }
+        if matches!(event,
+            EventBroadcast::Connected {..} |
+            EventBroadcast::Disconnected(_) |
+            EventBroadcast::Reconnected(_) |
+            EventBroadcast::GameCreation {..} |
+            EventBroadcast::GameDeletion(_) |
+            EventBroadcast::GameEntry {..} |
+            EventBroadcast::GameExit(_)
+        ) {
+            let _ = self.udp_events.send(event);
+        }
+    }
+}
+
+impl EventBroadcast {
+    // Bumps the lobby version if the resulting update changes the lobby
+    fn as_package(&self, version: &mut u32) -> TcpFromServer {
+        let lobby_update = |update: LobbyUpdate, version: &mut u32| {
+            if update.changes_lobby() {
+                *version += 1;
+            }
+            TcpFromServer::LobbyUpdate { version: *version, update }
+        };
+        let game_update = |update: GameUpdate, version: &mut u32| {
+            if update.changes_lobby() {
+                *version += 1;
+            }
+            TcpFromServer::GameUpdate { version: *version, update }
+        };
+        match self.clone() {
+            EventBroadcast::Connected { client, .. } => {
+                lobby_update(LobbyUpdate::Connection(client), version)
+            }
+            EventBroadcast::Disconnected(client_id) => {
+                lobby_update(LobbyUpdate::Disconnection(client_id), version)
+            }
+            EventBroadcast::ConnectionInterrupt(client_id) => {
+                lobby_update(LobbyUpdate::ConnectionInterrupt(client_id), version)
+            }
+            EventBroadcast::Reconnected(client_id) => {
+                lobby_update(LobbyUpdate::Reconnect(client_id), version)
+            }
+            EventBroadcast::Message {client_id, content} => {
+                lobby_update(LobbyUpdate::Message { sender: client_id, content }, version)
+            }
+            EventBroadcast::ServerMessage(content) => {
+                lobby_update(LobbyUpdate::ServerMessage(content), version)
+            }
+            EventBroadcast::GameCreation {game, ..} => {
+                game_update(GameUpdate::Creation(game), version)
+            }
+            EventBroadcast::GameDeletion(game_id) => {
+                game_update(GameUpdate::Deletion(game_id), version)
+            }
+            EventBroadcast::GameEntry { client_id, game_id, .. } => {
+                game_update(GameUpdate::Entry { client_id, game_id }, version)
+            }
+            EventBroadcast::GameExit(client_id) => {
+                game_update(GameUpdate::Exit(client_id), version)
+            }
+            EventBroadcast::HostMigration { game_id, host_id } => {
+                game_update(GameUpdate::HostMigration { game_id, host_id }, version)
+            }
+        }
+    }
+}
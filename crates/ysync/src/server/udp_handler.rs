@@ -1,23 +1,48 @@
-use std::net::{IpAddr, SocketAddr};
+use std::{mem::take, net::{IpAddr, SocketAddr}, sync::Arc};
 
 use bevy_utils::HashMap;
 
-use tokio::{net::UdpSocket, sync::broadcast::Receiver, time::sleep_until};
+use tokio::{sync::mpsc::{UnboundedReceiver, UnboundedSender}, time::{interval, sleep_until, Instant, MissedTickBehavior}};
+use tracing::{debug_span, trace, warn};
 
-use crate::{safe_udp::{SafeUdpSupervisor, UdpRecvMemory}, Udp, UdpData, UdpPackage};
+use crate::{transport::DatagramSocket, safe_udp::{ChannelReceiver, ChannelSequences, Resend, SafeUdpSupervisor, SEND_INTERVAL}, UdpData, UdpDatagram, UdpMessage, UdpPackage, MAX_DATAGRAM_SIZE};
 
-use super::EventBroadcast;
+use super::{manager::ManagerNotify, metrics::Metrics, EventBroadcast};
+
+// Both directions of the udp traffic with a single client
+struct ClientLink {
+    // Where the client's datagrams come from, None until the first one arrived
+    addr: Option<SocketAddr>,
+    supervisor: SafeUdpSupervisor,
+    sequences: ChannelSequences,
+    channels: ChannelReceiver,
+    // Messages send with the next tick
+    outgoing: Vec<UdpMessage>,
+}
+
+impl ClientLink {
+    fn new() -> ClientLink {
+        ClientLink {
+            addr: None,
+            supervisor: SafeUdpSupervisor::new(),
+            sequences: ChannelSequences::default(),
+            channels: ChannelReceiver::new(),
+            outgoing: vec![],
+        }
+    }
+}
 
 struct AddrManager {
     // Client Ip to (game_id, bool) where bool indicates whether the full SocketAddr has already
     // been inserted
     clients: HashMap<IpAddr, (u16, bool)>,
-    // Client id to Ip
+    // Client id to Ip, of every connected client
     client_ids: HashMap<u16, IpAddr>,
-    // Client Ip to id
+    // Client Ip to id, of every connected client
     client_ips: HashMap<IpAddr, u16>,
-    // Last 50 packets received from every client
-    memory: HashMap<IpAddr, UdpRecvMemory>,
+    // Udp link to every connected client, kept as long as the client's lobby connection as the
+    // client keeps its side of the link for that long as well
+    links: HashMap<IpAddr, ClientLink>,
     // Game id to client Ips + Ports
     games: HashMap<u16, Vec<SocketAddr>>
 }
@@ -28,132 +53,197 @@ impl AddrManager {
             clients: HashMap::new(),
             client_ids: HashMap::new(),
             client_ips: HashMap::new(),
-            memory: HashMap::new(),
+            links: HashMap::new(),
             games: HashMap::new()
         }
     }
-    fn game_creation(&mut self, game_id: u16, host_id: u16, host_addr: IpAddr) {
+    fn connection(&mut self, client_id: u16, client_addr: IpAddr) {
+        self.client_ids.insert(client_id, client_addr);
+        self.client_ips.insert(client_addr, client_id);
+        self.links.insert(client_addr, ClientLink::new());
+    }
+    // The client connected again with a new socket, so the link starts over
+    fn reconnection(&mut self, client_id: u16) {
+        if let Some(client_addr) = self.client_ids.get(&client_id) {
+            self.links.insert(*client_addr, ClientLink::new());
+        }
+    }
+    fn disconnection(&mut self, client_id: u16) {
+        self.game_exit(client_id);
+        if let Some(client_addr) = self.client_ids.remove(&client_id) {
+            self.client_ips.remove(&client_addr);
+            self.links.remove(&client_addr);
+        }
+    }
+    fn game_creation(&mut self, game_id: u16, host_addr: IpAddr) {
         self.clients.insert(host_addr, (game_id, false));
-        self.client_ids.insert(host_id, host_addr);
-        self.client_ips.insert(host_addr, host_id);
-        self.memory.insert(host_addr, UdpRecvMemory::new());
         self.games.insert(game_id, vec![]);
     }
     fn game_deletion(&mut self, game_id: u16) {
-        if let Some(clients) = self.games.remove(&game_id) {
-            let _ = clients.iter().map(|c| {
-                let ip = c.ip();
-                self.clients.remove(&ip);
-                self.memory.remove(&ip);
-                if let Some(id) = self.client_ips.remove(&c.ip()) {
-                    self.client_ids.remove(&id);
-                }
-            });
-        }
+        self.games.remove(&game_id);
+        self.clients.retain(|_, (game, _)| *game != game_id);
     }
-    fn game_entry(&mut self, client_id: u16, client_addr: IpAddr, game_id: u16) {
+    fn game_entry(&mut self, client_addr: IpAddr, game_id: u16) {
         self.clients.insert(client_addr, (game_id, false));
-        self.client_ids.insert(client_id, client_addr);
-        self.client_ips.insert(client_addr, client_id);
-        self.memory.insert(client_addr, UdpRecvMemory::new());
     }
     fn game_exit(&mut self, client_id: u16) {
-        if let Some(client_addr) = self.client_ids.remove(&client_id) {
-            self.client_ips.remove(&client_addr);
-            if let Some((game_id, is_registered)) = self.clients.remove(&client_addr) {
-                if is_registered {
-                    self.games.get_mut(&game_id).map(|clients| clients.retain(|c| c.ip() != client_addr));
+        if let Some(client_addr) = self.client_ids.get(&client_id) {
+            if let Some((game_id, is_registered)) = self.clients.remove(client_addr) {
+                if let (true, Some(clients)) = (is_registered, self.games.get_mut(&game_id)) {
+                    clients.retain(|c| c.ip() != *client_addr);
                 }
             }
-            self.memory.remove(&client_addr);
         }
     }
     fn get_client_id(&self, client_addr: SocketAddr) -> Option<u16> {
         self.client_ips.get(&client_addr.ip()).copied()
     }
+    fn get_game_id(&self, client_addr: IpAddr) -> Option<u16> {
+        self.clients.get(&client_addr).map(|(game_id, _)| *game_id)
+    }
     fn get_redirect_list(&self, client_addr: IpAddr) -> Vec<SocketAddr> {
         let default = vec![];
         self.clients.get(&client_addr).map(|(game_id, _)| self.games.get(game_id).unwrap_or(&default)).unwrap_or(&default).to_vec()
     }
-    fn is_registered(&self, client_addr: IpAddr) -> bool {
-        self.clients.get(&client_addr).unwrap().1
-    }
+    // Put the client on the redirect_list of it's game, if it is in one and not registered yet
     fn register_full_addr(&mut self, client_addr: SocketAddr) {
-        if let Some((game_id, is_registered)) = self.clients.get_mut(&client_addr.ip()) {
+        if let Some((game_id, is_registered @ false)) = self.clients.get_mut(&client_addr.ip()) {
             *is_registered = true;
-            self.games.get_mut(game_id).map(|clients| clients.push(client_addr));
+            if let Some(clients) = self.games.get_mut(game_id) {
+                clients.push(client_addr);
+            }
         }
     }
 }
 
-pub async fn udp_handler(mut event_broadcast: Receiver<EventBroadcast>) -> tokio::io::Result<()> {
-    let udp = UdpSocket::bind("0.0.0.0:9983").await?;
+pub async fn udp_handler<S: DatagramSocket>(
+    udp: S,
+    mut event_broadcast: UnboundedReceiver<EventBroadcast>,
+    manager_send: UnboundedSender<ManagerNotify>,
+    metrics: Arc<Metrics>,
+) -> tokio::io::Result<()> {
     let mut manager = AddrManager::new();
-    let mut supervisor = SafeUdpSupervisor::new();
-    let mut buf = [0; Udp::MAX_SIZE + 4];
+    let mut send_tick = interval(SEND_INTERVAL);
+    send_tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut buf = [0; MAX_DATAGRAM_SIZE];
     loop {
+        let next_resend = manager.links.values()
+            .filter_map(|link| link.supervisor.next_resend())
+            .min();
         tokio::select! {
             // Receive and handle packets from the clients
-            Ok((_, sender)) = udp.recv_from(&mut buf) => {
-                if let Some(sender_id) = manager.get_client_id(sender) {
-                    match Udp::from_buf(&buf[4..]) {
-                        Ok(Udp::Data { id, data }) => {
-                            // Let the client know we got the pkg
-                            let _ = udp.send_to(&Udp::Response(id).as_bytes(), sender).await;
-                            // Check if we already got this pkg before
-                            if manager.memory.get_mut(&sender.ip()).map(|m| m.check_packet(id)).unwrap_or(false) {
-                                if let UdpData::FromClient(content) = data {
-                                    match content {
-                                        UdpPackage::Heartbeat => {
-                                            // Check if the client has already been registered on
-                                            // the redirect_list of it's game and register if not
-                                            if !manager.is_registered(sender.ip()) {
-                                                manager.register_full_addr(sender);
-                                            }
-                                        }
-                                        _ => {
-                                            // Forward pkg to all other clients connected to the
-                                            // game
-                                            let redirect_list = manager.get_redirect_list(sender.ip());
-                                            let pkg_data = UdpData::FromServer {
-                                                sender_id,
-                                                content
-                                            };
-                                            for client in redirect_list.into_iter() {
-                                                if client != sender {
-                                                    udp.send_to(&Udp::Data { id, data: pkg_data.clone()}.as_bytes(), client).await?;
-                                                    // Remember that we send this pkg, so we can
-                                                    // resend if we don't get a response
-                                                    supervisor.send(pkg_data.clone());
-                                                }
-                                            }
-                                        }
-                                    }
-                                } else {println!("unexpectedly got a UdpData::FromServer: {data:?}")}
+            Ok((n, sender)) = udp.recv_from(&mut buf) => {
+                let Some(sender_id) = manager.get_client_id(sender) else {
+                    continue;
+                };
+                metrics.udp_in();
+                // Too short to even hold the length prefix
+                if n < 4 {
+                    metrics.decode_error();
+                    warn!(%sender, n, "Got a truncated udp package");
+                    continue;
+                }
+                let datagram = match UdpDatagram::from_buf(&buf[4..n]) {
+                    Ok(datagram) => datagram,
+                    Err(e) => {
+                        metrics.decode_error();
+                        warn!(%sender, "Got invalid udp package, e: {e}");
+                        continue;
+                    }
+                };
+                let Some(link) = manager.links.get_mut(&sender.ip()) else {
+                    continue;
+                };
+                link.addr = Some(sender);
+                if let Some(rtt) = link.supervisor.acked(datagram.ack, datagram.ack_bits) {
+                    metrics.record_ping(sender_id, rtt);
+                }
+                // Duplicates, stale and not yet ordered messages are held back by the channel
+                // receiver
+                for (channel, data) in link.channels.receive(datagram) {
+                    match data {
+                        UdpData::FromClient(UdpPackage::Heartbeat) => {
+                            manager.register_full_addr(sender);
+                        }
+                        UdpData::FromClient(content) => {
+                            // Queue the message for all other clients connected to the game, on
+                            // the channel it was sent on
+                            let redirect_list = manager.get_redirect_list(sender.ip());
+                            let pkg_data = UdpData::FromServer {
+                                sender_id,
+                                content
+                            };
+                            let game_id = manager.get_game_id(sender.ip());
+                            debug_span!("game", ?game_id, sender_id).in_scope(|| {
+                                trace!(recipients = redirect_list.len().saturating_sub(1), ?channel, "forwarding {:?}", pkg_data);
+                            });
+                            for client in redirect_list.into_iter().filter(|c| *c != sender) {
+                                if let Some(link) = manager.links.get_mut(&client.ip()) {
+                                    // Reliable messages are remembered, so we can resend if the
+                                    // datagram carrying them doesn't get acknowledged
+                                    let msgs = link.supervisor.package(pkg_data.clone(), channel, &mut link.sequences);
+                                    link.outgoing.extend(msgs);
+                                }
                             }
                         }
-                        Ok(Udp::Response(id)) => supervisor.received(id),
-                        Err(e) => println!("Got invalid udp package, e: {e}"),
+                        data => warn!(%sender, "unexpectedly got a UdpData::FromServer: {data:?}")
                     }
                 }
             }
-            // If we don't get a response in time, resend the package
-            _ = sleep_until(supervisor.next_resend.instant) => {
-                if let Some(pkg) = supervisor.resend(supervisor.next_resend.id) {
-                    let _ = udp.send(&pkg.as_bytes()).await;
+            // Send everything queued since the last tick, together with the acknowledgements
+            _ = send_tick.tick() => {
+                for link in manager.links.values_mut() {
+                    let Some(addr) = link.addr else {
+                        continue;
+                    };
+                    if link.outgoing.is_empty() && !link.channels.ack_pending() {
+                        continue;
+                    }
+                    for datagram in link.supervisor.datagrams(take(&mut link.outgoing), link.channels.ack()) {
+                        udp.send_to(&datagram.as_bytes(), addr).await?;
+                        metrics.udp_out();
+                    }
+                }
+            }
+            // If a message didn't get acknowledged in time, queue it again
+            _ = sleep_until(next_resend.unwrap_or_else(Instant::now)), if next_resend.is_some() => {
+                for (addr, link) in manager.links.iter_mut() {
+                    match link.supervisor.resends() {
+                        Resend::Messages(messages) => {
+                            for _ in 0..messages.len() {
+                                metrics.udp_resend();
+                            }
+                            link.outgoing.extend(messages);
+                        }
+                        // The lobby connection is interrupted, so the client has to connect again
+                        Resend::Unreachable => {
+                            let client_id = manager.client_ips.get(addr);
+                            warn!(?client_id, %addr, "client stopped acknowledging udp messages, interrupting its connection");
+                            let _ = manager_send.send(ManagerNotify::ConnectionInterrupt(*addr));
+                        }
+                    }
                 }
             }
             // Get Tcp events and update the AddrManager accordingly
-            Ok(event) = event_broadcast.recv() => {
+            Some(event) = event_broadcast.recv() => {
                 match event {
+                    EventBroadcast::Connected { client, addr } => {
+                        manager.connection(client.client_id, addr);
+                    }
+                    EventBroadcast::Reconnected(client_id) => {
+                        manager.reconnection(client_id);
+                    }
+                    EventBroadcast::Disconnected(client_id) => {
+                        manager.disconnection(client_id);
+                    }
                     EventBroadcast::GameCreation { game, host_addr } => {
-                        manager.game_creation(game.game_id, game.host_id, host_addr);
+                        manager.game_creation(game.game_id, host_addr);
                     }
                     EventBroadcast::GameDeletion(game_id) => {
                         manager.game_deletion(game_id);
                     }
-                    EventBroadcast::GameEntry { client_id, client_addr, game_id } => {
-                        manager.game_entry(client_id, client_addr, game_id);
+                    EventBroadcast::GameEntry { client_addr, game_id, .. } => {
+                        manager.game_entry(client_addr, game_id);
                     }
                     EventBroadcast::GameExit(client_id) => {
                         manager.game_exit(client_id);
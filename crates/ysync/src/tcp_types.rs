@@ -3,6 +3,11 @@ use std::fmt::Display;
 use bevy_utils::HashMap;
 use yserde_bytes::AsBytes;
 
+/// Size of the chunks a game world is transferred in
+pub const WORLD_CHUNK_SIZE: usize = 16 * 1024;
+/// Largest game world the lobby accepts from a host
+pub const MAX_WORLD_SIZE: usize = 16 * 1024 * 1024;
+
 #[derive(AsBytes, Debug)]
 pub enum TcpFromClient {
     LobbyDisconnect,
@@ -16,15 +21,65 @@ pub enum TcpFromClient {
         game_id: u16
     },
     GameExit,
-    GameWorld(#[u16]String),
+    // A piece of the hosted game's world, answering `TcpFromServer::WorldRequest`
+    WorldChunk {
+        request: u16,
+        index: u16,
+        count: u16,
+        #[u16]
+        data: Vec<u8>
+    },
+    // Asks for a chunk of the world announced by `TcpFromServer::WorldAvailable`
+    RequestWorldChunk {
+        world: u16,
+        index: u16
+    },
     Message(String),
-    Heartbeat
+    Heartbeat,
+    // Send if the client noticed a gap in the lobby versions
+    RequestLobbySnapshot
 }
 
-#[derive(AsBytes, Debug)]
+// `version` is the version of the lobby after applying the update, it only increases with
+// updates that change the lobby (see `changes_lobby`)
+#[derive(AsBytes, Debug, Clone)]
 pub enum TcpFromServer {
-    LobbyUpdate(LobbyUpdate),
-    GameUpdate(GameUpdate)
+    LobbyUpdate {
+        version: u32,
+        update: LobbyUpdate
+    },
+    GameUpdate {
+        version: u32,
+        update: GameUpdate
+    },
+    // Send instead of the missed updates if the client fell behind or requested it
+    LobbySnapshot {
+        version: u32,
+        lobby: Lobby
+    },
+    // Send right before the server closes the connection
+    Disconnect(DisconnectReason),
+    // Asks the host for its world on behalf of the client that just joined its game
+    WorldRequest {
+        request: u16,
+        client_id: u16
+    },
+    // The world of the game the client joined can be downloaded, `size` bytes in `count` chunks
+    WorldAvailable {
+        world: u16,
+        size: u32,
+        count: u16
+    },
+    WorldChunk {
+        world: u16,
+        index: u16,
+        #[u16]
+        data: Vec<u8>
+    },
+    // The requested world got replaced by a newer one or its game closed
+    WorldUnavailable(u16),
+    // The host didn't share its world with the client that joined its game in time
+    WorldTimeout,
 }
 
 #[derive(AsBytes, Default, Debug)]
@@ -34,6 +89,7 @@ pub struct LobbyConnectionRequest(pub String);
 pub enum LobbyConnectionResponse {
     Accept {
         client_id: u16,
+        version: u32,
         lobby: Lobby
     },
     Deny(LobbyConnectionDenyReason)
@@ -42,13 +98,35 @@ pub enum LobbyConnectionResponse {
 #[derive(AsBytes, Default, Debug)]
 pub enum LobbyConnectionDenyReason {
     #[default]
-    AlreadyConnected
+    AlreadyConnected,
+    // Seconds until the ban expires, None if it is permanent
+    Banned(Option<u64>),
 }
 
 impl Display for LobbyConnectionDenyReason {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::AlreadyConnected => write!(f, "There already is an active connection with this IP")
+            Self::AlreadyConnected => write!(f, "There already is an active connection with this IP"),
+            Self::Banned(None) => write!(f, "You are permanently banned from this lobby"),
+            Self::Banned(Some(secs)) => write!(f, "You are banned from this lobby for another {secs}s"),
+        }
+    }
+}
+
+#[derive(AsBytes, Default, Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    #[default]
+    Kicked,
+    // Seconds until the ban expires, None if it is permanent
+    Banned(Option<u64>),
+}
+
+impl Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Kicked => write!(f, "You have been kicked from the lobby"),
+            Self::Banned(None) => write!(f, "You have been permanently banned from the lobby"),
+            Self::Banned(Some(secs)) => write!(f, "You have been banned from the lobby for {secs}s"),
         }
     }
 }
@@ -65,6 +143,14 @@ pub enum LobbyUpdate {
         sender: u16,
         content: String
     },
+    // Announcement from the server operator
+    ServerMessage(String),
+}
+
+impl LobbyUpdate {
+    pub fn changes_lobby(&self) -> bool {
+        !matches!(self, LobbyUpdate::Default | LobbyUpdate::Message {..} | LobbyUpdate::ServerMessage(_))
+    }
 }
 
 #[derive(AsBytes, Default, Debug, PartialEq, Eq, Clone)]
@@ -78,7 +164,17 @@ pub enum GameUpdate {
         game_id: u16
     },
     Exit(u16),
-    World(#[u16]String),
+    // The host left the game, `host_id` took over, the old host's `Exit` follows
+    HostMigration {
+        game_id: u16,
+        host_id: u16
+    },
+}
+
+impl GameUpdate {
+    pub fn changes_lobby(&self) -> bool {
+        !matches!(self, GameUpdate::Default)
+    }
 }
 
 #[derive(AsBytes, Default, Debug, Clone, PartialEq, Eq)]
@@ -95,7 +191,7 @@ pub trait CustomDisplay {
 
 impl CustomDisplay for HashMap<u16, Client> {
     fn to_string(&self) -> String {
-        match self.len() == 0 {
+        match self.is_empty() {
              true => "No clients logged in".to_string(),
              false => self.values().fold("".to_string(), |acc, client| {
                 format!("{acc}{}: {} (in_game: {}, status: {})",
@@ -141,7 +237,7 @@ pub struct Game {
 
 impl CustomDisplay for HashMap<u16, Game> {
     fn to_string(&self) -> String {
-        match self.len() == 0 {
+        match self.is_empty() {
              true => "No games hosted".to_string(),
              false => self.values().fold("".to_string(), |acc, game| {
                 format!("{acc}{}: {}, (hosted_by: #{}, password: {}, connected clients: {:?})",
@@ -158,7 +254,7 @@ impl CustomDisplay for HashMap<u16, Game> {
     }
 }
 
-#[derive(AsBytes, Default, Debug)]
+#[derive(AsBytes, Default, Debug, Clone, PartialEq, Eq)]
 pub struct Lobby {
     pub client_count: u16,
     pub game_count: u16,
@@ -1,35 +1,1157 @@
-use crate::{client::{self, TcpPackage, TcpUpdate}, server, Game, GameUpdateData, LobbyUpdateData};
+use std::{collections::HashMap, net::{IpAddr, Ipv4Addr, SocketAddr}, time::Duration};
+
+use bevy_math::Vec3;
+use crossbeam::channel::Receiver;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt, DuplexStream}, time::{sleep, sleep_until, timeout, Instant}};
+
+use crate::{
+    client::{self, ConnectionSocket, LobbyConnectionError, TcpUpdate},
+    netsim::NetConditions,
+    safe_udp::{ChannelReceiver, ChannelSequences, Resend, SafeUdpSupervisor, UdpRecvMemory, RECV_WINDOW},
+    server::{self, commands::{Action, CommandOutput, CommandRegistry}, ServerConfig, TestConsole},
+    transport::{DatagramSocket, MemoryHost, MemoryNetwork, Transport},
+    DisconnectReason, Game, GameUpdate, Lobby, LobbyConnectionDenyReason, LobbyConnectionRequest, LobbyConnectionResponse, LobbyUpdate, TcpFromClient, TcpFromServer, UdpChannel, UdpData, UdpDatagram, UdpMessage, UdpPackage, YMove, YNpcState, YPlayerState, YSnapshot, MAX_DATAGRAM_SIZE, WORLD_CHUNK_SIZE
+};
+
+const LOBBY: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 9983);
+
+// Every client needs its own ip, the lobby allows only one connection per ip
+fn host(network: &MemoryNetwork, n: u8) -> MemoryHost {
+    network.host(IpAddr::V4(Ipv4Addr::new(10, 0, 0, n)))
+}
+
+async fn start_lobby() -> MemoryNetwork {
+    let network = MemoryNetwork::new();
+    let config = ServerConfig {
+        ban_file: std::env::temp_dir().join("ysync_test_bans.txt"),
+        ..Default::default()
+    };
+    tokio::spawn(server::listen_with(host(&network, 1), LOBBY, config));
+    sleep(Duration::from_millis(10)).await;
+    network
+}
+
+// A lobby with a ban file of its own, so bans don't leak into other tests
+async fn start_lobby_with_console(network: &MemoryNetwork, ban_file: &str) -> TestConsole {
+    let config = ServerConfig {
+        ban_file: std::env::temp_dir().join(ban_file),
+        ..Default::default()
+    };
+    let console = server::spawn_with_console(host(network, 1), LOBBY, config);
+    sleep(Duration::from_millis(10)).await;
+    console
+}
+
+async fn connect(network: &MemoryNetwork, n: u8, name: &str) -> (ConnectionSocket, Lobby) {
+    ConnectionSocket::build_with(&host(network, n), LOBBY, "0.0.0.0:0".parse().unwrap(), name.to_string(), None)
+        .await
+        .expect("Failed to connect to the lobby")
+}
+
+// Only the client's udp traffic goes through the simulated conditions
+async fn connect_conditioned(network: &MemoryNetwork, n: u8, name: &str, conditions: NetConditions) -> (ConnectionSocket, Lobby) {
+    ConnectionSocket::build_with(&host(network, n), LOBBY, "0.0.0.0:0".parse().unwrap(), name.to_string(), Some(conditions))
+        .await
+        .expect("Failed to connect to the lobby")
+}
+
+// The remaining seconds of the ban if the lobby denies the connection for one
+async fn ban_of(network: &MemoryNetwork, n: u8) -> Option<Option<u64>> {
+    match ConnectionSocket::build_with(&host(network, n), LOBBY, "0.0.0.0:0".parse().unwrap(), format!("c{n}"), None).await {
+        Err(LobbyConnectionError::ConnectionDenied(LobbyConnectionDenyReason::Banned(remaining))) => Some(remaining),
+        _ => None,
+    }
+}
+
+// Connects without a ConnectionSocket, so no heartbeats keep the connection alive
+async fn connect_raw(network: &MemoryNetwork, n: u8, name: &str) -> (DuplexStream, u16) {
+    let mut tcp = host(network, n).connect(LOBBY).await.expect("Failed to connect to the lobby");
+    tcp.write_all(&LobbyConnectionRequest(name.to_string()).as_bytes()).await.unwrap();
+    let mut buf = [0; 4];
+    tcp.read_exact(&mut buf).await.unwrap();
+    let mut pkg_buf = vec![0; u32::from_ne_bytes(buf) as usize];
+    tcp.read_exact(&mut pkg_buf).await.unwrap();
+    match LobbyConnectionResponse::from_buf(&pkg_buf) {
+        Ok(LobbyConnectionResponse::Accept { client_id, .. }) => (tcp, client_id),
+        _ => panic!("The lobby didn't accept the connection"),
+    }
+}
+
+// The sockets hand out updates through blocking channels, polling them lets the paused clock
+// advance in the meantime
+async fn next<T>(receiver: &Receiver<T>) -> T {
+    timeout(Duration::from_secs(90), async {
+        loop {
+            if let Ok(value) = receiver.try_recv() {
+                return value;
+            }
+            sleep(Duration::from_millis(5)).await;
+        }
+    }).await.expect("Nothing received in time")
+}
+
+async fn lobby_update(socket: &ConnectionSocket) -> LobbyUpdate {
+    match next(&socket.tcp_recv).await {
+        TcpUpdate::LobbyUpdate(update) => update,
+        update => panic!("Expected a lobby update, got {update:?}"),
+    }
+}
+
+async fn game_update(socket: &ConnectionSocket) -> GameUpdate {
+    match next(&socket.tcp_recv).await {
+        TcpUpdate::GameUpdate(update) => update,
+        update => panic!("Expected a game update, got {update:?}"),
+    }
+}
+
+// Waits until the whole world arrived, checking the progress on the way
+async fn download_world(socket: &ConnectionSocket) -> String {
+    let mut last_received = None;
+    loop {
+        match next(&socket.tcp_recv).await {
+            TcpUpdate::WorldProgress { received, size } => {
+                assert!(received <= size);
+                assert!(last_received.is_none_or(|last| last < received || received == 0));
+                last_received = Some(received);
+            }
+            TcpUpdate::World(scene) => {
+                assert!(last_received.is_some());
+                return scene;
+            }
+            update => panic!("Expected the world, got {update:?}"),
+        }
+    }
+}
+
+// The lobby's request for the host's world on behalf of the joining client
+async fn world_request(host: &ConnectionSocket, joiner: &ConnectionSocket) -> u16 {
+    match next(&host.tcp_recv).await {
+        TcpUpdate::WorldRequest { request, client_id } => {
+            assert_eq!(client_id, joiner.client_id);
+            request
+        }
+        update => panic!("Expected a world request, got {update:?}"),
+    }
+}
+
+// Host a game with `host` and let `guest` join it, returns the request for the host's world
+async fn shared_game(host: &ConnectionSocket, guest: &ConnectionSocket) -> u16 {
+    host.tcp_send.send(TcpFromClient::GameCreation { password: None, name: "testWorld".to_string() }).unwrap();
+    let GameUpdate::Creation(game) = game_update(guest).await else {
+        panic!("Expected the game creation");
+    };
+    guest.tcp_send.send(TcpFromClient::GameEntry { password: None, game_id: game.game_id }).unwrap();
+    assert_eq!(game_update(host).await, GameUpdate::Creation(game.clone()));
+    assert_eq!(game_update(host).await, GameUpdate::Entry { client_id: guest.client_id, game_id: game.game_id });
+    assert_eq!(game_update(guest).await, GameUpdate::Entry { client_id: guest.client_id, game_id: game.game_id });
+    world_request(host, guest).await
+}
+
+#[tokio::test(start_paused = true)]
+async fn connect_to_lobby() {
+    let network = start_lobby().await;
+    let (a, lobby) = connect(&network, 2, "a").await;
+    assert_eq!(lobby.client_count, 1);
+    assert_eq!(lobby.clients[&a.client_id].name, "a");
+
+    let (b, lobby) = connect(&network, 3, "b").await;
+    assert_ne!(a.client_id, b.client_id);
+    assert_eq!(lobby.client_count, 2);
+    let LobbyUpdate::Connection(client) = lobby_update(&a).await else {
+        panic!("Expected b's connection");
+    };
+    assert_eq!((client.client_id, client.name.as_str()), (b.client_id, "b"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn deny_second_connection_from_same_ip() {
+    let network = start_lobby().await;
+    let _a = connect(&network, 2, "a").await;
+    let result = ConnectionSocket::build_with(&host(&network, 2), LOBBY, "0.0.0.0:0".parse().unwrap(), "a2".to_string(), None).await;
+    assert!(matches!(result, Err(LobbyConnectionError::ConnectionDenied(LobbyConnectionDenyReason::AlreadyConnected))));
+}
+
+#[tokio::test(start_paused = true)]
+async fn chat() {
+    let network = start_lobby().await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+
+    let msg = "This 1$ @ t€$t m€$$@ge!".to_string();
+    a.tcp_send.send(TcpFromClient::Message(msg.clone())).unwrap();
+    let expected = LobbyUpdate::Message { sender: a.client_id, content: msg };
+    assert_eq!(lobby_update(&b).await, expected);
+    assert_eq!(lobby_update(&a).await, expected);
+}
+
+#[tokio::test(start_paused = true)]
+async fn game_lifecycle() {
+    let network = start_lobby().await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+
+    a.tcp_send.send(TcpFromClient::GameCreation { password: None, name: "testWorld".to_string() }).unwrap();
+    let game = Game {
+        game_id: 0,
+        host_id: a.client_id,
+        password: None,
+        game_name: "testWorld".to_string(),
+        clients: vec![a.client_id],
+    };
+    assert_eq!(game_update(&a).await, GameUpdate::Creation(game.clone()));
+    assert_eq!(game_update(&b).await, GameUpdate::Creation(game));
+
+    b.tcp_send.send(TcpFromClient::GameEntry { password: None, game_id: 0 }).unwrap();
+    let entry = GameUpdate::Entry { client_id: b.client_id, game_id: 0 };
+    assert_eq!(game_update(&a).await, entry);
+    assert_eq!(game_update(&b).await, entry);
+    world_request(&a, &b).await;
+
+    b.tcp_send.send(TcpFromClient::GameExit).unwrap();
+    assert_eq!(game_update(&a).await, GameUpdate::Exit(b.client_id));
+    assert_eq!(game_update(&b).await, GameUpdate::Exit(b.client_id));
+
+    a.tcp_send.send(TcpFromClient::GameDeletion).unwrap();
+    assert_eq!(game_update(&a).await, GameUpdate::Deletion(0));
+    assert_eq!(game_update(&b).await, GameUpdate::Deletion(0));
+}
+
+#[tokio::test(start_paused = true)]
+async fn world_sharing() {
+    let network = start_lobby().await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+    let request = shared_game(&a, &b).await;
+
+    a.share_world(request, SCENE_STRING);
+    assert_eq!(download_world(&b).await, SCENE_STRING);
+    // The host already knows its world
+    sleep(Duration::from_secs(1)).await;
+    assert!(a.tcp_recv.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn empty_world_sharing() {
+    let network = start_lobby().await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+    let request = shared_game(&a, &b).await;
+
+    a.share_world(request, "");
+    assert_eq!(download_world(&b).await, "");
+}
+
+#[tokio::test(start_paused = true)]
+async fn world_request_times_out() {
+    let network = start_lobby().await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+    let request = shared_game(&a, &b).await;
+
+    // The host never answers, so the joiner learns it won't get the world
+    assert_eq!(next(&b.tcp_recv).await, TcpUpdate::WorldTimeout);
+    // And an answer arriving too late is ignored
+    a.share_world(request, SCENE_STRING);
+    sleep(Duration::from_secs(1)).await;
+    assert!(b.tcp_recv.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn large_world_only_reaches_the_joiner() {
+    let network = start_lobby().await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+    let (c, _) = connect(&network, 4, "c").await;
+    lobby_update(&a).await;
+    lobby_update(&b).await;
+    let request = shared_game(&a, &b).await;
+
+    // Far more than a single package could carry
+    let scene = SCENE_STRING.repeat(200);
+    assert!(scene.len() > 10 * WORLD_CHUNK_SIZE);
+    a.share_world(request, &scene);
+    assert_eq!(download_world(&b).await, scene);
+    // c isn't in the game, it only saw the game get created and b join it
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(c.tcp_recv.len(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn host_migration() {
+    let network = start_lobby().await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+    let (c, _) = connect(&network, 4, "c").await;
+    lobby_update(&a).await;
+    lobby_update(&b).await;
+    shared_game(&a, &b).await;
+    game_update(&c).await;
+    game_update(&c).await;
+    c.tcp_send.send(TcpFromClient::GameEntry { password: None, game_id: 0 }).unwrap();
+    let entry = GameUpdate::Entry { client_id: c.client_id, game_id: 0 };
+    assert_eq!(game_update(&a).await, entry);
+    world_request(&a, &c).await;
+    assert_eq!(game_update(&b).await, entry);
+    assert_eq!(game_update(&c).await, entry);
+
+    // b is in the game the longest, so it takes over once the host leaves
+    a.tcp_send.send(TcpFromClient::LobbyDisconnect).unwrap();
+    for socket in [&b, &c] {
+        assert_eq!(game_update(socket).await, GameUpdate::HostMigration { game_id: 0, host_id: b.client_id });
+        assert_eq!(game_update(socket).await, GameUpdate::Exit(a.client_id));
+    }
+    // The request a didn't answer goes to the new host
+    let request = world_request(&b, &c).await;
+    assert_eq!(lobby_update(&b).await, LobbyUpdate::Disconnection(a.client_id));
+    assert_eq!(lobby_update(&c).await, LobbyUpdate::Disconnection(a.client_id));
+    b.share_world(request, SCENE_STRING);
+    assert_eq!(download_world(&c).await, SCENE_STRING);
+
+    // And only the new host can close the game
+    b.tcp_send.send(TcpFromClient::GameDeletion).unwrap();
+    assert_eq!(game_update(&c).await, GameUpdate::Deletion(0));
+}
+
+#[tokio::test(start_paused = true)]
+async fn heartbeat_timeout() {
+    let network = start_lobby().await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (_b, b_id) = connect_raw(&network, 3, "b").await;
+    assert!(matches!(lobby_update(&a).await, LobbyUpdate::Connection(_)));
+
+    // b never sends a heartbeat, so its connection counts as interrupted
+    assert_eq!(lobby_update(&a).await, LobbyUpdate::ConnectionInterrupt(b_id));
+    // And b gets disconnected for good if it doesn't reconnect
+    assert_eq!(lobby_update(&a).await, LobbyUpdate::Disconnection(b_id));
+}
+
+#[tokio::test(start_paused = true)]
+async fn interrupt_and_reconnect() {
+    let network = start_lobby().await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, b_id) = connect_raw(&network, 3, "b").await;
+    assert!(matches!(lobby_update(&a).await, LobbyUpdate::Connection(_)));
+
+    drop(b);
+    assert_eq!(lobby_update(&a).await, LobbyUpdate::ConnectionInterrupt(b_id));
+
+    let (b, lobby) = connect(&network, 3, "b").await;
+    assert_eq!(b.client_id, b_id);
+    assert_eq!(lobby.client_count, 2);
+    assert_eq!(lobby_update(&a).await, LobbyUpdate::Reconnect(b_id));
+}
+
+#[tokio::test(start_paused = true)]
+async fn kick() {
+    let network = MemoryNetwork::new();
+    let console = start_lobby_with_console(&network, "ysync_test_bans_kick.txt").await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+
+    assert_eq!(console.run(&format!("kick {}", b.client_id)).await, format!("Kicked client #{}", b.client_id));
+    assert!(matches!(next(&b.tcp_recv).await, TcpUpdate::Disconnected(DisconnectReason::Kicked)));
+    assert_eq!(lobby_update(&a).await, LobbyUpdate::Disconnection(b.client_id));
+    assert_eq!(console.run("kick 99").await, "There is no client with id 99");
+    // Kicked clients may come back
+    let (b, lobby) = connect(&network, 3, "b").await;
+    assert_eq!(lobby.clients[&b.client_id].name, "b");
+}
+
+#[tokio::test(start_paused = true)]
+async fn ban_persists() {
+    let ban_file = "ysync_test_bans_ban.txt";
+    let _ = std::fs::remove_file(std::env::temp_dir().join(ban_file));
+    let network = MemoryNetwork::new();
+    let console = start_lobby_with_console(&network, ban_file).await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+
+    assert_eq!(console.run("ban b 1h").await, "Banned 10.0.0.3 for 3600s");
+    assert!(matches!(next(&b.tcp_recv).await, TcpUpdate::Disconnected(DisconnectReason::Banned(Some(3600)))));
+    assert_eq!(lobby_update(&a).await, LobbyUpdate::Disconnection(b.client_id));
+    // Durations too long to add up or to ever expire are rejected
+    assert!(console.run("ban 10.0.0.4 99999999999999999d").await.starts_with("'99999999999999999d' is not a valid duration"));
+    assert!(console.run(&format!("ban 10.0.0.4 {}", u64::MAX)).await.ends_with("would never expire, leave out the duration to ban permanently"));
+    assert_eq!(ban_of(&network, 4).await, None);
+    assert_eq!(console.run("ban 10.0.0.4").await, "Banned 10.0.0.4 permanently");
+    assert!(ban_of(&network, 3).await.is_some_and(|remaining| remaining.is_some()));
+    assert_eq!(ban_of(&network, 4).await, Some(None));
+
+    // A restarted lobby still knows the bans
+    let network = MemoryNetwork::new();
+    let console = start_lobby_with_console(&network, ban_file).await;
+    assert!(ban_of(&network, 3).await.is_some_and(|remaining| remaining.is_some_and(|secs| secs <= 3600)));
+    assert_eq!(ban_of(&network, 4).await, Some(None));
+    assert_eq!(ban_of(&network, 2).await, None);
+
+    assert_eq!(console.run("unban 10.0.0.3").await, "Unbanned 10.0.0.3");
+    connect(&network, 3, "b").await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn mute() {
+    let network = MemoryNetwork::new();
+    let console = start_lobby_with_console(&network, "ysync_test_bans_mute.txt").await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+
+    assert_eq!(console.run(&format!("mute {}", b.client_id)).await, format!("Muted client #{}", b.client_id));
+    assert_eq!(lobby_update(&b).await, LobbyUpdate::ServerMessage("You have been muted".to_string()));
+    b.tcp_send.send(TcpFromClient::Message("hi".to_string())).unwrap();
+    assert_eq!(lobby_update(&b).await, LobbyUpdate::ServerMessage("You are muted, your message was not delivered".to_string()));
+
+    assert_eq!(console.run(&format!("unmute {}", b.client_id)).await, format!("Unmuted client #{}", b.client_id));
+    assert_eq!(lobby_update(&b).await, LobbyUpdate::ServerMessage("You are no longer muted".to_string()));
+    b.tcp_send.send(TcpFromClient::Message("hi again".to_string())).unwrap();
+    // The muted message never reached a
+    assert_eq!(lobby_update(&a).await, LobbyUpdate::Message { sender: b.client_id, content: "hi again".to_string() });
+}
+
+#[tokio::test(start_paused = true)]
+async fn registered_commands() {
+    let mut commands = CommandRegistry::default();
+    commands
+        .register("whois", "whois <name> [verbose]", "look up a client", |args, view| {
+            let name: String = args.required("name")?;
+            let verbose = args.optional::<bool>("verbose")?.unwrap_or(false);
+            let client_id = view.find_client(&name).ok_or(format!("Nobody is called '{name}'"))?;
+            Ok(CommandOutput::Text(match verbose {
+                true => format!("{name} is #{client_id} at {}", view.client_addr(client_id).unwrap()),
+                false => format!("#{client_id}"),
+            }))
+        })
+        .register("shout", "shout <msg>", "say something loudly", |args, _| {
+            Ok(CommandOutput::Action(Action::Say(args.rest().to_uppercase())))
+        });
+    let network = MemoryNetwork::new();
+    let config = ServerConfig {
+        ban_file: std::env::temp_dir().join("ysync_test_bans_commands.txt"),
+        commands,
+        ..Default::default()
+    };
+    let console = server::spawn_with_console(host(&network, 1), LOBBY, config);
+    sleep(Duration::from_millis(10)).await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+
+    assert_eq!(console.run("whois b").await, format!("#{}", b.client_id));
+    assert_eq!(console.run("whois  b   true ").await, format!("b is #{} at 10.0.0.3", b.client_id));
+    assert_eq!(console.run("whois c").await, "Nobody is called 'c'\nUsage: whois <name> [verbose]");
+    assert_eq!(console.run("whois b maybe").await, "'maybe' is not a valid verbose\nUsage: whois <name> [verbose]");
+    assert_eq!(console.run("whois").await, "Missing argument <name>\nUsage: whois <name> [verbose]");
+    assert_eq!(console.run("nope").await, "'nope' is not a valid command, try 'help' instead");
+    // The help lists the registered commands next to the built-in ones
+    assert_eq!(console.run("help whois").await, "whois <name> [verbose] - look up a client");
+    let help = console.run("help").await;
+    assert!(help.contains("\nshout <msg> - say something loudly"));
+    assert!(help.contains("\nkick <id> - disconnect a client"));
+    // Actions are carried out by the lobby
+    console.run("shout hello there").await;
+    assert_eq!(lobby_update(&a).await, LobbyUpdate::ServerMessage("HELLO THERE".to_string()));
+}
+
+// Writes a package the way the lobby would
+async fn send_package(tcp: &mut DuplexStream, pkg: TcpFromServer) {
+    tcp.write_all(&pkg.as_bytes()).await.unwrap();
+}
+
+async fn read_package(tcp: &mut DuplexStream) -> TcpFromClient {
+    timeout(Duration::from_secs(90), async {
+        let mut buf = [0; 4];
+        tcp.read_exact(&mut buf).await.unwrap();
+        let mut pkg_buf = vec![0; u32::from_ne_bytes(buf) as usize];
+        tcp.read_exact(&mut pkg_buf).await.unwrap();
+        TcpFromClient::from_buf(&pkg_buf).unwrap()
+    }).await.expect("Nothing received in time")
+}
+
+fn exit(version: u32, client_id: u16) -> TcpFromServer {
+    TcpFromServer::GameUpdate { version, update: GameUpdate::Exit(client_id) }
+}
+
+#[tokio::test(start_paused = true)]
+async fn gaps_in_the_lobby_versions_request_a_snapshot() {
+    let (client, mut lobby) = tokio::io::duplex(4096);
+    let (_tcp_send, tcp_recv) = tokio::sync::mpsc::unbounded_channel();
+    let (updates, updates_recv) = crossbeam::channel::unbounded();
+    tokio::spawn(client::tcp_handler(client, 3, tcp_recv, updates));
+
+    send_package(&mut lobby, exit(4, 1)).await;
+    assert!(matches!(next(&updates_recv).await, TcpUpdate::GameUpdate(GameUpdate::Exit(1))));
+    // Version 5 got lost
+    send_package(&mut lobby, exit(6, 2)).await;
+    assert!(matches!(read_package(&mut lobby).await, TcpFromClient::RequestLobbySnapshot));
+    // Until the snapshot arrives only updates not changing the lobby get through
+    send_package(&mut lobby, exit(7, 3)).await;
+    send_package(&mut lobby, server_message("still there")).await;
+    let snapshot = Lobby { client_count: 4, ..Default::default() };
+    send_package(&mut lobby, TcpFromServer::LobbySnapshot { version: 7, lobby: snapshot.clone() }).await;
+    // Updates the snapshot already contains are ignored
+    send_package(&mut lobby, exit(7, 3)).await;
+    send_package(&mut lobby, exit(8, 4)).await;
+
+    assert!(matches!(next(&updates_recv).await, TcpUpdate::LobbyUpdate(LobbyUpdate::ServerMessage(content)) if content == "still there"));
+    assert!(matches!(next(&updates_recv).await, TcpUpdate::LobbySnapshot(resynced) if resynced == snapshot));
+    assert!(matches!(next(&updates_recv).await, TcpUpdate::GameUpdate(GameUpdate::Exit(4))));
+    sleep(Duration::from_secs(1)).await;
+    assert!(updates_recv.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn lobby_answers_snapshot_requests() {
+    let network = start_lobby().await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (mut b, b_id) = connect_raw(&network, 3, "b").await;
+    assert!(matches!(lobby_update(&a).await, LobbyUpdate::Connection(_)));
+
+    b.write_all(&TcpFromClient::RequestLobbySnapshot.as_bytes()).await.unwrap();
+    let mut buf = [0; 4];
+    b.read_exact(&mut buf).await.unwrap();
+    let mut pkg_buf = vec![0; u32::from_ne_bytes(buf) as usize];
+    b.read_exact(&mut pkg_buf).await.unwrap();
+    match TcpFromServer::from_buf(&pkg_buf) {
+        Ok(TcpFromServer::LobbySnapshot { version, lobby }) => {
+            // Both connections changed the lobby
+            assert_eq!(version, 2);
+            assert_eq!(lobby.client_count, 2);
+            assert_eq!(lobby.clients[&b_id].name, "b");
+        }
+        pkg => panic!("Expected a lobby snapshot, got {pkg:?}"),
+    }
+}
+
+fn server_message(content: &str) -> TcpFromServer {
+    TcpFromServer::LobbyUpdate { version: 0, update: LobbyUpdate::ServerMessage(content.to_string()) }
+}
+
+#[tokio::test(start_paused = true)]
+async fn outbound_overflow_resyncs() {
+    let (outbound, mut receiver) = server::outbound_queue();
+    for i in 0..40 {
+        outbound.push(server_message(&i.to_string()));
+    }
+    // Everything queued before the overflow is still written before a snapshot is requested
+    let mut delivered = 0;
+    while !receiver.needs_resync() {
+        assert!(matches!(receiver.recv().await, Some(TcpFromServer::LobbyUpdate {..})));
+        delivered += 1;
+    }
+    assert!(delivered < 40);
+    assert!(!receiver.needs_resync(), "A lag requests a single snapshot");
+
+    outbound.resync(TcpFromServer::LobbySnapshot { version: 1, lobby: Lobby::default() });
+    outbound.push(server_message("after"));
+    assert!(matches!(receiver.recv().await, Some(TcpFromServer::LobbySnapshot { version: 1, .. })));
+    assert!(matches!(receiver.recv().await, Some(TcpFromServer::LobbyUpdate { update: LobbyUpdate::ServerMessage(content), .. }) if content == "after"));
+    assert!(!receiver.needs_resync());
+}
+
+#[tokio::test(start_paused = true)]
+async fn outbound_holds_targeted_packages_while_lagging() {
+    let (outbound, mut receiver) = server::outbound_queue();
+    for i in 0..40 {
+        outbound.push(server_message(&i.to_string()));
+    }
+    outbound.push(TcpFromServer::WorldRequest { request: 1, client_id: 2 });
+    outbound.push(server_message("dropped"));
+    outbound.push(TcpFromServer::WorldTimeout);
+    while !receiver.needs_resync() {
+        assert!(matches!(receiver.recv().await, Some(TcpFromServer::LobbyUpdate {..})));
+    }
+
+    outbound.resync(TcpFromServer::LobbySnapshot { version: 1, lobby: Lobby::default() });
+    outbound.push(server_message("after"));
+    assert!(matches!(receiver.recv().await, Some(TcpFromServer::LobbySnapshot {..})));
+    assert!(matches!(receiver.recv().await, Some(TcpFromServer::WorldRequest { request: 1, client_id: 2 })));
+    assert!(matches!(receiver.recv().await, Some(TcpFromServer::WorldTimeout)));
+    assert!(matches!(receiver.recv().await, Some(TcpFromServer::LobbyUpdate { update: LobbyUpdate::ServerMessage(content), .. }) if content == "after"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn udp_relay() {
+    let network = start_lobby().await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+    shared_game(&a, &b).await;
+    // The lobby only relays to clients whose heartbeat arrived since joining
+    sleep(Duration::from_secs(2)).await;
+
+    a.send_udp(UdpPackage::Jump);
+    let (sender, pkg) = next(&b.udp_recv).await;
+    assert_eq!(sender, a.client_id);
+    assert!(matches!(pkg, UdpPackage::Jump));
+
+    b.send_udp(UdpPackage::Jump);
+    let (sender, pkg) = next(&a.udp_recv).await;
+    assert_eq!(sender, b.client_id);
+    assert!(matches!(pkg, UdpPackage::Jump));
+
+    let players = (0..3).map(|id| YPlayerState { id, translation: Vec3::new(1., 2., id as f32).into(), health: 5, last_move: id as u32 * 10 }).collect();
+    a.send_udp(UdpPackage::Snapshot(YSnapshot { time: 500, players, npcs: vec![YNpcState { id: 1, health: 3 }] }));
+    let (_, UdpPackage::Snapshot(snapshot)) = next(&b.udp_recv).await else {
+        panic!("Expected the snapshot");
+    };
+    assert_eq!(snapshot.time, 500);
+    assert_eq!(snapshot.npcs.iter().map(|s| (s.id, s.health)).collect::<Vec<_>>(), vec![(1, 3)]);
+    assert_eq!(snapshot.players.iter().map(|s| (s.id, Vec3::from(s.translation.clone()), s.health, s.last_move)).collect::<Vec<_>>(), vec![
+        (0, Vec3::new(1., 2., 0.), 5, 0),
+        (1, Vec3::new(1., 2., 1.), 5, 10),
+        (2, Vec3::new(1., 2., 2.), 5, 20),
+    ]);
+    // Heartbeats are not relayed
+    sleep(Duration::from_secs(2)).await;
+    assert!(a.udp_recv.is_empty() && b.udp_recv.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn net_stats() {
+    let network = start_lobby().await;
+    // Only a's datagrams to the lobby are delayed
+    let (a, _) = connect_conditioned(&network, 2, "a", NetConditions { latency: Duration::from_millis(40), ..Default::default() }).await;
+    let lossy = NetConditions { loss: 0.5, seed: Some(3), ..Default::default() };
+    let (b, _) = connect_conditioned(&network, 3, "b", lossy).await;
+    // The heartbeats are reliable, so their acknowledgements keep the stats going
+    sleep(Duration::from_secs(20)).await;
+
+    let stats = a.stats.borrow().clone();
+    assert!(stats.rtt_min >= Duration::from_millis(40), "{stats:?}");
+    assert!(stats.rtt_avg >= stats.rtt_min && stats.rtt_avg < Duration::from_millis(200), "{stats:?}");
+    assert_eq!((stats.packet_loss, stats.resends, stats.unreachable), (0., 0, false));
+    assert!(stats.since_last_packet().is_some_and(|since| since <= Duration::from_secs(2)), "{stats:?}");
+    // A heartbeat doesn't fall into every second
+    let mut updates = a.stats.clone();
+    let (mut bytes_in, mut bytes_out) = (0, 0);
+    for _ in 0..3 {
+        updates.changed().await.unwrap();
+        bytes_in += updates.borrow().bytes_in_per_sec;
+        bytes_out += updates.borrow().bytes_out_per_sec;
+    }
+    assert!(bytes_in > 0 && bytes_out > 0);
+
+    let stats = b.stats.borrow().clone();
+    assert!(stats.packet_loss > 0. && stats.resends > 0, "{stats:?}");
+}
+
+#[tokio::test(start_paused = true)]
+async fn reliable_ordered_under_bad_conditions() {
+    let network = start_lobby().await;
+    let conditions = NetConditions {
+        latency: Duration::from_millis(30),
+        jitter: Duration::from_millis(40),
+        loss: 0.2,
+        duplication: 0.1,
+        reorder: 0.2,
+        seed: Some(7),
+    };
+    let (a, _) = connect_conditioned(&network, 2, "a", conditions.clone()).await;
+    let (b, _) = connect_conditioned(&network, 3, "b", NetConditions { seed: Some(8), ..conditions }).await;
+    lobby_update(&a).await;
+    shared_game(&a, &b).await;
+    sleep(Duration::from_secs(2)).await;
+
+    for sequence in 0..100 {
+        a.send_udp_on(UdpChannel::ReliableOrdered, UdpPackage::Move(YMove { sequence, ..Default::default() }));
+    }
+    // Every message arrives exactly once and in the order it was sent
+    let mut received = vec![];
+    while received.len() < 100 {
+        let (sender, pkg) = next(&b.udp_recv).await;
+        assert_eq!(sender, a.client_id);
+        let UdpPackage::Move(ymove) = pkg else {
+            panic!("Expected a move, got {pkg:?}");
+        };
+        received.push(ymove.sequence);
+    }
+    assert_eq!(received, (0..100).collect::<Vec<_>>());
+    sleep(Duration::from_secs(2)).await;
+    assert!(b.udp_recv.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn truncated_datagrams_are_dropped() {
+    let network = start_lobby().await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+    shared_game(&a, &b).await;
+    sleep(Duration::from_secs(2)).await;
+
+    // From a's ip, so the lobby takes them for a's datagrams
+    let raw = host(&network, 2).bind("0.0.0.0:0".parse().unwrap()).await.unwrap();
+    for len in 0..4 {
+        raw.send_to(&vec![1; len], LOBBY).await.unwrap();
+    }
+    sleep(Duration::from_millis(100)).await;
+
+    // The lobby keeps relaying
+    a.send_udp(UdpPackage::Jump);
+    let (sender, pkg) = next(&b.udp_recv).await;
+    assert_eq!(sender, a.client_id);
+    assert!(matches!(pkg, UdpPackage::Jump));
+}
+
+// The metrics as printed by the stats command, by name
+fn stats(output: &str) -> HashMap<String, u64> {
+    output.lines()
+        .filter_map(|line| line.split_once(": "))
+        .filter_map(|(name, value)| Some((name.to_string(), value.parse().ok()?)))
+        .collect()
+}
+
+#[tokio::test(start_paused = true)]
+async fn stats_command() {
+    let network = MemoryNetwork::new();
+    let console = start_lobby_with_console(&network, "ysync_test_bans_stats.txt").await;
+    let (a, _) = connect(&network, 2, "a").await;
+    let (b, _) = connect(&network, 3, "b").await;
+    lobby_update(&a).await;
+    shared_game(&a, &b).await;
+    sleep(Duration::from_secs(2)).await;
+
+    let raw = host(&network, 2).bind("0.0.0.0:0".parse().unwrap()).await.unwrap();
+    for len in [2, 12] {
+        raw.send_to(&vec![1; len], LOBBY).await.unwrap();
+    }
+    // Relayed reliably, so b's acknowledgement gives the lobby a round trip time
+    a.send_udp_on(UdpChannel::ReliableOrdered, UdpPackage::Jump);
+    next(&b.udp_recv).await;
+    sleep(Duration::from_secs(1)).await;
+
+    let output = console.run("stats").await;
+    let metrics = stats(&output);
+    assert_eq!(metrics["connected_clients"], 2);
+    assert_eq!(metrics["active_games"], 1);
+    assert_eq!(metrics["decode_errors_total"], 2);
+    for counter in ["tcp_packets_received_total", "tcp_packets_sent_total", "udp_packets_received_total", "udp_packets_sent_total"] {
+        assert!(metrics[counter] > 0, "{counter} wasn't counted");
+    }
+    assert!(output.contains(&format!("\n  #{}: ", b.client_id)), "No ping of b in {output}");
+
+    a.tcp_send.send(TcpFromClient::GameDeletion).unwrap();
+    game_update(&b).await;
+    assert_eq!(stats(&console.run("stats").await)["active_games"], 0);
+}
+
+#[tokio::test]
+async fn metrics_over_http() {
+    // A free port for the endpoint, it only serves over tcp
+    let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let network = MemoryNetwork::new();
+    let config = ServerConfig {
+        ban_file: std::env::temp_dir().join("ysync_test_bans_metrics.txt"),
+        metrics_addr: Some(addr),
+        ..Default::default()
+    };
+    tokio::spawn(server::listen_with(host(&network, 1), LOBBY, config));
+    sleep(Duration::from_millis(10)).await;
+    connect(&network, 2, "a").await;
+
+    let get = |path: &'static str| async move {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    };
+    let response = get("/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("\n# TYPE ysync_connected_clients gauge\nysync_connected_clients 1\n"));
+    assert!(response.contains("\n# TYPE ysync_udp_resends_total counter\n"));
+    assert!(get("/").await.starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
 
 #[test]
-fn it_works() {
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    rt.spawn(async move {
-        server::listen("127.0.0.1:9983", Some(())).await.expect("Server failed to listen");
+fn truncated_packages_fail_to_decode() {
+    let mut supervisor = SafeUdpSupervisor::new();
+    let messages = supervisor.package(numbered(4), UdpChannel::ReliableOrdered, &mut ChannelSequences::default());
+    let datagram = supervisor.datagrams(messages, (0, 0)).remove(0).as_bytes();
+    assert!(UdpDatagram::from_buf(&datagram[4..]).is_ok());
+    for len in 4..datagram.len() {
+        assert!(UdpDatagram::from_buf(&datagram[4..len]).is_err(), "Decoded {len} of {} bytes", datagram.len());
+    }
+    let mut lobby = Lobby { client_count: 1, ..Default::default() };
+    lobby.clients.insert(2, crate::Client { client_id: 2, name: "a".to_string(), ..Default::default() });
+    let snapshot = TcpFromServer::LobbySnapshot { version: 3, lobby }.as_bytes();
+    for len in 4..snapshot.len() {
+        assert!(TcpFromServer::from_buf(&snapshot[4..len]).is_err(), "Decoded {len} of {} bytes", snapshot.len());
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn unreachable_client_gets_interrupted() {
+    let network = start_lobby().await;
+    let (a, _) = connect(&network, 2, "a").await;
+    // Keeps its lobby connection alive, but never acknowledges a udp message
+    let (b, b_id) = connect_raw(&network, 3, "b").await;
+    let (mut b_read, mut b_write) = tokio::io::split(b);
+    lobby_update(&a).await;
+    a.tcp_send.send(TcpFromClient::GameCreation { password: None, name: "testWorld".to_string() }).unwrap();
+    let GameUpdate::Creation(game) = game_update(&a).await else {
+        panic!("Expected the game creation");
+    };
+    b_write.write_all(&TcpFromClient::GameEntry { password: None, game_id: game.game_id }.as_bytes()).await.unwrap();
+    tokio::spawn(async move {
+        while b_write.write_all(&TcpFromClient::Heartbeat.as_bytes()).await.is_ok() {
+            sleep(Duration::from_secs(1)).await;
+        }
     });
-    rt.block_on(async move {
-        let msg = "This 1$ @ t€$t m€$$@ge!".to_string();
-        let (socket, _lobby) = client::ConnectionSocket::build("127.0.0.1:9983", "0.0.0.0:9983", "tester".into()).await.expect("Failed to get ConnectionSocket");
-        socket.tcp_send.send(TcpPackage::Message(msg.clone())).expect("Failed to send LobbyConnect request");
-        let update = socket.tcp_recv.recv().expect("Failed to receive TcpUpdate!");
+    sleep(Duration::from_millis(100)).await;
+    // A single heartbeat, so the lobby knows where to relay to
+    let udp = host(&network, 3).bind("0.0.0.0:0".parse().unwrap()).await.unwrap();
+    let mut supervisor = SafeUdpSupervisor::new();
+    let heartbeat = supervisor.package(UdpData::FromClient(UdpPackage::Heartbeat), UdpPackage::Heartbeat.channel(), &mut ChannelSequences::default());
+    for datagram in supervisor.datagrams(heartbeat, (0, 0)) {
+        udp.send_to(&datagram.as_bytes(), LOBBY).await.unwrap();
+    }
+    sleep(Duration::from_millis(100)).await;
 
-        assert_eq!(update, TcpUpdate::LobbyUpdate(LobbyUpdateData::Message { sender: 0, length: msg.len() as u8, content: msg }));
+    a.send_udp_on(UdpChannel::ReliableOrdered, UdpPackage::Jump);
+    loop {
+        if let TcpUpdate::LobbyUpdate(LobbyUpdate::ConnectionInterrupt(client_id)) = next(&a.tcp_recv).await {
+            assert_eq!(client_id, b_id);
+            break;
+        }
+    }
+    // The lobby closes the interrupted connection
+    let mut rest = vec![];
+    timeout(Duration::from_secs(1), b_read.read_to_end(&mut rest)).await
+        .expect("The lobby kept the connection open")
+        .unwrap();
+}
 
-        socket.tcp_send.send(TcpPackage::GameCreation { name: "testWorld".to_string(), with_password: false }).expect("Failed to send GameCreation TcpPackage");
-        let update = socket.tcp_recv.recv().expect("Failed to receive TcpUpdate! (#2)");
+#[cfg(feature = "websocket")]
+#[tokio::test]
+async fn websocket_client() {
+    // Real sockets, as the WebSocket listener only runs on top of tcp
+    let free_port = || std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let (lobby, websocket) = (free_port(), free_port());
+    let config = ServerConfig {
+        ban_file: std::env::temp_dir().join("ysync_test_bans.txt"),
+        websocket_addr: Some(websocket),
+        ..Default::default()
+    };
+    tokio::spawn(server::listen(lobby, config));
+    sleep(Duration::from_millis(100)).await;
 
-        assert_eq!(update, TcpUpdate::GameUpdate(GameUpdateData::Creation(Game {
-            game_id: 0,
-            host_id: 0,
-            password: false,
-            game_name: "testWorld".to_string(),
-            clients: vec![0],
-        })));
+    // A peer that never starts its handshake doesn't hold up the others
+    let _stalled = tokio::net::TcpStream::connect(websocket).await.unwrap();
+    let (a, lobby) = timeout(Duration::from_secs(1), ConnectionSocket::build_websocket(websocket, "a".to_string())).await
+        .expect("The WebSocket handshake waited on another peer")
+        .expect("Failed to connect over WebSocket");
+    assert_eq!(lobby.clients[&a.client_id].name, "a");
+    a.tcp_send.send(TcpFromClient::Message("hi".to_string())).unwrap();
+    assert_eq!(lobby_update(&a).await, LobbyUpdate::Message { sender: a.client_id, content: "hi".to_string() });
+    // The first heartbeat gets acknowledged through the tunnel before the stats are updated again
+    sleep(Duration::from_millis(2500)).await;
+    assert!(a.stats.borrow().since_last_packet().is_some());
+}
 
-        socket.tcp_send.send(TcpPackage::GameWorld(SCENE_STRING.to_string())).expect("Failed to send GameWorld as TcpPackage");
-        let update = socket.tcp_recv.recv().expect("Failed to receive TcpUpdate! (#3)");
+#[cfg(feature = "quic")]
+#[tokio::test]
+async fn quic_client() {
+    use crate::quic::{Quic, QuicIdentity};
 
-        assert_eq!(update, TcpUpdate::GameWorld(SCENE_STRING.to_string()));
-    });
+    let lobby = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let identity = QuicIdentity::generate();
+    let cert = identity.cert.clone();
+    let config = ServerConfig {
+        ban_file: std::env::temp_dir().join("ysync_test_bans.txt"),
+        ..Default::default()
+    };
+    tokio::spawn(server::listen_with(Quic::server(identity), lobby, config));
+    sleep(Duration::from_millis(100)).await;
+
+    // A peer that connects but never opens its stream doesn't hold up the others
+    let stalled = Quic::client(cert.clone());
+    let _stalled = stalled.connect(lobby).await.unwrap();
+    let (a, lobby) = timeout(Duration::from_secs(1), ConnectionSocket::build_quic(lobby, cert, "a".to_string())).await
+        .expect("The QUIC handshake waited on another peer")
+        .expect("Failed to connect over QUIC");
+    assert_eq!(lobby.clients[&a.client_id].name, "a");
+    a.tcp_send.send(TcpFromClient::Message("hi".to_string())).unwrap();
+    assert_eq!(lobby_update(&a).await, LobbyUpdate::Message { sender: a.client_id, content: "hi".to_string() });
+    // The first heartbeat gets acknowledged over the datagrams before the stats are updated again
+    sleep(Duration::from_millis(2500)).await;
+    assert!(a.stats.borrow().since_last_packet().is_some());
+}
+
+#[test]
+fn recv_memory_rejects_duplicates() {
+    let mut memory = UdpRecvMemory::new();
+    assert!(memory.check_packet(5));
+    assert!(!memory.check_packet(5));
+    // Reordered ids inside the window are still new
+    assert!(memory.check_packet(7));
+    assert!(memory.check_packet(6));
+    assert!(!memory.check_packet(6));
+    assert!(!memory.check_packet(7));
+}
+
+#[test]
+fn recv_memory_window() {
+    let mut memory = UdpRecvMemory::new();
+    assert!(memory.check_packet(0));
+    assert!(memory.check_packet(RECV_WINDOW));
+    // Fell out of the window
+    assert!(!memory.check_packet(0));
+    // The oldest id still inside the window
+    assert!(memory.check_packet(1));
+    // Wrapping around, u16::MAX comes right before 0
+    let mut memory = UdpRecvMemory::new();
+    assert!(memory.check_packet(u16::MAX - 1));
+    assert!(memory.check_packet(1));
+    assert!(memory.check_packet(u16::MAX));
+    assert!(memory.check_packet(0));
+    assert!(!memory.check_packet(u16::MAX));
+}
+
+#[test]
+fn recv_memory_long_run() {
+    let mut memory = UdpRecvMemory::new();
+    // Several wraparounds, with every id arriving twice and pairs swapped, must keep delivering
+    // each id exactly once
+    let mut delivered = 0;
+    for n in (0..400_000u32).step_by(2) {
+        for id in [n + 1, n, n + 1, n] {
+            if memory.check_packet(id as u16) {
+                delivered += 1;
+            }
+        }
+    }
+    assert_eq!(delivered, 400_000);
+}
+
+#[test]
+fn acks_cover_the_last_32_datagrams() {
+    let mut receiver = ChannelReceiver::new();
+    let datagram = |seq| UdpDatagram { seq, ..Default::default() };
+    // Every fifth datagram gets lost, while the sequence numbers wrap around
+    let sent = (0..40u16).map(|n| n.wrapping_add(65520)).collect::<Vec<_>>();
+    for seq in sent.iter().filter(|seq| *seq % 5 != 0) {
+        receiver.receive(datagram(*seq));
+    }
+    let (ack, ack_bits) = receiver.ack();
+    assert_eq!(ack, 23);
+    for n in 1..=32u16 {
+        assert_eq!(ack_bits & (1 << (n - 1)) != 0, ack.wrapping_sub(n) % 5 != 0, "datagram {}", ack.wrapping_sub(n));
+    }
+    // A late datagram is still acknowledged if it's inside the bitfield
+    receiver.receive(datagram(15));
+    assert_eq!(receiver.ack(), (23, ack_bits | 1 << 7));
+    // The first one lost is too old by now
+    receiver.receive(datagram(sent[0]));
+    assert_eq!(receiver.ack(), (23, ack_bits | 1 << 7));
+    // Right at the edge only the previous newest datagram is left
+    receiver.receive(datagram(55));
+    assert_eq!(receiver.ack(), (55, 1 << 31));
+    receiver.receive(datagram(88));
+    assert_eq!(receiver.ack(), (88, 0));
+}
+
+#[tokio::test(start_paused = true)]
+async fn acks_release_the_messages_of_their_datagrams() {
+    let mut supervisor = SafeUdpSupervisor::new();
+    let mut sequences = ChannelSequences::default();
+    for n in 0..40 {
+        let messages = supervisor.package(numbered(n), UdpChannel::ReliableUnordered, &mut sequences);
+        assert_eq!(supervisor.datagrams(messages, (0, 0)).len(), 1);
+    }
+    // Acknowledges datagram 39 and the 32 before it, the first 7 are beyond the bitfield
+    supervisor.acked(39, u32::MAX);
+    sleep_until(supervisor.next_resend().expect("Messages are still pending")).await;
+    let Resend::Messages(resent) = supervisor.resends() else {
+        panic!("Expected the lost messages to get resent");
+    };
+    let resent = resent.into_iter().map(|message| match message {
+        UdpMessage::Data { data, .. } => (UdpChannel::ReliableUnordered, data),
+        message => panic!("Expected a reliable message, got {message:?}"),
+    }).collect();
+    assert_eq!(numbers(resent), (0..7).collect::<Vec<_>>());
+}
+
+#[test]
+fn datagrams_batch_up_to_the_maximum_size() {
+    let mut supervisor = SafeUdpSupervisor::new();
+    let mut sequences = ChannelSequences::default();
+    let messages = (0..300)
+        .flat_map(|n| supervisor.package(numbered(n), UdpChannel::Sequenced, &mut sequences))
+        .collect::<Vec<_>>();
+    let datagrams = supervisor.datagrams(messages, (7, 3));
+    assert!(datagrams.len() > 1);
+    for datagram in &datagrams {
+        assert!(datagram.as_bytes().len() <= MAX_DATAGRAM_SIZE);
+        assert_eq!((datagram.ack, datagram.ack_bits), (7, 3));
+    }
+    // A datagram is only closed once the next message doesn't fit anymore
+    for pair in datagrams.windows(2) {
+        assert!(pair[0].as_bytes().len() + pair[1].messages[0].as_bytes().len() > MAX_DATAGRAM_SIZE);
+        assert_eq!(pair[1].seq, pair[0].seq.wrapping_add(1));
+    }
+    let delivered = datagrams.into_iter().flat_map(|datagram| datagram.messages).map(|message| match message {
+        UdpMessage::Sequenced { data, .. } => (UdpChannel::Sequenced, data),
+        message => panic!("Expected a sequenced message, got {message:?}"),
+    }).collect();
+    assert_eq!(numbers(delivered), (0..300).collect::<Vec<_>>());
+    // Without messages a single datagram still carries the acknowledgement
+    let datagrams = supervisor.datagrams(vec![], (8, 1));
+    assert_eq!(datagrams.len(), 1);
+    assert!(datagrams[0].messages.is_empty());
+    assert_eq!((datagrams[0].ack, datagrams[0].ack_bits), (8, 1));
+}
+
+// A package that takes several fragments of 8 bytes
+fn fragmented(supervisor: &mut SafeUdpSupervisor, channel: UdpChannel) -> Vec<UdpMessage> {
+    let pkg = UdpData::FromServer { sender_id: 3, content: UdpPackage::Move(YMove { sequence: 1, time: 50, movement: Vec3::X.into(), position: Vec3::new(1., 2., 3.).into() }) };
+    let fragments = supervisor.package(pkg, channel, &mut ChannelSequences::default());
+    assert!(fragments.len() > 2);
+    assert!(fragments.iter().all(|f| matches!(f, UdpMessage::Fragment { .. })));
+    fragments
+}
+
+// Sends every message in its own datagram, through the bytes that would go over the wire
+fn deliver(receiver: &mut ChannelReceiver, messages: Vec<UdpMessage>) -> Vec<(UdpChannel, UdpData)> {
+    messages.into_iter().flat_map(|message| {
+        let bytes = UdpDatagram { messages: vec![message], ..Default::default() }.as_bytes();
+        receiver.receive(UdpDatagram::from_buf(&bytes[4..]).unwrap())
+    }).collect()
+}
+
+#[test]
+fn fragments_reassemble_in_any_order() {
+    let mut supervisor = SafeUdpSupervisor::with_fragment_size(8);
+    let mut fragments = fragmented(&mut supervisor, UdpChannel::ReliableOrdered);
+    let mut receiver = ChannelReceiver::new();
+    let last = fragments.remove(0);
+    fragments.reverse();
+    // Duplicates of the reliable fragments are ignored
+    assert!(deliver(&mut receiver, fragments.clone()).is_empty());
+    assert!(deliver(&mut receiver, fragments).is_empty());
+    let delivered = deliver(&mut receiver, vec![last.clone()]);
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0].0, UdpChannel::ReliableOrdered);
+    assert!(matches!(delivered[0].1, UdpData::FromServer { sender_id: 3, content: UdpPackage::Move(_) }));
+    assert!(deliver(&mut receiver, vec![last]).is_empty());
+}
+
+// A package that can be told apart from the others by its number
+fn numbered(n: u32) -> UdpData {
+    UdpData::FromServer { sender_id: 3, content: UdpPackage::Move(YMove { sequence: n, ..Default::default() }) }
+}
+
+fn numbers(delivered: Vec<(UdpChannel, UdpData)>) -> Vec<u32> {
+    delivered.into_iter().map(|(_, data)| match data {
+        UdpData::FromServer { content: UdpPackage::Move(ymove), .. } => ymove.sequence,
+        data => panic!("Expected a numbered package, got {data:?}"),
+    }).collect()
+}
+
+#[test]
+fn sequenced_drops_stale_messages() {
+    let mut receiver = ChannelReceiver::new();
+    let messages = [2, 1, 3, 3].map(|seq| UdpMessage::Sequenced { seq, data: numbered(seq as u32) });
+    assert_eq!(numbers(deliver(&mut receiver, messages.to_vec())), [2, 3]);
+}
+
+#[test]
+fn reliable_unordered_delivers_everything_once() {
+    let mut receiver = ChannelReceiver::new();
+    let messages = [2, 0, 1, 1, 2].map(|id| UdpMessage::Data { id, data: numbered(id as u32) });
+    assert_eq!(numbers(deliver(&mut receiver, messages.to_vec())), [2, 0, 1]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn reliable_ordered_holds_back_until_the_gap_is_filled() {
+    let mut receiver = ChannelReceiver::new();
+    let mut ordered = |seqs: &[u16]| {
+        let messages = seqs.iter().map(|seq| UdpMessage::Ordered { id: *seq, seq: *seq, data: numbered(*seq as u32) }).collect();
+        numbers(deliver(&mut receiver, messages))
+    };
+    assert!(ordered(&[1, 2]).is_empty());
+    assert_eq!(ordered(&[0]), [0, 1, 2]);
+    // A long burst behind a single missing message is held back as a whole
+    assert!(ordered(&(4..200).collect::<Vec<_>>()).is_empty());
+    assert_eq!(ordered(&[3]), (3..200).collect::<Vec<_>>());
+    // Only once the sender gave up on the missing message the channel goes on without it
+    assert!(ordered(&[201]).is_empty());
+    sleep(Duration::from_secs(60)).await;
+    assert_eq!(ordered(&[202]), [201, 202]);
+    assert!(ordered(&[200]).is_empty());
+}
+
+// Twenty messages that only miss their first fragment, which is returned for each of them
+async fn incomplete_messages(receiver: &mut ChannelReceiver, channel: UdpChannel) -> Vec<UdpMessage> {
+    let mut supervisor = SafeUdpSupervisor::with_fragment_size(8);
+    let mut sequences = ChannelSequences::default();
+    let mut firsts = vec![];
+    for n in 0..20 {
+        let mut fragments = supervisor.package(numbered(n), channel, &mut sequences);
+        firsts.push(fragments.remove(0));
+        assert!(deliver(receiver, fragments).is_empty());
+        sleep(Duration::from_millis(1)).await;
+    }
+    firsts
+}
+
+#[tokio::test(start_paused = true)]
+async fn only_unreliable_fragments_get_evicted() {
+    // The other fragments of reliable messages are acknowledged already, so none is given up on
+    let mut receiver = ChannelReceiver::new();
+    let firsts = incomplete_messages(&mut receiver, UdpChannel::ReliableUnordered).await;
+    assert_eq!(numbers(deliver(&mut receiver, firsts)), (0..20).collect::<Vec<_>>());
+
+    let mut receiver = ChannelReceiver::new();
+    let mut firsts = incomplete_messages(&mut receiver, UdpChannel::Sequenced).await;
+    let kept = firsts.split_off(4);
+    assert_eq!(numbers(deliver(&mut receiver, kept)), (4..20).collect::<Vec<_>>());
+    // The oldest ones made room for the newer ones
+    assert!(deliver(&mut receiver, firsts).is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn resends_back_off_until_unreachable() {
+    let mut supervisor = SafeUdpSupervisor::new();
+    let messages = supervisor.package(UdpData::FromClient(UdpPackage::Jump), UdpChannel::ReliableOrdered, &mut ChannelSequences::default());
+    supervisor.datagrams(messages, (0, 0));
+    let mut last = Instant::now();
+    let mut intervals = vec![];
+    loop {
+        let due = supervisor.next_resend().expect("The message is still pending");
+        sleep_until(due).await;
+        intervals.push((due - last).as_millis());
+        last = due;
+        match supervisor.resends() {
+            Resend::Messages(messages) => assert_eq!(messages.len(), 1),
+            Resend::Unreachable => break,
+        }
+    }
+    // Doubles from the initial timeout up to the maximum, the last one gives up
+    assert_eq!(intervals, [250, 500, 1000, 2000, 2000, 2000, 2000]);
+    assert_eq!(supervisor.resend_count(), 6);
+    assert!(supervisor.next_resend().is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn incomplete_fragments_time_out() {
+    let mut supervisor = SafeUdpSupervisor::with_fragment_size(8);
+    let mut fragments = fragmented(&mut supervisor, UdpChannel::Sequenced);
+    let mut receiver = ChannelReceiver::new();
+    let last = fragments.pop().unwrap();
+    assert!(deliver(&mut receiver, fragments).is_empty());
+    sleep(Duration::from_secs(60)).await;
+    // The rest of the message got dropped in the meantime
+    assert!(deliver(&mut receiver, vec![last]).is_empty());
 }
 
 const SCENE_STRING: &str = r#"(
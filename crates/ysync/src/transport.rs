@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap, future::Future, io, net::{IpAddr, SocketAddr}, sync::{Arc, Mutex}
+};
+
+use tokio::{
+    io::{duplex, AsyncRead, AsyncWrite, DuplexStream},
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+// Buffer size of each direction of an in-memory stream
+const MEMORY_STREAM_BUFFER: usize = 64 * 1024;
+
+/// How the client and the lobby reach each other, over the network or in-memory for tests
+pub trait Transport: Send + Sync + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+    type Listener: Listener<Stream = Self::Stream>;
+    type Datagram: DatagramSocket;
+
+    fn listen(&self, addr: SocketAddr) -> impl Future<Output = io::Result<Self::Listener>> + Send;
+    fn connect(&self, addr: SocketAddr) -> impl Future<Output = io::Result<Self::Stream>> + Send;
+    fn bind(&self, addr: SocketAddr) -> impl Future<Output = io::Result<Self::Datagram>> + Send;
+}
+
+/// Accepts the streams of connecting clients
+pub trait Listener: Send + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    fn accept(&mut self) -> impl Future<Output = io::Result<(Self::Stream, SocketAddr)>> + Send;
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+/// The socket the udp handlers of the client and server send and receive datagrams with
+pub trait DatagramSocket: Send + Sync + 'static {
+    fn send_to(&self, buf: &[u8], target: SocketAddr) -> impl Future<Output = io::Result<usize>> + Send;
+    fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send;
+}
+
+/// Tcp and udp over the real network, a browser only has WebSocket
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Network;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Transport for Network {
+    type Stream = TcpStream;
+    type Listener = TcpListener;
+    type Datagram = UdpSocket;
+
+    async fn listen(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        TcpListener::bind(addr).await
+    }
+    async fn connect(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        TcpStream::connect(addr).await
+    }
+    async fn bind(&self, addr: SocketAddr) -> io::Result<UdpSocket> {
+        UdpSocket::bind(addr).await
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept(&mut self) -> impl Future<Output = io::Result<(TcpStream, SocketAddr)>> + Send {
+        TcpListener::accept(self)
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpListener::local_addr(self)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DatagramSocket for UdpSocket {
+    fn send_to(&self, buf: &[u8], target: SocketAddr) -> impl Future<Output = io::Result<usize>> + Send {
+        UdpSocket::send_to(self, buf, target)
+    }
+    fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send {
+        UdpSocket::recv_from(self, buf)
+    }
+}
+
+#[derive(Default)]
+struct MemoryRoutes {
+    listeners: HashMap<SocketAddr, UnboundedSender<(DuplexStream, SocketAddr)>>,
+    sockets: HashMap<SocketAddr, UnboundedSender<(Vec<u8>, SocketAddr)>>,
+    next_port: u16,
+}
+
+/// A network that only exists in memory, so a lobby and its clients can run inside one test
+///
+/// Nothing is sent through the os, so tests don't collide over ports and work with
+/// `tokio::time::pause`. Datagrams to addresses nobody is bound to get dropped, like udp.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    routes: Arc<Mutex<MemoryRoutes>>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> MemoryNetwork {
+        MemoryNetwork::default()
+    }
+    /// A machine on this network, the lobby tells its clients apart by ip so every client needs
+    /// its own host
+    pub fn host(&self, ip: IpAddr) -> MemoryHost {
+        MemoryHost { ip, network: self.clone() }
+    }
+    // Unspecified ips become the host's ip, port 0 gets a free port assigned
+    fn local_addr(&self, ip: IpAddr, addr: SocketAddr, taken: impl Fn(&MemoryRoutes, SocketAddr) -> bool) -> io::Result<SocketAddr> {
+        let mut routes = self.routes.lock().unwrap();
+        let ip = if addr.ip().is_unspecified() { ip } else { addr.ip() };
+        if addr.port() != 0 {
+            let addr = SocketAddr::new(ip, addr.port());
+            return match taken(&routes, addr) {
+                true => Err(io::ErrorKind::AddrInUse.into()),
+                false => Ok(addr),
+            };
+        }
+        loop {
+            routes.next_port = routes.next_port.wrapping_add(1).max(49152);
+            let addr = SocketAddr::new(ip, routes.next_port);
+            if !taken(&routes, addr) {
+                return Ok(addr);
+            }
+        }
+    }
+}
+
+/// A single machine on a [`MemoryNetwork`]
+#[derive(Clone)]
+pub struct MemoryHost {
+    ip: IpAddr,
+    network: MemoryNetwork,
+}
+
+impl Transport for MemoryHost {
+    type Stream = DuplexStream;
+    type Listener = MemoryListener;
+    type Datagram = MemorySocket;
+
+    async fn listen(&self, addr: SocketAddr) -> io::Result<MemoryListener> {
+        let addr = self.network.local_addr(self.ip, addr, |routes, addr| routes.listeners.contains_key(&addr))?;
+        let (send, recv) = unbounded_channel();
+        self.network.routes.lock().unwrap().listeners.insert(addr, send);
+        Ok(MemoryListener { addr, network: self.network.clone(), incoming: recv })
+    }
+    async fn connect(&self, addr: SocketAddr) -> io::Result<DuplexStream> {
+        let local = self.network.local_addr(self.ip, SocketAddr::new(self.ip, 0), |_, _| false)?;
+        let (client, server) = duplex(MEMORY_STREAM_BUFFER);
+        let routes = self.network.routes.lock().unwrap();
+        match routes.listeners.get(&addr).map(|listener| listener.send((server, local))) {
+            Some(Ok(())) => Ok(client),
+            _ => Err(io::ErrorKind::ConnectionRefused.into()),
+        }
+    }
+    async fn bind(&self, addr: SocketAddr) -> io::Result<MemorySocket> {
+        let addr = self.network.local_addr(self.ip, addr, |routes, addr| routes.sockets.contains_key(&addr))?;
+        let (send, recv) = unbounded_channel();
+        self.network.routes.lock().unwrap().sockets.insert(addr, send);
+        Ok(MemorySocket { addr, network: self.network.clone(), incoming: tokio::sync::Mutex::new(recv) })
+    }
+}
+
+pub struct MemoryListener {
+    addr: SocketAddr,
+    network: MemoryNetwork,
+    incoming: UnboundedReceiver<(DuplexStream, SocketAddr)>,
+}
+
+impl Listener for MemoryListener {
+    type Stream = DuplexStream;
+
+    async fn accept(&mut self) -> io::Result<(DuplexStream, SocketAddr)> {
+        // The sender lives in the network as long as the listener does
+        self.incoming.recv().await.ok_or(io::ErrorKind::NotConnected.into())
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        self.network.routes.lock().unwrap().listeners.remove(&self.addr);
+    }
+}
+
+pub struct MemorySocket {
+    addr: SocketAddr,
+    network: MemoryNetwork,
+    incoming: tokio::sync::Mutex<UnboundedReceiver<(Vec<u8>, SocketAddr)>>,
+}
+
+impl DatagramSocket for MemorySocket {
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        if let Some(socket) = self.network.routes.lock().unwrap().sockets.get(&target) {
+            let _ = socket.send((buf.to_vec(), self.addr));
+        }
+        Ok(buf.len())
+    }
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let Some((datagram, from)) = self.incoming.lock().await.recv().await else {
+            return Err(io::ErrorKind::NotConnected.into());
+        };
+        // Like udp, whatever doesn't fit into the buffer is cut off
+        let n = datagram.len().min(buf.len());
+        buf[..n].copy_from_slice(&datagram[..n]);
+        Ok((n, from))
+    }
+}
+
+impl Drop for MemorySocket {
+    fn drop(&mut self) {
+        self.network.routes.lock().unwrap().sockets.remove(&self.addr);
+    }
+}
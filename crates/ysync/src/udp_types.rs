@@ -2,13 +2,68 @@ use bevy_math::{Quat, Vec3};
 use bevy_transform::components::Transform;
 use yserde_bytes::AsBytes;
 
-#[derive(AsBytes, Debug)]
-pub enum Udp {
+/// Largest datagram we send, small enough to not get fragmented on common links
+pub const MAX_DATAGRAM_SIZE: usize = 1200;
+
+/// Everything send over udp, a batch of messages with the acknowledgements piggybacked
+#[derive(AsBytes, Debug, Default)]
+pub struct UdpDatagram {
+    /// Counts the datagrams of a link, acknowledged instead of the single messages
+    pub seq: u16,
+    /// Newest datagram received from the other side
+    pub ack: u16,
+    /// Bit n set means datagram `ack - n - 1` got received as well
+    pub ack_bits: u32,
+    pub messages: Vec<UdpMessage>,
+}
+
+#[derive(AsBytes, Debug, Clone)]
+pub enum UdpMessage {
+    /// Reliable-unordered, resent until the datagram carrying it got acknowledged
     Data {
         id: u16,
         data: UdpData
     },
-    Response(u16)
+    /// Reliable-ordered, `seq` counts the ordered messages of a single link
+    Ordered {
+        id: u16,
+        seq: u16,
+        data: UdpData
+    },
+    /// Unreliable, messages older than the newest one of the same kind are dropped
+    Sequenced {
+        seq: u16,
+        data: UdpData
+    },
+    /// A piece of a message too large for a single datagram, the message is handled once all
+    /// `count` pieces of its `group` arrived
+    Fragment {
+        /// Set if the message is reliable, every piece is then acknowledged and resent on its own
+        id: Option<u16>,
+        /// Counts the fragmented messages of a single link
+        group: u16,
+        index: u8,
+        count: u8,
+        #[u16]
+        payload: Vec<u8>
+    },
+}
+
+impl Default for UdpMessage {
+    fn default() -> Self {
+        UdpMessage::Sequenced { seq: 0, data: UdpData::default() }
+    }
+}
+
+/// How a [`UdpPackage`] is delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpChannel {
+    /// Never resent, stale packages are dropped; for state that is superseded anyway
+    Sequenced,
+    /// Resent until acknowledged, delivered as soon as it arrives
+    ReliableUnordered,
+    /// Resent until acknowledged, delivered in the order it was sent
+    ReliableOrdered,
 }
 
 #[derive(AsBytes, Debug, Clone)]
@@ -28,14 +83,28 @@ impl Default for UdpData {
 
 #[derive(AsBytes, Debug, Default, Clone)]
 pub enum UdpPackage {
-    Move(YTranslation),
+    /// Walked since the previous move, reliable as an authoritative host has to apply every one
+    Move(YMove),
     Attack(YPosition),
-    Rotate(YRotation),
+    Rotate(YRotate),
     Jump,
+    /// State of the game, send by an authoritative host that overrules the players' own view
+    Snapshot(YSnapshot),
     #[default]
     Heartbeat
 }
 
+impl UdpPackage {
+    /// The channel this package is sent on unless another one is chosen at send time
+    pub fn channel(&self) -> UdpChannel {
+        match self {
+            UdpPackage::Rotate(_) | UdpPackage::Snapshot(_) => UdpChannel::Sequenced,
+            UdpPackage::Move(_) | UdpPackage::Attack(_) | UdpPackage::Jump => UdpChannel::ReliableOrdered,
+            UdpPackage::Heartbeat => UdpChannel::ReliableUnordered,
+        }
+    }
+}
+
 #[derive(AsBytes, Debug, Default, Clone)]
 pub struct YTranslation {
     x: f32,
@@ -55,6 +124,25 @@ impl From<YTranslation> for Vec3 {
     }
 }
 
+/// A player's movement since its previous one, numbered so an authoritative host can acknowledge
+/// the last one it applied
+#[derive(AsBytes, Debug, Default, Clone)]
+pub struct YMove {
+    pub sequence: u32,
+    // Milliseconds since the sender started, so the peers space the positions as they were sent
+    pub time: u32,
+    pub movement: YTranslation,
+    // Where the player ended up, for the peers that just follow it
+    pub position: YTranslation,
+}
+
+#[derive(AsBytes, Debug, Default, Clone)]
+pub struct YRotate {
+    // Milliseconds since the sender started
+    pub time: u32,
+    pub rotation: YRotation,
+}
+
 #[derive(AsBytes, Debug, Default, Clone)]
 pub struct YRotation {
     x: f32,
@@ -100,3 +188,27 @@ impl From<YPosition> for Transform {
         }
     }
 }
+
+#[derive(AsBytes, Debug, Default, Clone)]
+pub struct YSnapshot {
+    // Milliseconds since the host started
+    pub time: u32,
+    pub players: Vec<YPlayerState>,
+    // Npcs missing from the snapshot have been killed
+    pub npcs: Vec<YNpcState>,
+}
+
+#[derive(AsBytes, Debug, Default, Clone)]
+pub struct YNpcState {
+    pub id: u16,
+    pub health: u32,
+}
+
+#[derive(AsBytes, Debug, Default, Clone)]
+pub struct YPlayerState {
+    pub id: u16,
+    pub translation: YTranslation,
+    pub health: u32,
+    // Sequence of the player's last applied move
+    pub last_move: u32,
+}
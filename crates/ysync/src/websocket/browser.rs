@@ -0,0 +1,132 @@
+use std::{io, net::{Ipv4Addr, SocketAddr, SocketAddrV4}};
+
+use js_sys::{ArrayBuffer, Uint8Array};
+use tokio::{
+    io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream},
+    select,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+use tracing::{debug, warn};
+use wasm_bindgen::{closure::Closure, JsCast};
+use web_sys::{BinaryType, MessageEvent, WebSocket};
+
+use crate::rt::spawn;
+
+use super::{Datagram, TunnelSocket, DATAGRAM_FRAME, STREAM_BUFFER, STREAM_FRAME};
+
+/// Where the datagrams of the lobby come from, a browser doesn't tell the address of its peer
+pub(crate) const LOBBY_ADDR: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+
+// What the callbacks of the browser's WebSocket hand to the pump
+enum Event {
+    Open,
+    Message(Vec<u8>),
+    Closed,
+}
+
+// The browser's WebSocket together with its callbacks, which have to live as long as it's open
+struct Socket {
+    ws: WebSocket,
+    _on_open: Closure<dyn FnMut()>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut()>,
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        // The callbacks are freed with the socket, the browser must not call them anymore
+        self.ws.set_onopen(None);
+        self.ws.set_onmessage(None);
+        self.ws.set_onerror(None);
+        self.ws.set_onclose(None);
+        let _ = self.ws.close();
+    }
+}
+
+/// Opens a WebSocket to the lobby at `url`, returning the stream the tcp packages are framed in
+/// and the socket of the tunneled udp traffic
+pub(crate) async fn connect(url: &str) -> io::Result<(DuplexStream, TunnelSocket)> {
+    let ws = WebSocket::new(url).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{e:?}")))?;
+    ws.set_binary_type(BinaryType::Arraybuffer);
+    let (events_send, mut events) = unbounded_channel();
+    let on_open = {
+        let events = events_send.clone();
+        Closure::<dyn FnMut()>::new(move || {
+            let _ = events.send(Event::Open);
+        })
+    };
+    let on_message = {
+        let events = events_send.clone();
+        Closure::<dyn FnMut(MessageEvent)>::new(move |msg: MessageEvent| match msg.data().dyn_into::<ArrayBuffer>() {
+            Ok(data) => {
+                let _ = events.send(Event::Message(Uint8Array::new(&data).to_vec()));
+            }
+            Err(_) => warn!("Got a WebSocket message that isn't binary"),
+        })
+    };
+    // An error always closes the WebSocket, so both end the connection
+    let on_close = Closure::<dyn FnMut()>::new(move || {
+        let _ = events_send.send(Event::Closed);
+    });
+    ws.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+    ws.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    ws.set_onerror(Some(on_close.as_ref().unchecked_ref()));
+    ws.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+    let socket = Socket { ws, _on_open: on_open, _on_message: on_message, _on_close: on_close };
+
+    let Some(Event::Open) = events.recv().await else {
+        return Err(io::ErrorKind::ConnectionRefused.into());
+    };
+    let (stream, tunnel_end) = duplex(STREAM_BUFFER);
+    let (datagrams_out, datagrams) = unbounded_channel();
+    let (inbox_send, inbox) = unbounded_channel();
+    spawn(pump(socket, events, tunnel_end, datagrams, inbox_send));
+    Ok((stream, TunnelSocket::new(datagrams_out, inbox)))
+}
+
+// Runs until either the WebSocket or the stream closes, dropping the stream so the tcp handler
+// notices the lost connection
+async fn pump(
+    socket: Socket,
+    mut events: UnboundedReceiver<Event>,
+    mut stream: DuplexStream,
+    mut datagrams_out: UnboundedReceiver<Vec<u8>>,
+    datagrams_in: UnboundedSender<Datagram>,
+) {
+    let mut buf = vec![0; 4096];
+    loop {
+        select! {
+            event = events.recv() => match event {
+                Some(Event::Message(data)) => match data.split_first() {
+                    Some((&STREAM_FRAME, bytes)) => {
+                        if stream.write_all(bytes).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some((&DATAGRAM_FRAME, datagram)) => {
+                        let _ = datagrams_in.send((datagram.to_vec(), LOBBY_ADDR));
+                    }
+                    _ => warn!("Got a WebSocket message of unknown kind"),
+                },
+                Some(Event::Closed) | None => break,
+                Some(Event::Open) => {}
+            },
+            n = stream.read(&mut buf) => {
+                let Ok(n @ 1..) = n else {
+                    break;
+                };
+                let frame = [&[STREAM_FRAME], &buf[..n]].concat();
+                if socket.ws.send_with_u8_array(&frame).is_err() {
+                    break;
+                }
+            }
+            Some(datagram) = datagrams_out.recv() => {
+                let frame = [&[DATAGRAM_FRAME], datagram.as_slice()].concat();
+                if socket.ws.send_with_u8_array(&frame).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    debug!("WebSocket closed");
+}
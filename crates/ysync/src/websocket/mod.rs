@@ -0,0 +1,52 @@
+use std::{io, net::SocketAddr};
+
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use crate::transport::DatagramSocket;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native;
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::{Tunneled, WebSocketClient, WebSocketListener};
+#[cfg(target_arch = "wasm32")]
+mod browser;
+#[cfg(target_arch = "wasm32")]
+pub(crate) use browser::{connect, LOBBY_ADDR};
+
+// Every binary message starts with one of these, the rest is either a piece of the byte stream
+// the tcp packages are framed in or a whole udp datagram
+const STREAM_FRAME: u8 = 0;
+const DATAGRAM_FRAME: u8 = 1;
+
+// Buffer size of each direction of the stream handed to the tcp handlers
+const STREAM_BUFFER: usize = 64 * 1024;
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+/// The client's end of the udp traffic tunneled through a WebSocket
+pub struct TunnelSocket {
+    out: UnboundedSender<Vec<u8>>,
+    inbox: tokio::sync::Mutex<UnboundedReceiver<Datagram>>,
+}
+
+impl TunnelSocket {
+    fn new(out: UnboundedSender<Vec<u8>>, inbox: UnboundedReceiver<Datagram>) -> TunnelSocket {
+        TunnelSocket { out, inbox: tokio::sync::Mutex::new(inbox) }
+    }
+}
+
+impl DatagramSocket for TunnelSocket {
+    async fn send_to(&self, buf: &[u8], _target: SocketAddr) -> io::Result<usize> {
+        self.out.send(buf.to_vec()).map_err(|_| io::Error::from(io::ErrorKind::NotConnected))?;
+        Ok(buf.len())
+    }
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        // Once the WebSocket closed nothing arrives anymore, just like on a udp socket
+        let Some((datagram, from)) = self.inbox.lock().await.recv().await else {
+            return std::future::pending().await;
+        };
+        let n = datagram.len().min(buf.len());
+        buf[..n].copy_from_slice(&datagram[..n]);
+        Ok((n, from))
+    }
+}
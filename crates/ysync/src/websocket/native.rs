@@ -0,0 +1,236 @@
+use std::{collections::HashMap, io, net::SocketAddr, sync::{Arc, Mutex}, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    io::{duplex, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream},
+    net::{TcpListener, TcpStream},
+    select,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    time::timeout,
+};
+use tokio_tungstenite::{accept_async, client_async, tungstenite::Message, WebSocketStream};
+use tracing::{debug, warn};
+
+use crate::transport::{DatagramSocket, Listener, Transport};
+
+use super::{Datagram, TunnelSocket, DATAGRAM_FRAME, STREAM_BUFFER, STREAM_FRAME};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Runs until either the WebSocket or the stream closes, dropping the stream so the tcp handler
+// notices the lost connection
+async fn pump<S: AsyncRead + AsyncWrite + Unpin>(
+    ws: WebSocketStream<S>,
+    peer: SocketAddr,
+    mut stream: DuplexStream,
+    mut datagrams_out: UnboundedReceiver<Vec<u8>>,
+    datagrams_in: UnboundedSender<Datagram>,
+) {
+    let (mut sink, mut source) = ws.split();
+    let mut buf = vec![0; 4096];
+    loop {
+        select! {
+            msg = source.next() => match msg {
+                Some(Ok(Message::Binary(data))) => match data.split_first() {
+                    Some((&STREAM_FRAME, bytes)) => {
+                        if stream.write_all(bytes).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some((&DATAGRAM_FRAME, datagram)) => {
+                        let _ = datagrams_in.send((datagram.to_vec(), peer));
+                    }
+                    _ => warn!(%peer, "Got a WebSocket message of unknown kind"),
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Pings are answered by tungstenite
+                Some(Ok(_)) => {}
+            },
+            n = stream.read(&mut buf) => {
+                let Ok(n @ 1..) = n else {
+                    break;
+                };
+                let frame = [&[STREAM_FRAME], &buf[..n]].concat();
+                if sink.send(Message::Binary(frame)).await.is_err() {
+                    break;
+                }
+            }
+            Some(datagram) = datagrams_out.recv() => {
+                let frame = [&[DATAGRAM_FRAME], datagram.as_slice()].concat();
+                if sink.send(Message::Binary(frame)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    debug!(%peer, "WebSocket closed");
+    let _ = sink.close().await;
+}
+
+// Where datagrams to peers connected over WebSocket have to go
+#[derive(Clone)]
+struct Tunnels {
+    routes: Arc<Mutex<HashMap<SocketAddr, UnboundedSender<Vec<u8>>>>>,
+    inbox: UnboundedSender<Datagram>,
+}
+
+impl Tunnels {
+    fn open<S>(&self, ws: WebSocketStream<S>, peer: SocketAddr) -> DuplexStream
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (stream, tunnel_end) = duplex(STREAM_BUFFER);
+        let (datagrams_out, datagrams) = unbounded_channel();
+        self.routes.lock().unwrap().insert(peer, datagrams_out);
+        let tunnels = self.clone();
+        tokio::spawn(async move {
+            pump(ws, peer, tunnel_end, datagrams, tunnels.inbox.clone()).await;
+            tunnels.routes.lock().unwrap().remove(&peer);
+        });
+        stream
+    }
+}
+
+/// Accepts lobby connections over WebSocket, next to the ones over tcp
+///
+/// The udp traffic of these clients is tunneled through the same WebSocket, so the udp handler
+/// has to use the socket returned by [`WebSocketListener::datagrams`].
+pub struct WebSocketListener {
+    local_addr: SocketAddr,
+    // Connections that finished their handshake
+    accepted: UnboundedReceiver<io::Result<(DuplexStream, SocketAddr)>>,
+    routes: Arc<Mutex<HashMap<SocketAddr, UnboundedSender<Vec<u8>>>>>,
+    inbox: Option<UnboundedReceiver<Datagram>>,
+}
+
+impl WebSocketListener {
+    pub async fn bind(addr: SocketAddr) -> io::Result<WebSocketListener> {
+        let (inbox_send, inbox) = unbounded_channel();
+        let (accepted_send, accepted) = unbounded_channel();
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let tunnels = Tunnels { routes: Arc::default(), inbox: inbox_send };
+        let routes = tunnels.routes.clone();
+        tokio::spawn(accept_handshakes(listener, tunnels, accepted_send));
+        Ok(WebSocketListener {
+            local_addr,
+            accepted,
+            routes,
+            inbox: Some(inbox),
+        })
+    }
+    /// Wraps the lobby's udp socket, so it also reaches the clients connected over WebSocket
+    ///
+    /// Panics if called twice.
+    pub fn datagrams<S: DatagramSocket>(&mut self, udp: S) -> Tunneled<S> {
+        Tunneled {
+            inner: udp,
+            routes: self.routes.clone(),
+            inbox: tokio::sync::Mutex::new(self.inbox.take().expect("the datagrams of a WebSocketListener are taken once")),
+        }
+    }
+}
+
+impl Listener for WebSocketListener {
+    type Stream = DuplexStream;
+
+    async fn accept(&mut self) -> io::Result<(DuplexStream, SocketAddr)> {
+        self.accepted.recv().await.unwrap_or(Err(io::ErrorKind::NotConnected.into()))
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+// Every handshake runs on its own, so a slow client doesn't hold up the others
+async fn accept_handshakes(
+    listener: TcpListener,
+    tunnels: Tunnels,
+    accepted: UnboundedSender<io::Result<(DuplexStream, SocketAddr)>>,
+) {
+    loop {
+        let (tcp, addr) = select! {
+            result = listener.accept() => match result {
+                Ok(connection) => connection,
+                Err(e) => {
+                    let _ = accepted.send(Err(e));
+                    return;
+                }
+            },
+            // The WebSocketListener got dropped
+            _ = accepted.closed() => return,
+        };
+        let (tunnels, accepted) = (tunnels.clone(), accepted.clone());
+        tokio::spawn(async move {
+            match timeout(HANDSHAKE_TIMEOUT, accept_async(tcp)).await {
+                Ok(Ok(ws)) => {
+                    let _ = accepted.send(Ok((tunnels.open(ws, addr), addr)));
+                }
+                Ok(Err(e)) => warn!(%addr, "WebSocket handshake failed, e: {e}"),
+                Err(_) => warn!(%addr, "WebSocket handshake timed out"),
+            }
+        });
+    }
+}
+
+/// A datagram socket that also reaches the peers connected over WebSocket
+pub struct Tunneled<S> {
+    inner: S,
+    routes: Arc<Mutex<HashMap<SocketAddr, UnboundedSender<Vec<u8>>>>>,
+    inbox: tokio::sync::Mutex<UnboundedReceiver<Datagram>>,
+}
+
+impl<S: DatagramSocket> DatagramSocket for Tunneled<S> {
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let tunnel = self.routes.lock().unwrap().get(&target).cloned();
+        match tunnel {
+            Some(tunnel) => {
+                let _ = tunnel.send(buf.to_vec());
+                Ok(buf.len())
+            }
+            None => self.inner.send_to(buf, target).await,
+        }
+    }
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut inbox = self.inbox.lock().await;
+        let (datagram, from) = select! {
+            result = self.inner.recv_from(buf) => return result,
+            Some(datagram) = inbox.recv() => datagram,
+        };
+        let n = datagram.len().min(buf.len());
+        buf[..n].copy_from_slice(&datagram[..n]);
+        Ok((n, from))
+    }
+}
+
+/// Connects to a lobby over WebSocket instead of tcp and udp
+///
+/// The udp socket is tunneled through the WebSocket, so [`Transport::bind`] only works after
+/// [`Transport::connect`] and only reaches the lobby.
+#[derive(Default)]
+pub struct WebSocketClient {
+    tunnel: Mutex<Option<TunnelSocket>>,
+}
+
+impl Transport for WebSocketClient {
+    type Stream = DuplexStream;
+    type Listener = WebSocketListener;
+    type Datagram = TunnelSocket;
+
+    async fn listen(&self, addr: SocketAddr) -> io::Result<WebSocketListener> {
+        WebSocketListener::bind(addr).await
+    }
+    async fn connect(&self, addr: SocketAddr) -> io::Result<DuplexStream> {
+        let tcp = TcpStream::connect(addr).await?;
+        let (ws, _) = client_async(format!("ws://{addr}/"), tcp).await.map_err(io::Error::other)?;
+        let (stream, tunnel_end) = duplex(STREAM_BUFFER);
+        let (datagrams_out, datagrams) = unbounded_channel();
+        let (inbox_send, inbox) = unbounded_channel();
+        tokio::spawn(pump(ws, addr, tunnel_end, datagrams, inbox_send));
+        *self.tunnel.lock().unwrap() = Some(TunnelSocket::new(datagrams_out, inbox));
+        Ok(stream)
+    }
+    async fn bind(&self, _addr: SocketAddr) -> io::Result<TunnelSocket> {
+        self.tunnel.lock().unwrap().take().ok_or(io::ErrorKind::NotConnected.into())
+    }
+}
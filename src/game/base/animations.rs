@@ -33,7 +33,7 @@ pub fn animate(
                     .play(&mut player, animation.get_node(&animations), Duration::from_millis(250))
                     .repeat();
             } else {
-                println!("Couldn't find the right AnimationPlayer!");
+                warn!("Couldn't find the right AnimationPlayer!");
             }
         }
     }
@@ -1,7 +1,14 @@
+use std::collections::VecDeque;
+
 use bevy::prelude::*;
 
 use super::resources::Animations;
 
+// How long a remote player keeps moving on its own once its positions are late
+const MAX_EXTRAPOLATION: f32 = 0.1;
+// A clock this far off from the previous one belongs to another sender, e.g. after a host migration
+const CLOCK_JUMP: f32 = 1.;
+
 // This Component indicates that an Entity should be despawned with it's children when leaving
 // InGame state
 #[derive(Component)]
@@ -28,7 +35,10 @@ pub struct Player {
 
 #[derive(Component, Reflect, Default)]
 #[reflect(Component)]
-pub struct Npc;
+pub struct Npc {
+    // Names the npc in the snapshots of an authoritative host
+    pub id: u16,
+}
 
 #[derive(Component)]
 pub struct NormalCamera;
@@ -135,3 +145,108 @@ pub struct GlobalUiPosition {
 pub struct Follow {
     pub entity: Entity,
 }
+
+// The positions and rotations of a remote player by the time they were sent at, it is shown a bit
+// in the past so there is always a newer value to move towards
+#[derive(Component, Default)]
+pub struct InterpolationBuffer {
+    positions: Samples<Vec3>,
+    rotations: Samples<Quat>,
+}
+
+impl InterpolationBuffer {
+    // `sent` is the sender's time, `received` the local time it arrived at
+    pub fn push_position(&mut self, sent: f32, received: f32, position: Vec3) {
+        self.positions.push(sent, received, position);
+    }
+    pub fn push_rotation(&mut self, sent: f32, received: f32, rotation: Quat) {
+        self.rotations.push(sent, received, rotation);
+    }
+    // Where the player was `delay` seconds before `now`, None once the physics take over again
+    pub fn position_at(&mut self, now: f32, delay: f32) -> Option<Vec3> {
+        let time = self.positions.sender_time(now, delay)?;
+        let samples = &mut self.positions.values;
+        drop_passed(samples, time);
+        let &(from_time, from) = samples.front()?;
+        let Some(&(to_time, to)) = samples.get(1) else {
+            if time > from_time + MAX_EXTRAPOLATION {
+                samples.clear();
+            }
+            return Some(from);
+        };
+        if time <= from_time {
+            return Some(from);
+        }
+        if time < to_time {
+            return Some(from.lerp(to, (time - from_time) / (to_time - from_time)));
+        }
+        // The next position is late, keep going the same way for a bit
+        let late = time - to_time;
+        if late > MAX_EXTRAPOLATION {
+            samples.clear();
+            return Some(to);
+        }
+        Some(to + (to - from) / (to_time - from_time) * late)
+    }
+    pub fn rotation_at(&mut self, now: f32, delay: f32) -> Option<Quat> {
+        let time = self.rotations.sender_time(now, delay)?;
+        let samples = &mut self.rotations.values;
+        drop_passed(samples, time);
+        let &(from_time, from) = samples.front()?;
+        match samples.get(1) {
+            Some(&(to_time, to)) if time < to_time => {
+                Some(from.slerp(to, ((time - from_time) / (to_time - from_time)).max(0.)))
+            }
+            last => {
+                let (last_time, last) = last.copied().unwrap_or((from_time, from));
+                if time > last_time + MAX_EXTRAPOLATION {
+                    samples.clear();
+                }
+                Some(last)
+            }
+        }
+    }
+}
+
+// Values by the sender's time they belong to
+struct Samples<T> {
+    values: VecDeque<(f32, T)>,
+    // Local time minus the sender's time of the quickest value so far, slower ones only add delay
+    offset: Option<f32>,
+}
+
+impl<T> Default for Samples<T> {
+    fn default() -> Self {
+        Samples { values: VecDeque::new(), offset: None }
+    }
+}
+
+impl<T> Samples<T> {
+    fn push(&mut self, sent: f32, received: f32, value: T) {
+        let offset = received - sent;
+        match self.offset {
+            Some(last) if (offset - last).abs() > CLOCK_JUMP => {
+                self.values.clear();
+                self.offset = Some(offset);
+            }
+            Some(last) if offset >= last => {}
+            _ => self.offset = Some(offset),
+        }
+        // Values older than the newest one only arrive with another sender's clock
+        if self.values.back().is_some_and(|(last, _)| *last >= sent) {
+            self.values.pop_back();
+        }
+        self.values.push_back((sent, value));
+    }
+    // The sender's time that is shown at `now`
+    fn sender_time(&self, now: f32, delay: f32) -> Option<f32> {
+        self.offset.map(|offset| now - offset - delay)
+    }
+}
+
+// Drop the samples that are too old to interpolate from, one is kept before the time
+fn drop_passed<T>(samples: &mut VecDeque<(f32, T)>, time: f32) {
+    while samples.len() > 2 && samples[1].0 <= time {
+        samples.pop_front();
+    }
+}
@@ -2,7 +2,7 @@ use std::time::Instant;
 
 use bevy::prelude::*;
 
-use crate::{commands::{GameCommand, SettingToggle}, AppState};
+use crate::{commands::{GameCommand, SettingToggle}, game::online::resource::{PredictedMoves, RemoteAuthority}, AppState};
 
 use super::{components::{Follow, GameComponent, GameComponentParent, GlobalUiPosition}, resources::{GameAge, TimeInGame}};
 
@@ -64,7 +64,7 @@ pub fn despawn_all_entities(
     parents: Query<Entity, With<GameComponentParent>>,
     entities: Query<Entity, (With<GameComponent>, Without<GameComponentParent>)>,
 ) {
-    println!("full despawn");
+    debug!("full despawn");
     for entity in parents.iter() {
         commands.entity(entity).despawn_recursive();
     }
@@ -73,6 +73,8 @@ pub fn despawn_all_entities(
     }
     commands.remove_resource::<TimeInGame>();
     commands.remove_resource::<GameAge>();
+    commands.remove_resource::<RemoteAuthority>();
+    commands.insert_resource(PredictedMoves::default());
 }
 
 pub fn return_to_menu(
@@ -5,7 +5,7 @@ use camera::CameraPlugin;
 use cursor::CursorPlugin;
 use light::setup_light;
 use misc_systems::{advance_time, compute_screen_positions, despawn_all_entities, follow_for_node, insert_game_age, insert_in_game_time, return_to_menu, toggle_debug};
-use npcs::{insert_npc_components, spawn_npc};
+use npcs::{insert_npc_bodies, insert_npc_visuals, spawn_npc};
 use players::PlayerPlugin;
 use projectiles::{bullet_hits_attackable, move_bullets};
 use resources::{GameAge, PlayerId, PlayerName};
@@ -27,21 +27,17 @@ pub mod projectiles;
 mod scene_setup;
 pub mod players;
 
-pub struct GameBasePlugin;
+pub struct GameBasePlugin {
+    pub headless: bool,
+}
 
 impl Plugin for GameBasePlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_plugins((
-                CameraPlugin,
-                AtmospherePlugin,
-                PlayerPlugin,
-                CursorPlugin
-            ))
+            .add_plugins(PlayerPlugin { headless: self.headless })
             .insert_resource(PlayerName("Jon".to_string()))
             .insert_resource(PlayerId(0))
             .add_systems(OnEnter(AppState::InGame), (
-                setup_light,
                 spawn_floor,
                 spawn_npc.run_if(not(in_state(OnlineState::Client))),
                 insert_in_game_time,
@@ -51,15 +47,32 @@ impl Plugin for GameBasePlugin {
                 advance_time.run_if(resource_exists::<GameAge>),
                 move_bullets,
                 bullet_hits_attackable,
+                insert_npc_bodies,
+            ).run_if(in_state(AppState::InGame)))
+            .add_systems(OnExit(AppState::InGame), despawn_all_entities);
+        if self.headless {
+            // Meshes and materials are still created, they are just never rendered
+            app
+                .init_asset::<Mesh>()
+                .init_asset::<StandardMaterial>();
+            return;
+        }
+        app
+            .add_plugins((
+                CameraPlugin,
+                AtmospherePlugin,
+                CursorPlugin
+            ))
+            .add_systems(OnEnter(AppState::InGame), setup_light)
+            .add_systems(Update, (
                 setup_animation.before(animate_targets),
                 animate.before(animate_targets),
                 toggle_debug,
-                insert_npc_components,
+                insert_npc_visuals,
                 compute_screen_positions,
                 follow_for_node,
             ).run_if(in_state(AppState::InGame)))
             .add_systems(Update, return_to_menu
-                .run_if(not(in_state(ChatState::Open))).run_if(in_state(AppState::InGame)).run_if(in_state(OnlineState::None)))
-            .add_systems(OnExit(AppState::InGame), despawn_all_entities);
+                .run_if(not(in_state(ChatState::Open))).run_if(in_state(AppState::InGame)).run_if(in_state(OnlineState::None)));
     }
 }
@@ -10,7 +10,7 @@ pub fn spawn_npc(
         Health {
             value: 5
         },
-        Npc,
+        Npc { id: 1 },
         VisibilityBundle {
             visibility: Visibility::Visible,
             ..default()
@@ -19,7 +19,26 @@ pub fn spawn_npc(
     ));
 }
 
-pub fn insert_npc_components(
+// Physics of the npcs, needed with and without rendering
+pub fn insert_npc_bodies(
+    mut commands: Commands,
+    npc_query: Query<Entity, Added<Npc>>,
+) {
+    for npc in &npc_query {
+        commands.entity(npc).insert((
+            RigidBody::Dynamic {},
+            Collider::cylinder(1., 0.25),
+            GravityScale(9.81),
+            AdditionalMassProperties::Mass(10.),
+            Velocity::zero(),
+            CollisionGroups::new(Group::GROUP_3, Group::GROUP_2),
+            (LockedAxes::ROTATION_LOCKED_X | LockedAxes::ROTATION_LOCKED_Z),
+            GameComponent {},
+        ));
+    }
+}
+
+pub fn insert_npc_visuals(
     mut commands: Commands,
     asset: Res<AssetServer>,
     npc_query: Query<(Entity, &Transform), Added<Npc>>,
@@ -49,14 +68,6 @@ pub fn insert_npc_components(
         commands.entity(npc).insert((
             enemy_mesh,
             AnimationState::Idle,
-            RigidBody::Dynamic {},
-            Collider::cylinder(1., 0.25),
-            GravityScale(9.81),
-            AdditionalMassProperties::Mass(10.),
-            Velocity::zero(),
-            CollisionGroups::new(Group::GROUP_3, Group::GROUP_2),
-            (LockedAxes::ROTATION_LOCKED_X | LockedAxes::ROTATION_LOCKED_Z),
-            GameComponent {},
             GlobalUiPosition {
                 pos: Vec2::ZERO,
                 node_entity
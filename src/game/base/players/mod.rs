@@ -10,21 +10,31 @@ use super::components::{AnimationState, GameComponent};
 
 pub mod player_ctrl;
 
-pub struct PlayerPlugin;
+pub struct PlayerPlugin {
+    // Without rendering there is no main character, only the players of the other peers
+    pub headless: bool,
+}
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app
             .insert_resource(PlayerName("Jon".to_string()))
             .insert_resource(PlayerId(0))
+            .add_systems(Update, (
+                respawn_players,
+                insert_player_bodies,
+            ).run_if(in_state(AppState::InGame)));
+        if self.headless {
+            return;
+        }
+        app
             .add_systems(OnEnter(AppState::InGame), spawn_main_character)
             .add_systems(Update, (
                 rotate_eagle_player.run_if(in_state(CameraState::Eagle)),
                 rotate_normal_player.run_if(in_state(CameraState::Normal)),
                 move_player.run_if(not(in_state(ChatState::Open))),
-                respawn_players,
                 player_attack,
-                insert_player_components,
+                insert_player_visuals,
             ).run_if(in_state(AppState::InGame)));
     }
 }
@@ -95,18 +105,39 @@ pub fn spawn_player(
 
 pub fn despawn_players(
     mut commands: Commands,
-    player_query: Query<(Entity, &Player, &GlobalUiPosition)>,
+    player_query: Query<(Entity, &Player, Option<&GlobalUiPosition>)>,
     mut event_reader: EventReader<DespawnPlayer>,
 ) {
     for event in event_reader.read().into_iter() {
         player_query.iter().find(|(_, p, _)| p.id == event.0).map(|(entity, _, node)| {
-            commands.entity(node.node_entity).despawn_recursive();
+            if let Some(node) = node {
+                commands.entity(node.node_entity).despawn_recursive();
+            }
             commands.entity(entity).despawn_recursive();
         });
     }
 }
 
-pub fn insert_player_components(
+// Physics of the players, needed with and without rendering
+pub fn insert_player_bodies(
+    mut commands: Commands,
+    player_query: Query<Entity, Added<Player>>,
+) {
+    for player_entity in &player_query {
+        commands.entity(player_entity).insert((
+            RigidBody::Dynamic,
+            Collider::cylinder(1., 0.25),
+            GravityScale(16.),
+            AdditionalMassProperties::Mass(1.),
+            Velocity::zero(),
+            CollisionGroups::new(Group::GROUP_1, Group::GROUP_2),
+            (LockedAxes::ROTATION_LOCKED_X | LockedAxes::ROTATION_LOCKED_Z),
+            GameComponent,
+        ));
+    }
+}
+
+pub fn insert_player_visuals(
     mut commands: Commands,
     asset: Res<AssetServer>,
     player_query: Query<(Entity, &Player, &Transform), Added<Player>>,
@@ -144,14 +175,6 @@ pub fn insert_player_components(
                 ..default()
             },
             AnimationState::Idle,
-            RigidBody::Dynamic,
-            Collider::cylinder(1., 0.25),
-            GravityScale(16.),
-            AdditionalMassProperties::Mass(1.),
-            Velocity::zero(),
-            CollisionGroups::new(Group::GROUP_1, Group::GROUP_2),
-            (LockedAxes::ROTATION_LOCKED_X | LockedAxes::ROTATION_LOCKED_Z),
-            GameComponent,
             GlobalUiPosition {
                 pos: Vec2::ZERO,
                 node_entity
@@ -1,7 +1,7 @@
 use bevy::{color::palettes::css::BLUE, input::mouse::MouseMotion, prelude::*};
 use bevy_rapier3d::prelude::Velocity;
 
-use crate::{game::{base::{camera::CameraState, components::{AnimationState, WalkDirection}}, online::{events::{ShareAttack, ShareJump, ShareMovement, ShareRotation}, resource::{ShareMovementTimer, ShareRotationTimer}}}, ui::chat::ChatState};
+use crate::{game::{base::{camera::CameraState, components::{AnimationState, WalkDirection}}, online::{events::{ShareAttack, ShareJump, ShareMovement, ShareRotation}, resource::{PredictedMoves, ShareMovementTimer, ShareRotationTimer}}}, ui::chat::ChatState};
 
 use crate::game::base::components::{Bullet, GameComponentParent, MainCharacter, Player};
 
@@ -66,6 +66,7 @@ pub fn move_player(
     time: Res<Time>,
     mut commands: Commands,
     mut share_timer: ResMut<ShareMovementTimer>,
+    mut moves: ResMut<PredictedMoves>,
     mut share_movement: EventWriter<ShareMovement>,
     mut share_jump: EventWriter<ShareJump>,
 ) {
@@ -97,12 +98,14 @@ pub fn move_player(
                 direction.y = 0.;
                 let movement = direction.normalize_or_zero() * player.base_velocity * speed_multiplier * time.delta_seconds();
                 player_pos.translation += movement;
-                if share_timer.0.finished() {
-                    share_movement.send(ShareMovement(player_pos.translation));
-                    share_timer.0.reset();
-                }
+                moves.unshared += movement;
             }
         }
+        // Also share the rest of the movement once the player stopped
+        if share_timer.0.finished() && moves.unshared != Vec3::ZERO {
+            share_movement.send(ShareMovement(player_pos.translation));
+            share_timer.0.reset();
+        }
         if input.just_pressed(KeyCode::Space) {
             if let Ok(mut player_velocity) = player_velocity.get_single_mut() {
                 if player_pos.translation.y <= 1. && player_pos.translation.y >= 0. {
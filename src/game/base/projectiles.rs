@@ -1,6 +1,6 @@
 use bevy::{color::palettes::css::BLUE, prelude::*};
 
-use crate::game::online::events::PlayerAttack;
+use crate::game::online::{events::PlayerAttack, resource::RemoteAuthority};
 
 use super::components::{Bullet, GameComponentParent, GlobalUiPosition, Health, Player};
 
@@ -10,22 +10,24 @@ pub fn spawn_bullets(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    let event = attack_event.read().next().expect("No attack event huh");
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Sphere::new(0.1).mesh()),
-            material: materials.add(StandardMaterial::from_color(BLUE)),
-            transform: event.position,
-            ..default()
-        },
-        Bullet {
-            origin: event.position.translation,
-            range: 14.,
-            velocity: 16.,
-            shooter: event.player_id,
-        },
-        GameComponentParent {},
-    ));
+    // The server sends one attack per accepted client attack, several may arrive in a frame
+    for event in attack_event.read() {
+        commands.spawn((
+            PbrBundle {
+                mesh: meshes.add(Sphere::new(0.1).mesh()),
+                material: materials.add(StandardMaterial::from_color(BLUE)),
+                transform: event.position,
+                ..default()
+            },
+            Bullet {
+                origin: event.position.translation,
+                range: 14.,
+                velocity: 16.,
+                shooter: event.player_id,
+            },
+            GameComponentParent {},
+        ));
+    }
 }
 
 pub fn move_bullets(
@@ -44,23 +46,31 @@ pub fn move_bullets(
 
 pub fn bullet_hits_attackable(
     mut players: Query<(&mut Health, &Transform, &Player, Entity)>,
-    mut attackables: Query<(&mut Health, &Transform, Entity, &GlobalUiPosition), Without<Player>>,
+    mut attackables: Query<(&mut Health, &Transform, Entity, Option<&GlobalUiPosition>), Without<Player>>,
     bullets: Query<(&Transform, Entity, &Bullet)>,
+    remote_authority: Option<Res<RemoteAuthority>>,
     mut commands: Commands,
 ) {
     for (bullet_pos, bullet_id, bullet) in &bullets {
         for (mut health, player_pos, player, _entity) in &mut players {
             if bullet_pos.translation.distance(player_pos.translation) <= 0.5 && bullet.shooter != player.id {
                 commands.entity(bullet_id).despawn_recursive();
-                health.value -= 1;
+                if remote_authority.is_none() {
+                    health.value -= 1;
+                }
             }
         }
         for (mut health, attackable_pos, entity, node) in &mut attackables {
             if bullet_pos.translation.distance(attackable_pos.translation) <= 0.5 {
                 commands.entity(bullet_id).despawn();
+                if remote_authority.is_some() {
+                    continue;
+                }
                 health.value -= 1;
                 if health.value == 0 {
-                    commands.entity(node.node_entity).despawn_recursive();
+                    if let Some(node) = node {
+                        commands.entity(node.node_entity).despawn_recursive();
+                    }
                     commands.entity(entity).despawn();
                 }
             }
@@ -3,7 +3,7 @@ use bevy_inspector_egui::{bevy_egui::EguiContext, bevy_inspector, egui};
 
 use crate::{game::{base::resources::{GameAge, TimeInGame}, online::OnlineState}, ui::lobby::LobbySocket, Settings};
 
-use super::{FpsInfo, FpsInfoText, GameAgeInfoText, HudDebugState, HudParentEntities, InGameTimeInfoText, PingInfoText};
+use super::{FpsInfo, FpsInfoText, GameAgeInfoText, HudDebugState, HudParentEntities, InGameTimeInfoText, NetStatsInfoText};
 
 pub fn build_debug_hud(
     mut commands: Commands,
@@ -31,7 +31,7 @@ pub fn build_debug_hud(
         if *online_state.get() != OnlineState::None {
             p.spawn((
                 TextBundle::from_section("Ping: 0ms", text_style.clone()),
-                PingInfoText
+                NetStatsInfoText
             ));
         }
         if *online_state.get() == OnlineState::Client {
@@ -75,14 +75,27 @@ pub fn update_fps(
     }
 }
 
-pub fn update_ping(
-    mut info_text: Query<&mut Text, With<PingInfoText>>,
+pub fn update_net_stats(
+    mut info_text: Query<&mut Text, With<NetStatsInfoText>>,
     mut remote: ResMut<LobbySocket>,
 ) {
-    if remote.socket.ping.has_changed().is_ok() {
+    if let Ok(true) = remote.socket.stats.has_changed() {
         if let Ok(mut text) = info_text.get_single_mut() {
-            let ping = remote.socket.ping.borrow_and_update();
-            text.sections[0].value = format!("Ping: {}ms", ping.as_millis());
+            let stats = remote.socket.stats.borrow_and_update();
+            let last_packet = stats.since_last_packet()
+                .map(|since| format!("{}ms ago", since.as_millis()))
+                .unwrap_or("never".to_string());
+            let unreachable = if stats.unreachable { " (unreachable)" } else { "" };
+            text.sections[0].value = format!(
+                "Ping: {}ms (min {}ms, jitter {}ms)\nLoss: {:.1}%, resends: {}\nIn: {}B/s, out: {}B/s\nLast packet: {last_packet}{unreachable}",
+                stats.rtt_avg.as_millis(),
+                stats.rtt_min.as_millis(),
+                stats.jitter.as_millis(),
+                stats.packet_loss,
+                stats.resends,
+                stats.bytes_in_per_sec,
+                stats.bytes_out_per_sec,
+            );
         }
     }
 }
@@ -27,7 +27,7 @@ impl Plugin for HudPlugin {
             .add_systems(OnExit(HudDebugState::Enabled), despawn_debug_hud)
             .add_systems(Update, (
                 update_fps,
-                update_ping.run_if(not(in_state(ConnectionState::None))),
+                update_net_stats.run_if(not(in_state(ConnectionState::None))),
                 update_game_age.run_if(in_state(OnlineState::Client)),
                 update_in_game_time,
                 inspector_ui,
@@ -64,4 +64,4 @@ struct InGameTimeInfoText;
 struct GameAgeInfoText;
 
 #[derive(Component)]
-struct PingInfoText;
+struct NetStatsInfoText;
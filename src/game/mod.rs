@@ -8,15 +8,20 @@ pub mod hud;
 use hud::HudPlugin;
 use online::GameOnlinePlugin;
 
-pub struct GamePlugin;
+pub struct GamePlugin {
+    // Leave out everything that renders or reads input, to run without a window
+    pub headless: bool,
+}
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         app
             .add_plugins((
-                HudPlugin,
-                GameBasePlugin,
-                GameOnlinePlugin,
+                GameBasePlugin { headless: self.headless },
+                GameOnlinePlugin { headless: self.headless },
             ));
+        if !self.headless {
+            app.add_plugins(HudPlugin);
+        }
     }
 }
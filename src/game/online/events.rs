@@ -1,7 +1,8 @@
 use bevy::prelude::*;
 
+// The lobby asks for the world on behalf of a joining player, carries the id of the request
 #[derive(Event)]
-pub struct ShareWorld;
+pub struct ShareWorld(pub u16);
 
 #[derive(Event)]
 pub struct ReceivedWorld(pub String);
@@ -21,13 +22,17 @@ pub struct ShareAttack(pub Transform);
 #[derive(Event)]
 pub struct MovePlayer {
     pub id: u16,
-    pub position: Vec3
+    pub position: Vec3,
+    // Seconds since the sender started
+    pub time: f32,
 }
 
 #[derive(Event)]
 pub struct RotatePlayer {
     pub id: u16,
-    pub rotation: Quat
+    pub rotation: Quat,
+    // Seconds since the sender started
+    pub time: f32,
 }
 
 #[derive(Event)]
@@ -48,3 +53,24 @@ pub struct SpawnPlayer {
 
 #[derive(Event)]
 pub struct DespawnPlayer(pub u16);
+
+pub struct PlayerState {
+    pub id: u16,
+    pub position: Vec3,
+    pub health: u32,
+    pub last_move: u32,
+}
+
+pub struct NpcState {
+    pub id: u16,
+    pub health: u32,
+}
+
+// State of the players and npcs as decided by the authoritative host
+#[derive(Event)]
+pub struct ReceivedSnapshot {
+    // Seconds since the host started
+    pub time: f32,
+    pub players: Vec<PlayerState>,
+    pub npcs: Vec<NpcState>,
+}
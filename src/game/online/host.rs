@@ -1,15 +1,14 @@
 use bevy::prelude::*;
-use ysync::TcpFromClient;
-
-use crate::{game::{base::{components::{Health, Npc, Player}, resources::GameAge}, online::resource::GameAgeDuration}, ui::lobby::LobbySocket};
+use crate::{game::{base::{components::{Health, Npc, Player}, resources::GameAge}, online::{events::ShareWorld, resource::GameAgeDuration}}, ui::lobby::LobbySocket};
 
 pub fn share_world(
     world: &World,
     players: Query<Entity, With<Player>>,
     npcs: Query<Entity, With<Npc>>,
     remote: Res<LobbySocket>,
+    mut share_world_events: EventReader<ShareWorld>,
 ) {
-    println!("executing share_world");
+    debug!("executing share_world");
     let game_age = world.get_resource::<GameAge>().unwrap_or(&GameAge::default()).startup;
     let mut scene = DynamicSceneBuilder::from_world(world)
         .allow::<Player>()
@@ -25,5 +24,8 @@ pub fn share_world(
         .build();
     scene.resources.push(Box::new(GameAgeDuration(game_age.elapsed())));
     let serialized_scene = scene.serialize(&world.resource::<AppTypeRegistry>().read()).unwrap();
-    let _ = remote.socket.tcp_send.send(TcpFromClient::GameWorld(serialized_scene));
+    // Players joining at the same time get the same world
+    for ShareWorld(request) in share_world_events.read() {
+        remote.socket.share_world(*request, &serialized_scene);
+    }
 }
@@ -2,8 +2,8 @@ use bevy::prelude::*;
 use client::load_world;
 use events::*;
 use host::share_world;
-use receive_events::{move_other_players, other_players_jump, rotate_other_players};
-use resource::{GameAgeDuration, ShareMovementTimer, ShareRotationTimer};
+use receive_events::{apply_snapshot, insert_interpolation_buffers, interpolate_other_players, move_other_players, other_players_jump, rotate_other_players, smooth_correction};
+use resource::{GameAgeDuration, PredictedMoves, ShareMovementTimer, ShareRotationTimer};
 use share_events::{advance_timers, share_attack, share_jump, share_movement, share_rotation};
 use ysync::TcpFromClient;
 
@@ -18,7 +18,11 @@ mod host;
 mod share_events;
 mod receive_events;
 
-pub struct GameOnlinePlugin;
+pub struct GameOnlinePlugin {
+    // The authoritative server applies what the players send right away, instead of showing it
+    // with a delay
+    pub headless: bool,
+}
 
 impl Plugin for GameOnlinePlugin {
     fn build(&self, app: &mut App) {
@@ -41,6 +45,8 @@ impl Plugin for GameOnlinePlugin {
             .add_event::<MovePlayer>()
             .add_event::<RotatePlayer>()
             .add_event::<PlayerJump>()
+            .add_event::<ReceivedSnapshot>()
+            .init_resource::<PredictedMoves>()
             .insert_resource(ShareMovementTimer(Timer::from_seconds(0.05, TimerMode::Once)))
             .insert_resource(ShareRotationTimer(Timer::from_seconds(0.1, TimerMode::Once)))
             .add_systems(OnEnter(AppState::InGame), (
@@ -59,10 +65,20 @@ impl Plugin for GameOnlinePlugin {
                 rotate_other_players.run_if(on_event::<RotatePlayer>()),
                 other_players_jump.run_if(on_event::<PlayerJump>()),
             ).run_if(not(in_state(OnlineState::None))))
+            .add_systems(Update, (
+                apply_snapshot.run_if(on_event::<ReceivedSnapshot>()),
+                smooth_correction.after(apply_snapshot),
+            ).run_if(in_state(OnlineState::Client)))
             .add_systems(Update, return_to_lobby.run_if(not(in_state(ChatState::Open))).run_if(not(in_state(OnlineState::None))))
             .add_systems(Update, load_world.run_if(on_event::<ReceivedWorld>()).run_if(in_state(OnlineState::Client)))
             .add_systems(Update, share_world.run_if(on_event::<ShareWorld>()).run_if(in_state(OnlineState::Host)))
             .add_systems(OnExit(AppState::InGame), set_online_state_none);
+        if !self.headless {
+            app.add_systems(Update, (
+                insert_interpolation_buffers,
+                interpolate_other_players.after(move_other_players).after(rotate_other_players),
+            ).run_if(not(in_state(OnlineState::None))));
+        }
     }
 }
 
@@ -78,7 +94,7 @@ pub fn spawn_scene(
     mut commands: Commands,
     world_scene: Res<WorldScene>,
 ) {
-    println!("spawning scene");
+    debug!("spawning scene");
     commands.spawn(DynamicSceneBundle {
         scene: world_scene.0.clone(),
         ..default()
@@ -1,25 +1,86 @@
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::Velocity;
 
-use crate::game::base::components::{MainCharacter, Player};
+use crate::{game::base::components::{GlobalUiPosition, Health, InterpolationBuffer, MainCharacter, Npc, Player}, Settings};
 
-use super::events::{MovePlayer, PlayerJump, RotatePlayer};
+use super::{events::{MovePlayer, PlayerJump, ReceivedSnapshot, RotatePlayer}, resource::{PredictedMoves, RemoteAuthority}};
 
+// From this far away the main character is put right onto its reconciled position
+const SNAP_DISTANCE: f32 = 4.;
+// Share of the correction that is applied per second
+const CORRECTION_RATE: f32 = 10.;
+// Corrections shorter than this are finished at once
+const MIN_CORRECTION: f32 = 0.01;
+
+pub fn insert_interpolation_buffers(
+    mut commands: Commands,
+    players: Query<(Entity, &Player), Added<Player>>,
+) {
+    for (entity, player) in &players {
+        if !player.mc {
+            commands.entity(entity).insert(InterpolationBuffer::default());
+        }
+    }
+}
+
+// Players without a buffer, e.g. on the authoritative server, are turned right away
 pub fn rotate_other_players(
-    mut players: Query<(&mut Transform, &Player), Without<MainCharacter>>,
+    mut players: Query<(&mut Transform, Option<&mut InterpolationBuffer>, &Player)>,
     mut rotate_events: EventReader<RotatePlayer>,
+    time: Res<Time>,
 ) {
     for event in rotate_events.read().into_iter() {
-        players.iter_mut().find(|(_, p)| p.id == event.id).map(|(mut pos, _)| pos.rotation = event.rotation);
+        let Some((mut pos, buffer, _)) = players.iter_mut().find(|(_, _, p)| p.id == event.id) else {
+            continue;
+        };
+        match buffer {
+            Some(mut buffer) => buffer.push_rotation(event.time, time.elapsed_seconds(), event.rotation),
+            None => pos.rotation = event.rotation,
+        }
     }
 }
 
 pub fn move_other_players(
-    mut players: Query<(&mut Transform, &Player), Without<MainCharacter>>,
+    mut players: Query<(&mut Transform, Option<&mut InterpolationBuffer>, &Player)>,
     mut move_events: EventReader<MovePlayer>,
+    remote_authority: Option<Res<RemoteAuthority>>,
+    time: Res<Time>,
 ) {
     for event in move_events.read().into_iter() {
-        players.iter_mut().find(|(_, p)| p.id == event.id).map(|(mut pos, _)| pos.translation = event.position);
+        // The snapshots of the authoritative host overrule the players' own positions
+        if remote_authority.is_some() {
+            continue;
+        }
+        let Some((mut pos, buffer, _)) = players.iter_mut().find(|(_, _, p)| p.id == event.id) else {
+            continue;
+        };
+        match buffer {
+            Some(mut buffer) => buffer.push_position(event.time, time.elapsed_seconds(), event.position),
+            None => pos.translation = event.position,
+        }
+    }
+}
+
+// Show the remote players where they were a moment ago, between the two positions sent around
+// that time
+pub fn interpolate_other_players(
+    mut players: Query<(&mut Transform, &mut InterpolationBuffer)>,
+    settings: Res<Settings>,
+    time: Res<Time>,
+) {
+    let delay = settings.interpolation_delay.as_secs_f32();
+    let now = time.elapsed_seconds();
+    for (mut pos, mut buffer) in &mut players {
+        if let Some(translation) = buffer.position_at(now, delay) {
+            // Jumping and falling are left to the local physics, unless the player is far off
+            pos.translation = match (translation.y - pos.translation.y).abs() > SNAP_DISTANCE {
+                true => translation,
+                false => translation.with_y(pos.translation.y),
+            };
+        }
+        if let Some(rotation) = buffer.rotation_at(now, delay) {
+            pos.rotation = rotation;
+        }
     }
 }
 
@@ -34,3 +95,73 @@ pub fn other_players_jump(
         });
     }
 }
+
+pub fn apply_snapshot(
+    mut commands: Commands,
+    mut players: Query<(&mut Transform, &mut Health, &Player, Option<&mut InterpolationBuffer>)>,
+    mut npcs: Query<(Entity, &mut Health, &Npc, Option<&GlobalUiPosition>), Without<Player>>,
+    mut moves: ResMut<PredictedMoves>,
+    mut snapshot_events: EventReader<ReceivedSnapshot>,
+    time: Res<Time>,
+) {
+    commands.insert_resource(RemoteAuthority);
+    for snapshot in snapshot_events.read() {
+        for (entity, mut health, npc, node) in &mut npcs {
+            match snapshot.npcs.iter().find(|s| s.id == npc.id) {
+                Some(state) => health.value = state.health,
+                // Killed by the host
+                None => {
+                    if let Some(node) = node {
+                        commands.entity(node.node_entity).despawn_recursive();
+                    }
+                    commands.entity(entity).despawn();
+                }
+            }
+        }
+        for (mut pos, mut health, player, buffer) in &mut players {
+            let Some(state) = snapshot.players.iter().find(|s| s.id == player.id) else {
+                continue;
+            };
+            health.value = state.health;
+            if let Some(mut buffer) = buffer {
+                buffer.push_position(snapshot.time, time.elapsed_seconds(), state.position);
+                continue;
+            }
+            if !player.mc {
+                continue;
+            }
+            // Replay the moves the host hasn't applied yet on top of its position
+            moves.pending.retain(|(sequence, _)| *sequence > state.last_move);
+            let predicted = state.position + moves.pending.iter().map(|(_, movement)| *movement).sum::<Vec3>() + moves.unshared;
+            let error = predicted - pos.translation;
+            if error.length() > SNAP_DISTANCE {
+                pos.translation = predicted;
+                moves.correction = Vec3::ZERO;
+            } else {
+                // Jumping and falling are left to the local physics
+                moves.correction = error.with_y(0.);
+            }
+        }
+    }
+}
+
+// Move the main character towards its reconciled position bit by bit, instead of snapping it there
+pub fn smooth_correction(
+    mut player: Query<&mut Transform, With<MainCharacter>>,
+    mut moves: ResMut<PredictedMoves>,
+    time: Res<Time>,
+) {
+    if moves.correction == Vec3::ZERO {
+        return;
+    }
+    let Ok(mut pos) = player.get_single_mut() else {
+        return;
+    };
+    let step = if moves.correction.length() < MIN_CORRECTION {
+        moves.correction
+    } else {
+        moves.correction * (CORRECTION_RATE * time.delta_seconds()).min(1.)
+    };
+    pos.translation += step;
+    moves.correction -= step;
+}
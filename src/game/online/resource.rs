@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{collections::VecDeque, time::Duration};
 
 use bevy::prelude::*;
 
@@ -8,6 +8,23 @@ pub struct ShareMovementTimer(pub Timer);
 #[derive(Resource)]
 pub struct ShareRotationTimer(pub Timer);
 
+// The host of the joined game runs the authoritative simulation, the health of the players only
+// changes through its snapshots
+#[derive(Resource)]
+pub struct RemoteAuthority;
+
+// The main character's moves the authoritative host hasn't acknowledged yet, they are replayed on
+// top of its snapshots to predict where the main character is
+#[derive(Resource, Default)]
+pub struct PredictedMoves {
+    pub next_sequence: u32,
+    // Movement since the last shared move
+    pub unshared: Vec3,
+    pub pending: VecDeque<(u32, Vec3)>,
+    // What is left of the way to the reconciled position
+    pub correction: Vec3,
+}
+
 #[derive(Resource, Reflect)]
 #[reflect(Resource)]
 pub struct GameAgeDuration(pub Duration);
@@ -1,9 +1,12 @@
 use bevy::prelude::*;
-use ysync::{UdpPackage, YPosition, YRotation, YTranslation};
+use ysync::{UdpPackage, YMove, YPosition, YRotate, YRotation};
 
 use crate::ui::lobby::LobbySocket;
 
-use super::{events::{ShareAttack, ShareMovement, ShareRotation}, resource::{ShareMovementTimer, ShareRotationTimer}};
+use super::{events::{ShareAttack, ShareMovement, ShareRotation}, resource::{PredictedMoves, ShareMovementTimer, ShareRotationTimer}};
+
+// About 3 seconds of moves, without an authoritative host nobody acknowledges them
+const MAX_PENDING_MOVES: usize = 64;
 
 pub fn advance_timers(
     mut share_movement: ResMut<ShareMovementTimer>,
@@ -16,24 +19,42 @@ pub fn advance_timers(
 
 pub fn share_movement(
     remote: Res<LobbySocket>,
+    mut moves: ResMut<PredictedMoves>,
     mut movement_event: EventReader<ShareMovement>,
+    time: Res<Time>,
 ) {
     let event = movement_event.read().next().expect("All according to plan of course");
-    let _ = remote.socket.udp_send.send(UdpPackage::Move(YTranslation::from(event.0)));
+    moves.next_sequence += 1;
+    let sequence = moves.next_sequence;
+    let movement = std::mem::take(&mut moves.unshared);
+    moves.pending.push_back((sequence, movement));
+    if moves.pending.len() > MAX_PENDING_MOVES {
+        moves.pending.pop_front();
+    }
+    remote.socket.send_udp(UdpPackage::Move(YMove {
+        sequence,
+        time: time.elapsed().as_millis() as u32,
+        movement: movement.into(),
+        position: event.0.into(),
+    }));
 }
 
 pub fn share_rotation(
     socket: Res<LobbySocket>,
     mut rotation_event: EventReader<ShareRotation>,
+    time: Res<Time>,
 ) {
     let event = rotation_event.read().next().expect("All according to plan of course");
-    let _ = socket.socket.udp_send.send(UdpPackage::Rotate(YRotation::from(event.0)));
+    socket.socket.send_udp(UdpPackage::Rotate(YRotate {
+        time: time.elapsed().as_millis() as u32,
+        rotation: YRotation::from(event.0),
+    }));
 }
 
 pub fn share_jump(
     remote: Res<LobbySocket>,
 ) {
-    let _ = remote.socket.udp_send.send(UdpPackage::Jump);
+    remote.socket.send_udp(UdpPackage::Jump);
 }
 
 pub fn share_attack(
@@ -41,5 +62,5 @@ pub fn share_attack(
     mut attack_event: EventReader<ShareAttack>,
 ) {
     let event = attack_event.read().next().expect("All according to plan of course");
-    let _ = socket.socket.udp_send.send(UdpPackage::Attack(YPosition::from(event.0)));
+    socket.socket.send_udp(UdpPackage::Attack(YPosition::from(event.0)));
 }
@@ -0,0 +1,161 @@
+use std::{f32::consts::TAU, time::Duration};
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::Velocity;
+
+use crate::{game::{base::{components::{Health, Npc, Player}, projectiles::spawn_bullets}, online::{events::PlayerAttack, OnlineState}}, AppState};
+
+// Distance of the bots to the center of the floor while they walk around it
+const WALK_RADIUS: f32 = 6.;
+// How far a bot shoots, a bit less than the range of a bullet
+const ATTACK_RANGE: f32 = 12.;
+
+pub struct BotPlugin {
+    pub count: usize,
+    pub duration: Option<Duration>,
+}
+
+#[derive(Resource)]
+struct BotConfig {
+    count: usize,
+    duration: Option<Duration>,
+}
+
+#[derive(Resource, Default)]
+struct BotStats {
+    attacks: u32,
+    npc_kills: u32,
+}
+
+#[derive(Component)]
+struct Bot {
+    index: usize,
+    jump_timer: Timer,
+    attack_timer: Timer,
+}
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(BotConfig { count: self.count, duration: self.duration })
+            .init_resource::<BotStats>()
+            .add_systems(Startup, start_game)
+            .add_systems(OnEnter(AppState::InGame), spawn_bots)
+            .add_systems(Update, (
+                drive_bots,
+                count_npc_kills,
+                stop_after_duration,
+                spawn_bullets.run_if(on_event::<PlayerAttack>()).after(drive_bots),
+            ).run_if(in_state(AppState::InGame)).run_if(in_state(OnlineState::None)));
+    }
+}
+
+fn start_game(
+    config: Res<BotConfig>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    info!(bots = config.count, "starting an offline game");
+    next_state.set(AppState::InGame);
+}
+
+fn spawn_bots(
+    config: Res<BotConfig>,
+    mut commands: Commands,
+) {
+    for index in 0..config.count {
+        let angle = index as f32 * TAU / config.count as f32;
+        commands.spawn((
+            Bot {
+                index,
+                jump_timer: Timer::from_seconds(3. + index as f32 * 0.5, TimerMode::Repeating),
+                attack_timer: Timer::from_seconds(1., TimerMode::Once),
+            },
+            Player {
+                mc: false,
+                base_velocity: 4.,
+                name: format!("Bot #{}", index + 1),
+                id: index as u16 + 1,
+            },
+            Health {
+                value: 5
+            },
+            TransformBundle::from_transform(
+                Transform::from_xyz(angle.cos() * WALK_RADIUS, 10., angle.sin() * WALK_RADIUS).with_scale(Vec3::new(0.4, 0.4, 0.4))
+            ),
+        ));
+    }
+}
+
+// Walk around the center, jump every now and then and shoot at the closest npc
+fn drive_bots(
+    config: Res<BotConfig>,
+    mut bots: Query<(&mut Bot, &Player, &mut Transform, Option<&mut Velocity>)>,
+    npcs: Query<&Transform, (With<Npc>, Without<Bot>)>,
+    time: Res<Time>,
+    mut stats: ResMut<BotStats>,
+    mut attack_event: EventWriter<PlayerAttack>,
+) {
+    for (mut bot, player, mut pos, velocity) in &mut bots {
+        let angle = time.elapsed_seconds() * player.base_velocity / WALK_RADIUS + bot.index as f32 * TAU / config.count as f32;
+        let target = Vec3::new(angle.cos(), 0., angle.sin()) * WALK_RADIUS;
+        let direction = (target - pos.translation).with_y(0.).normalize_or_zero();
+        pos.translation += direction * player.base_velocity * time.delta_seconds();
+        if direction != Vec3::ZERO {
+            let look_at = pos.translation + direction;
+            pos.look_at(look_at, Vec3::Y);
+        }
+
+        if bot.jump_timer.tick(time.delta()).just_finished() && pos.translation.y <= 1. && pos.translation.y >= 0. {
+            if let Some(mut velocity) = velocity {
+                velocity.linvel = Vec3::new(0., 30., 0.);
+                velocity.angvel = Vec3::ZERO;
+            }
+        }
+
+        if !bot.attack_timer.tick(time.delta()).finished() {
+            continue;
+        }
+        let closest = npcs.iter()
+            .map(|npc| npc.translation)
+            .filter(|npc| npc.distance(pos.translation) <= ATTACK_RANGE)
+            .min_by(|a, b| a.distance(pos.translation).total_cmp(&b.distance(pos.translation)));
+        if let Some(npc) = closest {
+            attack_event.send(PlayerAttack {
+                player_id: player.id,
+                position: Transform::from_translation(pos.translation).looking_at(npc, Vec3::Y),
+            });
+            stats.attacks += 1;
+            bot.attack_timer = Timer::from_seconds(1., TimerMode::Once);
+        }
+    }
+}
+
+fn count_npc_kills(
+    mut removed_npcs: RemovedComponents<Npc>,
+    mut stats: ResMut<BotStats>,
+) {
+    for _ in removed_npcs.read() {
+        stats.npc_kills += 1;
+        info!(npc_kills = stats.npc_kills, "the bots killed an npc");
+    }
+}
+
+fn stop_after_duration(
+    config: Res<BotConfig>,
+    stats: Res<BotStats>,
+    time: Res<Time>,
+    bots: Query<(&Player, &Health), With<Bot>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Some(duration) = config.duration else {
+        return;
+    };
+    if time.elapsed() < duration {
+        return;
+    }
+    for (player, health) in &bots {
+        info!(health = health.value, "{} is done", player.name);
+    }
+    info!(attacks = stats.attacks, npc_kills = stats.npc_kills, "the bots played for {duration:?}");
+    exit.send(AppExit::Success);
+}
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use bevy::{app::ScheduleRunnerPlugin, input::InputPlugin, log::LogPlugin, prelude::*, scene::ScenePlugin, state::app::StatesPlugin};
+use bevy_rapier3d::prelude::*;
+use bots::BotPlugin;
+
+use crate::{game::GamePlugin, get_setting_value, log_file_layer, server::ServerPlugin, AppState};
+
+mod bots;
+
+// Ticks per second of the simulation
+const TICK_RATE: f64 = 60.;
+
+pub enum HeadlessMode {
+    // Host a game in the lobby and decide over the state of every player in it
+    Host { lobby_url: String, game_name: String },
+    // Play an offline game with scripted bots, until the duration is over if there is one
+    Bots { count: usize, duration: Option<Duration> },
+}
+
+/// Runs the game without a window, audio or ui, e.g. on machines without a gpu
+pub fn run(mode: HeadlessMode) {
+    let mut app = App::new();
+    app
+        .add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1. / TICK_RATE))),
+            LogPlugin {
+                filter: get_setting_value("--log", "wgpu=error,naga=warn,ysync=info"),
+                custom_layer: log_file_layer,
+                ..default()
+            },
+            TransformPlugin,
+            HierarchyPlugin,
+            AssetPlugin::default(),
+            ScenePlugin,
+            StatesPlugin,
+            // Nothing is ever pressed, but the game's systems expect the input resources
+            InputPlugin,
+            RapierPhysicsPlugin::<NoUserData>::default(),
+            GamePlugin { headless: true },
+        ))
+        .init_state::<AppState>();
+    match mode {
+        HeadlessMode::Host { lobby_url, game_name } => app.add_plugins(ServerPlugin { lobby_url, game_name }),
+        HeadlessMode::Bots { count, duration } => app.add_plugins(BotPlugin { count, duration }),
+    };
+    app.run();
+}
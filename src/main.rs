@@ -1,6 +1,7 @@
-use std::env::args;
+use std::{env::args, fs::File, time::Duration};
 
-use bevy::{prelude::*, window::{EnabledButtons, PresentMode, WindowMode, WindowResolution}};
+use bevy::{log::{tracing_subscriber::Layer, BoxedLayer, LogPlugin}, prelude::*, window::{EnabledButtons, PresentMode, WindowMode, WindowResolution}};
+use tracing_appender::non_blocking::WorkerGuard;
 use bevy_embedded_assets::EmbeddedAssetPlugin;
 use bevy_rapier3d::prelude::*;
 
@@ -8,13 +9,43 @@ mod ui;
 mod game;
 mod commands;
 mod audio;
+mod server;
+mod headless;
 
 use commands::{execute_cmds, GameCommand};
 use ui::{lobby::LobbyState, UiPlugin};
 use game::GamePlugin;
 use audio::SoundPlugin;
+use headless::HeadlessMode;
+
+// The public lobby
+const LOBBY_URL: &str = "91.108.102.51:9983";
 
 fn main() {
+    // --server is kept as the short form of --headless --host
+    if get_setting("--server", false) || get_setting("--headless", false) {
+        let mode = if get_setting("--server", false) || get_setting("--host", false) {
+            HeadlessMode::Host {
+                lobby_url: get_setting_value("--lobby_url", LOBBY_URL),
+                game_name: get_setting_value("--game_name", "Yggdrasil server"),
+            }
+        } else {
+            let count = get_setting_value("--bots", "4");
+            // Seconds, runs until stopped without it
+            let duration = match get_setting_value("--duration", "").as_str() {
+                "" => None,
+                value => Some(value.parse().ok()
+                    .and_then(|secs| Duration::try_from_secs_f32(secs).ok())
+                    .unwrap_or_else(|| invalid_setting("--duration", value, "a positive number of seconds"))),
+            };
+            HeadlessMode::Bots {
+                count: count.parse().unwrap_or_else(|_| invalid_setting("--bots", &count, "the number of bots")),
+                duration,
+            }
+        };
+        headless::run(mode);
+        return;
+    }
     let hitboxes_enabled = get_setting("--hitboxes", false);
     App::new()
         .add_plugins((
@@ -31,6 +62,13 @@ fn main() {
                     }),
                     ..default()
                 }
+            ).set(
+                LogPlugin {
+                    // Overwritten by RUST_LOG if set
+                    filter: get_setting_value("--log", "wgpu=error,naga=warn,ysync=info"),
+                    custom_layer: log_file_layer,
+                    ..default()
+                }
             ),
             EmbeddedAssetPlugin::default(),
             RapierPhysicsPlugin::<NoUserData>::default(),
@@ -39,7 +77,7 @@ fn main() {
                 ..default()
             },
             UiPlugin {},
-            GamePlugin {},
+            GamePlugin { headless: false },
             SoundPlugin {},
         ))
         .init_state::<AppState>()
@@ -51,7 +89,8 @@ fn main() {
             hitboxes_enabled,
             egui_enabled: get_setting("--no_egui", true),
             debug_hud_enabled: get_setting("--debug_hud", false),
-            lobby_url: get_setting_value("--lobby_url", "91.108.102.51:9983"),
+            lobby_url: get_setting_value("--lobby_url", LOBBY_URL),
+            interpolation_delay: Duration::from_millis(get_setting_value("--interpolation_delay", "100").parse().unwrap_or(100)),
         })
         .add_systems(Update, execute_cmds)
         .run();
@@ -74,6 +113,36 @@ pub struct Settings {
     debug_hud_enabled: bool,
     egui_enabled: bool,
     lobby_url: String,
+    // How far in the past the other players are shown
+    interpolation_delay: Duration,
+}
+
+// Keeps the log file writer alive
+#[derive(Resource)]
+struct LogFileGuard(#[allow(dead_code)] WorkerGuard);
+
+// Additionally write all logs to the file given by --log_file, replacing the previous log there
+fn log_file_layer(app: &mut App) -> Option<BoxedLayer> {
+    let path = get_setting_value("--log_file", "");
+    if path.is_empty() {
+        return None;
+    }
+    let file = match File::create(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to create log file {path}, e: {e}");
+            return None;
+        }
+    };
+    let (writer, guard) = tracing_appender::non_blocking(file);
+    app.insert_resource(LogFileGuard(guard));
+    Some(bevy::log::tracing_subscriber::fmt::layer().with_ansi(false).with_writer(writer).boxed())
+}
+
+// Invalid command line values are reported before anything starts
+fn invalid_setting(arg: &str, value: &str, expected: &str) -> ! {
+    eprintln!("Invalid value '{value}' for {arg}, expected {expected}");
+    std::process::exit(1)
 }
 
 fn get_setting(arg: &'static str, default: bool) -> bool {
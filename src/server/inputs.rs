@@ -0,0 +1,183 @@
+use bevy::prelude::*;
+use ysync::{client::TcpUpdate, GameUpdate, LobbyUpdate, UdpPackage};
+
+use crate::game::{base::components::Player, online::events::{DespawnPlayer, PlayerAttack, PlayerJump, RotatePlayer, ShareWorld, SpawnPlayer}};
+use crate::ui::lobby::LobbySocket;
+
+// Sprinting speeds the players up by this factor
+const MAX_SPEED_FACTOR: f32 = 1.8;
+// A move covers the sender's time since its previous move, at most this much after a long
+// frame or a break
+const MAX_MOVE_WINDOW: f32 = 0.5;
+// Seconds a move may exceed its window, as the times only have millisecond precision
+const MOVE_TOLERANCE: f32 = 0.01;
+// How far from the player's position a bullet may start
+const MAX_ATTACK_DISTANCE: f32 = 1.;
+// Seconds between two attacks of the same player
+const ATTACK_COOLDOWN: f32 = 0.1;
+// Players can only jump off the floor
+const MAX_JUMP_HEIGHT: f32 = 1.5;
+
+// The player's last inputs, to check the next ones against
+#[derive(Component)]
+pub struct LastInputs {
+    // Sender time of the last move in milliseconds, None before the first one
+    move_time: Option<u32>,
+    // When the last accepted attack arrived, in seconds since startup
+    attacked: f32,
+    // Sequence of the last move that got applied or rejected
+    pub last_move: u32,
+}
+
+pub fn insert_last_inputs(
+    mut commands: Commands,
+    players: Query<Entity, Added<Player>>,
+) {
+    for player in &players {
+        commands.entity(player).insert(LastInputs { move_time: None, attacked: 0., last_move: 0 });
+    }
+}
+
+// Follow the lobby and check every input of the players, rejected inputs are corrected by the next
+// snapshot
+pub fn read_lobby_events(
+    mut socket: ResMut<LobbySocket>,
+    mut players: Query<(&Player, &mut Transform, &mut LastInputs)>,
+    time: Res<Time>,
+    mut exit: EventWriter<AppExit>,
+    mut share_world_event: EventWriter<ShareWorld>,
+    mut player_spawn_event: EventWriter<SpawnPlayer>,
+    mut player_despawn_event: EventWriter<DespawnPlayer>,
+    mut player_attack_event: EventWriter<PlayerAttack>,
+    mut player_rotate_event: EventWriter<RotatePlayer>,
+    mut player_jump_event: EventWriter<PlayerJump>,
+) {
+    let socket = &mut *socket;
+    for update in socket.socket.tcp_recv.try_iter() {
+        match update {
+            TcpUpdate::LobbyUpdate(LobbyUpdate::Connection(client)) => {
+                socket.lobby.clients.insert(client.client_id, client);
+            }
+            TcpUpdate::LobbyUpdate(LobbyUpdate::Disconnection(client_id)) => {
+                socket.lobby.clients.remove(&client_id);
+            }
+            TcpUpdate::LobbyUpdate(_) => {}
+            TcpUpdate::GameUpdate(GameUpdate::Creation(game)) => {
+                if game.host_id == socket.socket.client_id {
+                    info!(game_id = game.game_id, "hosting game {}", game.game_name);
+                    socket.socket.game_id = Some(game.game_id);
+                }
+                socket.lobby.games.insert(game.game_id, game);
+            }
+            TcpUpdate::GameUpdate(GameUpdate::Deletion(game_id)) => {
+                socket.lobby.games.remove(&game_id);
+                if Some(game_id) == socket.socket.game_id {
+                    warn!(game_id, "the hosted game got closed, shutting down");
+                    exit.send(AppExit::Success);
+                }
+            }
+            TcpUpdate::GameUpdate(GameUpdate::Entry { client_id, game_id }) => {
+                if let Some(game) = socket.lobby.games.get_mut(&game_id) {
+                    game.clients.push(client_id);
+                }
+                if Some(game_id) == socket.socket.game_id {
+                    info!(client_id, "player joined");
+                    player_spawn_event.send(SpawnPlayer {
+                        name: socket.lobby.clients.get(&client_id).map_or(format!("#{client_id}"), |c| c.name.clone()),
+                        id: client_id,
+                        position: Transform::from_xyz(0., 10., 0.).with_scale(Vec3::new(0.4, 0.4, 0.4))
+                    });
+                }
+            }
+            TcpUpdate::GameUpdate(GameUpdate::Exit(client_id)) => {
+                if let Some(game) = socket.lobby.games.values_mut().find(|game| game.clients.contains(&client_id)) {
+                    game.clients.retain(|c| *c != client_id);
+                    if Some(game.game_id) == socket.socket.game_id {
+                        info!(client_id, "player left");
+                        player_despawn_event.send(DespawnPlayer(client_id));
+                    }
+                }
+            }
+            TcpUpdate::GameUpdate(GameUpdate::HostMigration { game_id, host_id }) => {
+                if let Some(game) = socket.lobby.games.get_mut(&game_id) {
+                    game.host_id = host_id;
+                }
+            }
+            TcpUpdate::GameUpdate(GameUpdate::Default) => {}
+            TcpUpdate::LobbySnapshot(lobby) => {
+                socket.lobby = lobby;
+            }
+            TcpUpdate::WorldRequest { request, client_id } => {
+                debug!(request, client_id, "sharing the world");
+                share_world_event.send(ShareWorld(request));
+            }
+            TcpUpdate::WorldProgress { .. } | TcpUpdate::World(_) | TcpUpdate::WorldUnavailable | TcpUpdate::WorldTimeout => {}
+            TcpUpdate::Disconnected(reason) => {
+                error!("disconnected by the lobby: {reason}");
+                exit.send(AppExit::error());
+                return;
+            }
+        }
+    }
+    let now = time.elapsed_seconds();
+    for (client_id, pkg) in socket.socket.udp_recv.try_iter() {
+        let Some((player, mut pos, mut last)) = players.iter_mut().find(|(p, _, _)| p.id == client_id) else {
+            continue;
+        };
+        match pkg {
+            UdpPackage::Move(ymove) => {
+                if ymove.sequence <= last.last_move {
+                    continue;
+                }
+                // Rejected moves count as handled too, so the player's prediction drops them
+                last.last_move = ymove.sequence;
+                // Moves arrive in order but possibly bunched up, so they are measured with the
+                // sender's time, idling doesn't save up distance for a later move
+                let elapsed = last.move_time
+                    .map_or(MAX_MOVE_WINDOW, |previous| ymove.time.saturating_sub(previous) as f32 / 1000.)
+                    .min(MAX_MOVE_WINDOW);
+                last.move_time = Some(ymove.time);
+                // Falling and jumping are up to the physics, only the walking is applied
+                let movement = Vec3::from(ymove.movement).with_y(0.);
+                let distance = movement.length();
+                let max_distance = player.base_velocity * MAX_SPEED_FACTOR * (elapsed + MOVE_TOLERANCE);
+                if distance > max_distance {
+                    warn!(client_id, distance, max_distance, "rejected a move that is too fast");
+                    continue;
+                }
+                pos.translation += movement;
+            }
+            UdpPackage::Rotate(rotate) => {
+                player_rotate_event.send(RotatePlayer {
+                    id: client_id,
+                    rotation: Quat::from(rotate.rotation),
+                    time: rotate.time as f32 / 1000.,
+                });
+            }
+            UdpPackage::Jump => {
+                if pos.translation.y > MAX_JUMP_HEIGHT {
+                    warn!(client_id, "rejected a jump in the air");
+                    continue;
+                }
+                player_jump_event.send(PlayerJump(client_id));
+            }
+            UdpPackage::Attack(ypos) => {
+                let position = Transform::from(ypos);
+                if now - last.attacked < ATTACK_COOLDOWN {
+                    warn!(client_id, "rejected an attack during the cooldown");
+                    continue;
+                }
+                if position.translation.distance(pos.translation) > MAX_ATTACK_DISTANCE {
+                    warn!(client_id, "rejected an attack far away from the player");
+                    continue;
+                }
+                last.attacked = now;
+                player_attack_event.send(PlayerAttack { player_id: client_id, position });
+            }
+            UdpPackage::Snapshot(_) => {
+                warn!(client_id, "rejected a snapshot, only the server decides over the game");
+            }
+            UdpPackage::Heartbeat => {}
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use inputs::{insert_last_inputs, read_lobby_events, LastInputs};
+use ysync::{client::ConnectionSocket, TcpFromClient, UdpPackage, YNpcState, YPlayerState, YSnapshot};
+
+use crate::{game::{base::components::{Health, Npc, Player}, online::OnlineState}, ui::lobby::LobbySocket, AppState};
+
+mod inputs;
+
+// How often the players get the authoritative state of the game
+const SNAPSHOT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Hosts a game in the lobby as its authoritative server, it decides over the state of every
+/// player in the game
+pub struct ServerPlugin {
+    pub lobby_url: String,
+    pub game_name: String,
+}
+
+// Keeps the tasks of the lobby connection running
+#[derive(Resource)]
+struct ServerRuntime(tokio::runtime::Runtime);
+
+#[derive(Resource)]
+struct ServerConfig {
+    lobby_url: String,
+    game_name: String,
+}
+
+#[derive(Resource)]
+struct SnapshotTimer(Timer);
+
+impl Plugin for ServerPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(ServerRuntime(tokio::runtime::Runtime::new().unwrap()))
+            .insert_resource(ServerConfig { lobby_url: self.lobby_url.clone(), game_name: self.game_name.clone() })
+            .insert_resource(SnapshotTimer(Timer::new(SNAPSHOT_INTERVAL, TimerMode::Repeating)))
+            .add_systems(Startup, host_game)
+            .add_systems(Update, (
+                read_lobby_events,
+                insert_last_inputs,
+                broadcast_snapshot.after(read_lobby_events),
+            ).run_if(resource_exists::<LobbySocket>).run_if(in_state(AppState::InGame)));
+    }
+}
+
+fn host_game(
+    runtime: Res<ServerRuntime>,
+    config: Res<ServerConfig>,
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut next_online_state: ResMut<NextState<OnlineState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    info!("connecting to the lobby at {}", config.lobby_url);
+    let connection = runtime.0.block_on(ConnectionSocket::build(config.lobby_url.as_str(), "0.0.0.0:0", config.game_name.clone()));
+    let (socket, lobby) = match connection {
+        Ok(connection) => connection,
+        Err(e) => {
+            error!("Failed to connect to the lobby: {e}");
+            exit.send(AppExit::error());
+            return;
+        }
+    };
+    info!(client_id = socket.client_id, "connected to the lobby, hosting {}", config.game_name);
+    let _ = socket.tcp_send.send(TcpFromClient::GameCreation { password: None, name: config.game_name.clone() });
+    // The lobby relays the game's udp traffic only once it knows where the host's datagrams come from
+    socket.send_udp(UdpPackage::Heartbeat);
+    commands.insert_resource(LobbySocket::new(socket, lobby));
+    next_online_state.set(OnlineState::Host);
+    next_state.set(AppState::InGame);
+}
+
+fn broadcast_snapshot(
+    socket: Res<LobbySocket>,
+    mut timer: ResMut<SnapshotTimer>,
+    time: Res<Time>,
+    players: Query<(&Player, &Transform, &Health, Option<&LastInputs>)>,
+    npcs: Query<(&Npc, &Health)>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() || players.is_empty() {
+        return;
+    }
+    let players = players.iter()
+        .map(|(player, pos, health, last)| YPlayerState {
+            id: player.id,
+            translation: pos.translation.into(),
+            health: health.value,
+            last_move: last.map_or(0, |last| last.last_move),
+        })
+        .collect();
+    let npcs = npcs.iter()
+        .map(|(npc, health)| YNpcState { id: npc.id, health: health.value })
+        .collect();
+    socket.socket.send_udp(UdpPackage::Snapshot(YSnapshot {
+        time: time.elapsed().as_millis() as u32,
+        players,
+        npcs,
+    }));
+}
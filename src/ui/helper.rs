@@ -103,7 +103,7 @@ fn get_text_input(
 
         match &event.logical_key {
             Key::Enter => {
-                println!("pressed enter!");
+                debug!("pressed enter!");
             }
             Key::Space => {
                 buffer.0.push(' ');
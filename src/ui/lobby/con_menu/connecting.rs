@@ -1,4 +1,4 @@
-use bevy::{prelude::*, utils::HashMap};
+use bevy::prelude::*;
 use tokio::sync::oneshot::channel;
 use ysync::client::ConnectionSocket;
 
@@ -13,13 +13,13 @@ pub fn connect_to_lobby(
     mut next_state: ResMut<NextState<ConnectionState>>,
     mut commands: Commands,
 ) {
-    println!("trying to connect");
     let name = name.0.clone();
     let (sender, receiver) = channel();
     let lobby_addr = match settings.local_lobby {
         true => "127.0.0.1:9983".to_string(),
         false => settings.lobby_url.clone(),
     };
+    info!("trying to connect to {lobby_addr}");
     rt.0.spawn(async move {
         let socket = ConnectionSocket::build(lobby_addr, "0.0.0.0:0".to_string(), name).await;
         let _ = sender.send(socket);
@@ -38,14 +38,15 @@ pub fn con_finished_check(
     if let Ok(result) = receiver.0.try_recv() {
         match result {
             Ok((socket, lobby)) => {
-                println!("Connected!\n{socket:?}\n{lobby:#?}");
+                info!(client_id = socket.client_id, "Connected to the lobby");
+                debug!("{lobby:?}");
                 player_id.0 = socket.client_id;
                 next_state.set(ConnectionState::Connected);
                 pending_msgs.0.push(format!("[INFO] Connected to lobby as #{}", socket.client_id));
-                commands.insert_resource(LobbySocket {client_nodes: HashMap::new(), game_nodes: HashMap::new(), lobby, socket});
+                commands.insert_resource(LobbySocket::new(socket, lobby));
             }
             Err(e) => {
-                println!("error with connection: {e}");
+                warn!("error with connection: {e}");
                 next_state.set(ConnectionState::None);
                 pending_msgs.0.push(format!("[INFO] Failed to connect to lobby"));
             }
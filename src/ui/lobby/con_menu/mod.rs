@@ -124,7 +124,7 @@ fn menu_interaction(
                 *color = PRESSED_BUTTON.into();
                 let name: String = name_field.get_single().unwrap().0.to_string();
                 if !name.is_empty() {
-                    println!("name is {name}");
+                    debug!("name is {name}");
                     player_name.0 = name;
                     next_state.set(AppState::Lobby(LobbyState::InLobby));
                 }
@@ -38,10 +38,10 @@ pub fn game_section_interaction(
                 let _ = socket.socket.tcp_send.send(TcpFromClient::GameCreation { name: name_input.single().sections[0].value.clone(), password: None });
                 app_state.set(AppState::InGame);
                 online_state.set(OnlineState::Host);
-                let _ = socket.socket.udp_send.send(UdpPackage::Heartbeat);
-                let _ = socket.socket.udp_send.send(UdpPackage::Heartbeat);
-                let _ = socket.socket.udp_send.send(UdpPackage::Heartbeat);
-                let _ = socket.socket.udp_send.send(UdpPackage::Heartbeat);
+                socket.socket.send_udp(UdpPackage::Heartbeat);
+                socket.socket.send_udp(UdpPackage::Heartbeat);
+                socket.socket.send_udp(UdpPackage::Heartbeat);
+                socket.socket.send_udp(UdpPackage::Heartbeat);
             }
             Interaction::Hovered => {
                 *color = HOVERED_BUTTON.into();
@@ -59,10 +59,10 @@ pub fn game_section_interaction(
                 socket.socket.game_id = Some(game_id.0);
                 app_state.set(AppState::Lobby(LobbyState::LoadGame));
                 online_state.set(OnlineState::Client);
-                let _ = socket.socket.udp_send.send(UdpPackage::Heartbeat);
-                let _ = socket.socket.udp_send.send(UdpPackage::Heartbeat);
-                let _ = socket.socket.udp_send.send(UdpPackage::Heartbeat);
-                let _ = socket.socket.udp_send.send(UdpPackage::Heartbeat);
+                socket.socket.send_udp(UdpPackage::Heartbeat);
+                socket.socket.send_udp(UdpPackage::Heartbeat);
+                socket.socket.send_udp(UdpPackage::Heartbeat);
+                socket.socket.send_udp(UdpPackage::Heartbeat);
             }
             Interaction::Hovered => {
                 *color = HOVERED_BUTTON.into();
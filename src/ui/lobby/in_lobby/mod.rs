@@ -4,9 +4,9 @@ use interaction::{game_section_interaction, lobby_interaction};
 use read_packets::get_lobby_events;
 use ysync::TcpFromClient;
 
-use crate::{ui::{despawn_camera, despawn_menu, spawn_camera}, AppState};
+use crate::{game::online::OnlineState, ui::{chat::PendingMessages, despawn_camera, despawn_menu, spawn_camera}, AppState};
 
-use super::{ConnectionState, LobbySocket, LobbyState};
+use super::{ConnectionState, LobbyDisconnected, LobbyResynced, LobbySocket, LobbyState, WorldDownload};
 
 mod build_ui;
 mod interaction;
@@ -17,6 +17,9 @@ pub struct InLobbyPlugin;
 impl Plugin for InLobbyPlugin {
     fn build(&self, app: &mut App) {
         app
+            .add_event::<LobbyResynced>()
+            .add_event::<LobbyDisconnected>()
+            .add_event::<WorldDownload>()
             .add_systems(OnEnter(AppState::Lobby(LobbyState::InLobby)), (
                 build_lobby,
                 build_lobby_details.run_if(resource_exists::<LobbySocket>).after(build_lobby),
@@ -29,16 +32,18 @@ impl Plugin for InLobbyPlugin {
             .add_systems(OnTransition {
                 exited: AppState::Lobby(LobbyState::InLobby),
                 entered: AppState::MainMenu,
-            }, disconnect_from_lobby.run_if(in_state(ConnectionState::Connected)))
-            .add_systems(Update,
+            }, disconnect_from_lobby.run_if(in_state(ConnectionState::Connected)).run_if(resource_exists::<LobbySocket>))
+            .add_systems(Update, (
                 get_lobby_events
                     .run_if(in_state(ConnectionState::Connected))
-                    .run_if(resource_exists::<LobbySocket>)
-            )
+                    .run_if(resource_exists::<LobbySocket>),
+                disconnected_by_server.run_if(on_event::<LobbyDisconnected>()).after(get_lobby_events),
+            ))
             .add_systems(OnEnter(ConnectionState::Connected), build_lobby_details)
             .add_systems(Update, (
                 lobby_interaction,
                 game_section_interaction.run_if(in_state(ConnectionState::Connected)),
+                build_lobby_details.run_if(on_event::<LobbyResynced>()).after(get_lobby_events),
             ).run_if(in_state(AppState::Lobby(LobbyState::InLobby))));
     }
 }
@@ -52,3 +57,20 @@ fn disconnect_from_lobby(
     next_state.set(ConnectionState::None);
     commands.remove_resource::<LobbySocket>();
 }
+
+fn disconnected_by_server(
+    mut events: EventReader<LobbyDisconnected>,
+    mut pending_msgs: ResMut<PendingMessages>,
+    mut commands: Commands,
+    mut next_app_state: ResMut<NextState<AppState>>,
+    mut next_con_state: ResMut<NextState<ConnectionState>>,
+    mut next_online_state: ResMut<NextState<OnlineState>>,
+) {
+    for LobbyDisconnected(reason) in events.read() {
+        pending_msgs.0.push(format!("[ERR] {reason}"));
+    }
+    next_app_state.set(AppState::MainMenu);
+    next_con_state.set(ConnectionState::None);
+    next_online_state.set(OnlineState::None);
+    commands.remove_resource::<LobbySocket>();
+}
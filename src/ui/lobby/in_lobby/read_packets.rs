@@ -1,9 +1,9 @@
 use bevy::prelude::*;
 use ysync::{client::TcpUpdate, ClientStatus, GameUpdate, LobbyUpdate, TcpFromClient, UdpPackage};
 
-use crate::{game::online::{events::{DespawnPlayer, MovePlayer, PlayerAttack, PlayerJump, ReceivedWorld, RotatePlayer, ShareWorld, SpawnPlayer}, OnlineState}, ui::{chat::{MessageSendEvent, PendingMessages}, lobby::JoinGameButton, MenuData, NORMAL_BUTTON}, AppState};
+use crate::{game::online::{events::{DespawnPlayer, MovePlayer, NpcState, PlayerAttack, PlayerJump, PlayerState, ReceivedSnapshot, ReceivedWorld, RotatePlayer, ShareWorld, SpawnPlayer}, resource::RemoteAuthority, OnlineState}, ui::{chat::{MessageSendEvent, PendingMessages}, lobby::JoinGameButton, MenuData, NORMAL_BUTTON}, AppState};
 
-use super::{LobbySocket, LobbyState};
+use super::{LobbyDisconnected, LobbyResynced, LobbySocket, LobbyState, WorldDownload};
 
 #[allow(private_interfaces)]
 pub fn get_lobby_events(
@@ -12,7 +12,6 @@ pub fn get_lobby_events(
     mut commands: Commands,
     menu_nodes: Res<MenuData>,
     app_state: Res<State<AppState>>,
-    online_state: Res<State<OnlineState>>,
     mut next_state: ResMut<NextState<AppState>>,
     mut share_world_event: EventWriter<ShareWorld>,
     mut received_world_event: EventWriter<ReceivedWorld>,
@@ -22,6 +21,8 @@ pub fn get_lobby_events(
     mut player_move_event: EventWriter<MovePlayer>,
     mut player_rotate_event: EventWriter<RotatePlayer>,
     mut player_jump_event: EventWriter<PlayerJump>,
+    (mut resync_event, mut disconnect_event, mut download_event): (EventWriter<LobbyResynced>, EventWriter<LobbyDisconnected>, EventWriter<WorldDownload>),
+    (online_state, mut next_online_state, mut snapshot_event): (Res<State<OnlineState>>, ResMut<NextState<OnlineState>>, EventWriter<ReceivedSnapshot>),
 ) {
     let in_lobby = match app_state.get() {
         AppState::Lobby(LobbyState::InLobby) => true,
@@ -49,8 +50,9 @@ pub fn get_lobby_events(
                         socket.lobby.clients.insert(client.client_id, client);
                     }
                     LobbyUpdate::Disconnection(client_id) => {
-                        pending_msgs.0.push(format!("[INFO] {} disconnected from the lobby", socket.lobby.clients.get(&client_id).unwrap().name));
-                        socket.lobby.clients.remove(&client_id);
+                        if let Some(client) = socket.lobby.clients.remove(&client_id) {
+                            pending_msgs.0.push(format!("[INFO] {} disconnected from the lobby", client.name));
+                        }
                         if let Some(entity) = socket.client_nodes.remove(&client_id) {
                             if in_lobby {
                                 commands.entity(entity).despawn();
@@ -61,8 +63,8 @@ pub fn get_lobby_events(
                         if let Some(client) = socket.lobby.clients.get_mut(&client_id) {
                             pending_msgs.0.push(format!("[INFO] connection to {} was interrupted", client.name));
                             client.status = ClientStatus::Idle(0);
-                            if in_lobby {
-                                commands.entity(*socket.client_nodes.get(&client_id).unwrap()).add(|mut entity: EntityWorldMut| {
+                            if let (true, Some(entity)) = (in_lobby, socket.client_nodes.get(&client_id)) {
+                                commands.entity(*entity).add(|mut entity: EntityWorldMut| {
                                     if let Some(mut text) = entity.get_mut::<Text>() {
                                         text.sections[0].style.color = Color::srgb(0.5, 0.5, 0.5);
                                     }
@@ -74,8 +76,8 @@ pub fn get_lobby_events(
                         if let Some(client) = socket.lobby.clients.get_mut(&client_id) {
                             pending_msgs.0.push(format!("[INFO] {} reconnected to the lobby", client.name));
                             client.status = ClientStatus::Active;
-                            if in_lobby {
-                                commands.entity(*socket.client_nodes.get(&client_id).unwrap()).add(|mut entity: EntityWorldMut| {
+                            if let (true, Some(entity)) = (in_lobby, socket.client_nodes.get(&client_id)) {
+                                commands.entity(*entity).add(|mut entity: EntityWorldMut| {
                                     if let Some(mut text) = entity.get_mut::<Text>() {
                                         text.sections[0].style.color = Color::srgb(0.9, 0.9, 0.9);
                                     }
@@ -85,11 +87,15 @@ pub fn get_lobby_events(
                     }
                     LobbyUpdate::Message {sender: client_id, content, ..} => {
                         if client_id != socket.socket.client_id {
-                            pending_msgs.0.push(format!("{}: {content}", socket.lobby.clients.get(&client_id).unwrap().name));
+                            let name = socket.lobby.clients.get(&client_id).map_or("<unknown>", |c| c.name.as_str());
+                            pending_msgs.0.push(format!("{name}: {content}"));
                         }
                     }
+                    LobbyUpdate::ServerMessage(content) => {
+                        pending_msgs.0.push(format!("[SERVER] {content}"));
+                    }
                     LobbyUpdate::Default => {
-                        println!("got a GameUpdate::Default ... this should not have happened!")
+                        warn!("got a LobbyUpdate::Default ... this should not have happened!")
                     }
                 }
             }
@@ -98,7 +104,7 @@ pub fn get_lobby_events(
                     GameUpdate::Creation(game) => {
                         pending_msgs.0.push(format!(
                             "[INFO] {} hosted game {} (#{})",
-                            socket.lobby.clients.get(&game.host_id).unwrap().name,
+                            socket.lobby.clients.get(&game.host_id).map_or("<unknown>", |c| c.name.as_str()),
                             game.game_name,
                             game.game_id
                         ));
@@ -156,7 +162,9 @@ pub fn get_lobby_events(
                         socket.lobby.games.insert(game.game_id, game);
                     }
                     GameUpdate::Deletion(game_id) => {
-                        let game = socket.lobby.games.remove(&game_id).unwrap();
+                        let Some(game) = socket.lobby.games.remove(&game_id) else {
+                            continue;
+                        };
                         pending_msgs.0.push(format!(
                             "[INFO] game {} (#{}) was deleted",
                             game.game_name,
@@ -180,17 +188,12 @@ pub fn get_lobby_events(
                                 game.game_name,
                                 game_id,
                             ));
-                            if Some(game.game_id) == socket.socket.game_id {
-                                if client_id != socket.socket.client_id {
-                                    player_spawn_event.send(SpawnPlayer {
-                                        name: socket.lobby.clients.get(&client_id).unwrap().name.clone(),
-                                        id: client_id,
-                                        position: Transform::from_xyz(0., 10., 0.).with_scale(Vec3::new(0.4, 0.4, 0.4))
-                                    });
-                                }
-                                if *online_state.get() == OnlineState::Host {
-                                    share_world_event.send(ShareWorld);
-                                }
+                            if Some(game.game_id) == socket.socket.game_id && client_id != socket.socket.client_id {
+                                player_spawn_event.send(SpawnPlayer {
+                                    name: socket.lobby.clients.get(&client_id).map_or(format!("#{client_id}"), |c| c.name.clone()),
+                                    id: client_id,
+                                    position: Transform::from_xyz(0., 10., 0.).with_scale(Vec3::new(0.4, 0.4, 0.4))
+                                });
                             }
                         }
                     }
@@ -208,19 +211,80 @@ pub fn get_lobby_events(
                             }
                         }
                     }
-                    GameUpdate::World(scene) => {
-                        println!("received TcpUpdate::GameWorld, sending ReceivedWorld event...");
-                        received_world_event.send(ReceivedWorld(scene));
-                        let _ = socket.socket.udp_send.send(UdpPackage::Heartbeat);
-                        let _ = socket.socket.udp_send.send(UdpPackage::Heartbeat);
-                        let _ = socket.socket.udp_send.send(UdpPackage::Heartbeat);
-                        let _ = socket.socket.udp_send.send(UdpPackage::Heartbeat);
+                    GameUpdate::HostMigration { game_id, host_id } => {
+                        let Some(game) = socket.lobby.games.get_mut(&game_id) else {
+                            continue;
+                        };
+                        game.host_id = host_id;
+                        pending_msgs.0.push(format!(
+                            "[INFO] client#{} took over game {} (#{})",
+                            host_id,
+                            game.game_name,
+                            game_id,
+                        ));
+                        if host_id == socket.socket.client_id && Some(game_id) == socket.socket.game_id {
+                            // Nobody else decides over the game anymore
+                            commands.remove_resource::<RemoteAuthority>();
+                            if *app_state.get() == AppState::Lobby(LobbyState::LoadGame) {
+                                // The world of the old host can't be downloaded anymore and there
+                                // is none to take over, so the game is left to the other players
+                                pending_msgs.0.push("[INFO] The host left before sharing the world, returning to the lobby".to_string());
+                                let _ = socket.socket.tcp_send.send(TcpFromClient::GameExit);
+                                socket.socket.game_id = None;
+                                next_state.set(AppState::Lobby(LobbyState::InLobby));
+                                continue;
+                            }
+                            pending_msgs.0.push("[INFO] The host left, you are hosting the game now".to_string());
+                            next_online_state.set(OnlineState::Host);
+                        }
                     }
                     GameUpdate::Default => {
-                        println!("got a GameUpdate::Default ... this should not have happened!")
+                        warn!("got a GameUpdate::Default ... this should not have happened!")
+                    }
+                }
+            }
+            Ok(TcpUpdate::LobbySnapshot(lobby)) => {
+                pending_msgs.0.push("[INFO] lost track of the lobby, resynchronized".to_string());
+                if in_lobby {
+                    for (_, entity) in socket.client_nodes.drain() {
+                        commands.entity(entity).despawn_recursive();
                     }
+                    for (_, entity) in socket.game_nodes.drain() {
+                        commands.entity(entity).despawn_recursive();
+                    }
+                    resync_event.send(LobbyResynced);
+                }
+                socket.lobby = lobby;
+            }
+            Ok(TcpUpdate::WorldProgress { received, size }) => {
+                download_event.send(WorldDownload::Progress { received, size });
+            }
+            Ok(TcpUpdate::World(scene)) => {
+                debug!("received TcpUpdate::World, sending ReceivedWorld event...");
+                received_world_event.send(ReceivedWorld(scene));
+                socket.socket.send_udp(UdpPackage::Heartbeat);
+                socket.socket.send_udp(UdpPackage::Heartbeat);
+                socket.socket.send_udp(UdpPackage::Heartbeat);
+                socket.socket.send_udp(UdpPackage::Heartbeat);
+            }
+            Ok(TcpUpdate::WorldUnavailable) => {
+                pending_msgs.0.push("[ERR] the world of the game is no longer available".to_string());
+                download_event.send(WorldDownload::Unavailable);
+            }
+            Ok(TcpUpdate::WorldTimeout) => {
+                pending_msgs.0.push("[ERR] the host didn't send the world of the game in time".to_string());
+                download_event.send(WorldDownload::TimedOut);
+            }
+            Ok(TcpUpdate::WorldRequest { request, client_id }) => {
+                if *online_state.get() == OnlineState::Host {
+                    debug!("client#{client_id} joined, sharing the world for request {request}");
+                    share_world_event.send(ShareWorld(request));
                 }
             }
+            Ok(TcpUpdate::Disconnected(reason)) => {
+                disconnect_event.send(LobbyDisconnected(reason));
+                return;
+            }
             Err(e) => {
                 pending_msgs.0.push(format!("[ERR] there was an unexpected error: {e}"));
             }
@@ -235,15 +299,44 @@ pub fn get_lobby_events(
                         position: Transform::from(ypos)
                     });
                 }
-                UdpPackage::Move(pos) => {
-                    player_move_event.send(MovePlayer { id: pkg.0, position: Vec3::from(pos) });
+                UdpPackage::Move(ymove) => {
+                    player_move_event.send(MovePlayer {
+                        id: pkg.0,
+                        position: Vec3::from(ymove.position),
+                        time: ymove.time as f32 / 1000.,
+                    });
                 }
-                UdpPackage::Rotate(rotation) => {
-                    player_rotate_event.send(RotatePlayer { id: pkg.0, rotation: Quat::from(rotation) });
+                UdpPackage::Rotate(rotate) => {
+                    player_rotate_event.send(RotatePlayer {
+                        id: pkg.0,
+                        rotation: Quat::from(rotate.rotation),
+                        time: rotate.time as f32 / 1000.,
+                    });
                 }
                 UdpPackage::Jump => {
                     player_jump_event.send(PlayerJump(pkg.0));
                 }
+                UdpPackage::Snapshot(snapshot) => {
+                    // Only the host of the game decides over it
+                    let from_host = socket.socket.game_id
+                        .and_then(|game_id| socket.lobby.games.get(&game_id))
+                        .is_some_and(|game| game.host_id == pkg.0);
+                    if from_host {
+                        snapshot_event.send(ReceivedSnapshot {
+                            time: snapshot.time as f32 / 1000.,
+                            players: snapshot.players.into_iter().map(|state| PlayerState {
+                                id: state.id,
+                                position: Vec3::from(state.translation),
+                                health: state.health,
+                                last_move: state.last_move,
+                            }).collect(),
+                            npcs: snapshot.npcs.into_iter().map(|state| NpcState {
+                                id: state.id,
+                                health: state.health,
+                            }).collect(),
+                        });
+                    }
+                }
                 _ => {
                     pending_msgs.0.push(format!("[ERR] there was an unexpected udp package"));
                 }
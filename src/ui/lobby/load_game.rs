@@ -3,7 +3,7 @@ use ysync::TcpFromClient;
 
 use crate::{game::online::OnlineState, ui::{components::ReturnButton, despawn_camera, despawn_menu, spawn_camera, MenuData, HOVERED_BUTTON, NORMAL_BUTTON, PRESSED_BUTTON}, AppState, LobbyState};
 
-use super::LobbySocket;
+use super::{LobbySocket, WorldDownload};
 
 pub struct GameLoadPlugin;
 
@@ -20,10 +20,15 @@ impl Plugin for GameLoadPlugin {
             ))
             .add_systems(Update, (
                 cancel_interaction,
+                show_download_progress,
             ).run_if(in_state(AppState::Lobby(LobbyState::LoadGame))));
     }
 }
 
+// Text showing how much of the world arrived
+#[derive(Component)]
+struct DownloadProgress;
+
 fn build_load_screen(mut commands: Commands) {
     let entity = commands
         .spawn(NodeBundle {
@@ -39,6 +44,20 @@ fn build_load_screen(mut commands: Commands) {
             ..default()
         })
         .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "Waiting for the world...",
+                    TextStyle {
+                        font_size: 33.0,
+                        color: Color::srgb(0.9, 0.9, 0.9),
+                        ..default()
+                    },
+                ).with_style(Style {
+                    margin: UiRect::bottom(Val::Px(20.)),
+                    ..default()
+                }),
+                DownloadProgress,
+            ));
             // Cancel button
             parent
                 .spawn((
@@ -94,3 +113,23 @@ fn cancel_interaction(
         }
     }
 }
+
+fn show_download_progress(
+    mut download_events: EventReader<WorldDownload>,
+    mut text_query: Query<&mut Text, With<DownloadProgress>>,
+) {
+    let Some(event) = download_events.read().last() else {
+        return;
+    };
+    let Ok(mut text) = text_query.get_single_mut() else {
+        return;
+    };
+    text.sections[0].value = match event {
+        WorldDownload::Progress { received, size } => {
+            let percent = (*received as u64 * 100).checked_div(*size as u64).unwrap_or(100);
+            format!("Loading world... {percent}%")
+        }
+        WorldDownload::Unavailable => "The world is no longer available".to_string(),
+        WorldDownload::TimedOut => "The host didn't send the world in time".to_string(),
+    };
+}
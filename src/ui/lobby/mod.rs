@@ -3,7 +3,7 @@ use con_menu::LobbyConMenuPlugin;
 use in_lobby::InLobbyPlugin;
 use load_game::GameLoadPlugin;
 use tokio::sync::oneshot::Receiver;
-use ysync::{client::{ConnectionSocket, LobbyConnectionError}, Lobby};
+use ysync::{client::{ConnectionSocket, LobbyConnectionError}, DisconnectReason, Lobby};
 
 mod con_menu;
 pub mod in_lobby;
@@ -36,6 +36,28 @@ pub struct LobbySocket {
     pub lobby: Lobby,
     pub socket: ConnectionSocket,
 }
+
+impl LobbySocket {
+    pub fn new(socket: ConnectionSocket, lobby: Lobby) -> LobbySocket {
+        LobbySocket { client_nodes: HashMap::new(), game_nodes: HashMap::new(), lobby, socket }
+    }
+}
+// The lobby got replaced by a snapshot, so the lobby details have to be rebuild
+#[derive(Event)]
+struct LobbyResynced;
+// The server closed the connection
+#[derive(Event)]
+struct LobbyDisconnected(DisconnectReason);
+// How far the world of the joined game got downloaded
+#[derive(Event)]
+enum WorldDownload {
+    Progress {
+        received: u32,
+        size: u32
+    },
+    Unavailable,
+    TimedOut,
+}
 #[derive(Component)]
 struct HostGameButton;
 #[derive(Component)]